@@ -0,0 +1,28 @@
+#include "include/fullscreen.wgsl"
+
+struct Blur {
+    direction: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> blur: Blur;
+
+// Separable 9 tap gaussian blur
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let texel = blur.direction / vec2<f32>(textureDimensions(t_source));
+
+    var result = textureSample(t_source, s_source, in.tex_coords).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = texel * f32(i);
+        result += textureSample(t_source, s_source, in.tex_coords + offset).rgb * weights[i];
+        result += textureSample(t_source, s_source, in.tex_coords - offset).rgb * weights[i];
+    }
+
+    return vec4<f32>(result, 1.0);
+}
@@ -0,0 +1,20 @@
+#include "include/fullscreen.wgsl"
+#include "include/post_process.wgsl"
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> post_process: PostProcess;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.tex_coords).rgb;
+
+    // Keep only the part of the color above the threshold
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - post_process.bloom_threshold, 0.0) / max(brightness, 0.0001);
+
+    return vec4<f32>(color * contribution, 1.0);
+}
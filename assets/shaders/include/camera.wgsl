@@ -0,0 +1,6 @@
+/// Camera uniform, bound by each shader at its own group.
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
@@ -0,0 +1,10 @@
+/// Distance fog towards the clear color.
+
+const FOG_COLOR: vec4<f32> = vec4<f32>(0.23922, 0.19216, 0.29020, 1.0);
+const FOG_START: f32 = 100.0;
+const FOG_END: f32 = 150.0;
+
+/// Fog amount from 0 (no fog) to 1 (full fog).
+fn fog_factor(view_distance: f32) -> f32 {
+    return clamp((view_distance - FOG_START) / (FOG_END - FOG_START), 0.0, 1.0);
+}
@@ -0,0 +1,6 @@
+/// Light uniform, bound by each shader at its own group.
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>
+};
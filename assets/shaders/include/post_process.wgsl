@@ -0,0 +1,8 @@
+/// Post process uniform, matches `PostProcessUniform`.
+
+struct PostProcess {
+    exposure: f32,
+    tonemapper: u32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+}
@@ -0,0 +1,57 @@
+/// Inverted hull outline, drawn around instances with an outline.
+/// Back faces are pushed out along the normals, so only the silhouette shows past the model.
+
+#include "include/camera.wgsl"
+#include "include/fog.wgsl"
+#include "include/model_vertex.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+/// Linear HDR color, bright enough to bloom.
+const OUTLINE_COLOR: vec3<f32> = vec3<f32>(4.0, 1.2, 0.2);
+/// Outline width per unit of view distance, so it keeps its screen size.
+const OUTLINE_WIDTH: f32 = 0.004;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) view_distance: f32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    let world_normal = normalize(normal_matrix * model.normal);
+    let world_position = (model_matrix * vec4<f32>(model.position, 1.0)).xyz;
+    let view_distance = distance(world_position, camera.view_pos.xyz);
+    let hull_position = world_position
+        + world_normal * instance.outline * OUTLINE_WIDTH * view_distance;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(hull_position, 1.0);
+    out.view_distance = view_distance;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let fog = fog_factor(in.view_distance);
+
+    return vec4<f32>(mix(OUTLINE_COLOR, FOG_COLOR.rgb, fog), 1.0);
+}
@@ -0,0 +1,65 @@
+/// Camera facing particle quads, with a soft round falloff.
+
+#include "include/camera.wgsl"
+#include "include/fog.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct ParticleUniform {
+    camera_right: vec4<f32>,
+    camera_up: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> particle: ParticleUniform;
+
+struct InstanceInput {
+    @location(0) position_size: vec4<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) view_distance: f32,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Two triangles, corners from -1 to 1
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let half_size = instance.position_size.w * 0.5;
+    let world_position = instance.position_size.xyz
+        + (particle.camera_right.xyz * corner.x + particle.camera_up.xyz * corner.y) * half_size;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.corner = corner;
+    out.color = instance.color;
+    out.view_distance = distance(world_position, camera.view_pos.xyz);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - smoothstep(0.0, 1.0, length(in.corner));
+    // Particles fade out in the fog
+    let alpha = in.color.a * falloff * (1.0 - fog_factor(in.view_distance));
+
+    return vec4<f32>(in.color.rgb, alpha);
+}
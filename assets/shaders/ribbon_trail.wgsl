@@ -0,0 +1,41 @@
+/// Camera facing ribbon strips, with soft edges.
+
+#include "include/camera.wgsl"
+#include "include/fog.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position_side: vec4<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) side: f32,
+    @location(1) color: vec4<f32>,
+    @location(2) view_distance: f32,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world_position = in.position_side.xyz;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.side = in.position_side.w;
+    out.color = in.color;
+    out.view_distance = distance(world_position, camera.view_pos.xyz);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - smoothstep(0.3, 1.0, abs(in.side));
+    // Trails fade out in the fog
+    let alpha = in.color.a * falloff * (1.0 - fog_factor(in.view_distance));
+
+    return vec4<f32>(in.color.rgb, alpha);
+}
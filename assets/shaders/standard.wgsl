@@ -1,30 +1,11 @@
-struct CameraUniform {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-};
+#include "include/camera.wgsl"
+#include "include/light.wgsl"
+#include "include/fog.wgsl"
+#include "include/model_vertex.wgsl"
+
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
-const FOG_COLOR: vec4<f32> = vec4<f32>(0.23922, 0.19216, 0.29020, 1.0);
-
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-    @location(9) normal_matrix_0: vec3<f32>,
-    @location(10) normal_matrix_1: vec3<f32>,
-    @location(11) normal_matrix_2: vec3<f32>,
-};
-
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-    @location(3) tangent: vec3<f32>,
-    @location(4) bitangent: vec3<f32>,
-};
-
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,  
     @location(0) tex_coords: vec2<f32>,
@@ -32,6 +13,8 @@ struct VertexOutput {
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
     @location(4) view_distance: f32,
+    @location(5) tint: vec4<f32>,
+    @location(6) emissive: vec3<f32>,
 };
 
 @vertex
@@ -53,9 +36,15 @@ fn vs_main(
     );
 
     // Construct the tangent matrix
+    // Tangents follow the surface, so they use the model matrix instead of the normal matrix
+    let model_matrix_3x3 = mat3x3<f32>(
+        model_matrix[0].xyz,
+        model_matrix[1].xyz,
+        model_matrix[2].xyz,
+    );
     let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
+    let world_tangent = normalize(model_matrix_3x3 * model.tangent);
+    let world_bitangent = normalize(model_matrix_3x3 * model.bitangent);
     let tangent_matrix = transpose(mat3x3<f32>(
         world_tangent,
         world_bitangent,
@@ -63,7 +52,7 @@ fn vs_main(
     ));
 
     let world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
-    let view_distance: f32 = distance(model.position, camera.view_pos.xyz);
+    let view_distance: f32 = distance(world_position.xyz, camera.view_pos.xyz);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
@@ -72,6 +61,8 @@ fn vs_main(
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
     out.view_distance = view_distance;
+    out.tint = instance.tint;
+    out.emissive = instance.emissive;
 
     return out;
 }
@@ -86,16 +77,19 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>
+struct MaterialUniform {
+    opacity: f32,
 };
+
+@group(0) @binding(4)
+var<uniform> material: MaterialUniform;
+
 @group(2) @binding(0)
 var<uniform> light: Light;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
 
     // Ambient lighting
@@ -117,12 +111,11 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let specular_color = specular_strength * light.color;
 
     // Distance fog
-    let fog_start: f32 = 100.0;
-    let fog_end: f32 = 150.0;
-    var fog_factor: f32 = (in.view_distance - fog_start) / (fog_end - fog_start);
-    fog_factor = clamp(fog_factor, 0.0, 1.0);
+    let fog = fog_factor(in.view_distance);
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.rgb + fog_factor * FOG_COLOR.rgb;
+    let result = (ambient_color + diffuse_color + specular_color) * object_color.rgb
+        + in.emissive * (1.0 - fog)
+        + fog * FOG_COLOR.rgb;
 
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(result, object_color.a * material.opacity);
 }
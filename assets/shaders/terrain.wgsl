@@ -0,0 +1,133 @@
+/// Splat-mapped terrain shader.
+/// Layers: 0 grass, 1 dirt, 2 sand, 3 cliff.
+
+#include "include/camera.wgsl"
+#include "include/light.wgsl"
+#include "include/fog.wgsl"
+#include "include/model_vertex.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) view_distance: f32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    let world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normalize(normal_matrix * model.normal);
+    out.view_distance = distance(world_position.xyz, camera.view_pos.xyz);
+
+    return out;
+}
+
+
+struct TerrainMaterial {
+    layer_tint: array<vec4<f32>, 4>,
+    layer_tiling: vec4<f32>,
+    sand_max_height: f32,
+    sand_blend_height: f32,
+    cliff_min_slope: f32,
+    cliff_max_slope: f32,
+};
+
+@group(0) @binding(0)
+var t_splat: texture_2d<f32>;
+@group(0) @binding(1)
+var s_splat: sampler;
+@group(0) @binding(2)
+var s_layer: sampler;
+@group(0) @binding(3)
+var t_layer_0: texture_2d<f32>;
+@group(0) @binding(4)
+var t_layer_1: texture_2d<f32>;
+@group(0) @binding(5)
+var t_layer_2: texture_2d<f32>;
+@group(0) @binding(6)
+var t_layer_3: texture_2d<f32>;
+@group(0) @binding(7)
+var<uniform> material: TerrainMaterial;
+
+/// Combines splat map weights with the automatic height and slope rules.
+fn layer_weights(splat: vec4<f32>, height: f32, normal: vec3<f32>) -> vec4<f32> {
+    var weights = splat;
+
+    // Sand on low ground
+    let sand = 1.0 - smoothstep(
+        material.sand_max_height,
+        material.sand_max_height + material.sand_blend_height,
+        height
+    );
+    weights = mix(weights, vec4<f32>(0.0, 0.0, 1.0, 0.0), sand);
+
+    // Cliff on steep slopes
+    let slope = 1.0 - clamp(normal.y, 0.0, 1.0);
+    let cliff = smoothstep(material.cliff_min_slope, material.cliff_max_slope, slope);
+    weights = mix(weights, vec4<f32>(0.0, 0.0, 0.0, 1.0), cliff);
+
+    let total = max(weights.x + weights.y + weights.z + weights.w, 0.0001);
+    return weights / total;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.world_normal);
+    let splat: vec4<f32> = textureSample(t_splat, s_splat, in.tex_coords);
+    let weights = layer_weights(splat, in.world_position.y, normal);
+
+    // Per-layer tiling avoids stretching on large terrains
+    let layer_0 = textureSample(t_layer_0, s_layer, in.tex_coords * material.layer_tiling.x);
+    let layer_1 = textureSample(t_layer_1, s_layer, in.tex_coords * material.layer_tiling.y);
+    let layer_2 = textureSample(t_layer_2, s_layer, in.tex_coords * material.layer_tiling.z);
+    let layer_3 = textureSample(t_layer_3, s_layer, in.tex_coords * material.layer_tiling.w);
+
+    let object_color: vec3<f32> = layer_0.rgb * material.layer_tint[0].rgb * weights.x
+        + layer_1.rgb * material.layer_tint[1].rgb * weights.y
+        + layer_2.rgb * material.layer_tint[2].rgb * weights.z
+        + layer_3.rgb * material.layer_tint[3].rgb * weights.w;
+
+    // Ambient lighting
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength;
+
+    // Diffuse lighting
+    let light_dir = normalize(light.position - in.world_position);
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    // Distance fog
+    let fog = fog_factor(in.view_distance);
+
+    let result = (ambient_color + diffuse_color) * object_color + fog * FOG_COLOR.rgb;
+
+    return vec4<f32>(result, 1.0);
+}
@@ -0,0 +1,57 @@
+#include "include/fullscreen.wgsl"
+#include "include/post_process.wgsl"
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(3)
+var<uniform> post_process: PostProcess;
+
+const TONEMAPPER_ACES: u32 = 0u;
+
+// Narkowicz ACES filmic curve fit
+fn tonemap_aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Hable (Uncharted 2) filmic curve
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn tonemap_filmic(x: vec3<f32>) -> vec3<f32> {
+    let white_point = 11.2;
+    return hable(x * 2.0) / hable(vec3<f32>(white_point));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.tex_coords).rgb;
+    let bloom = textureSample(t_bloom, s_hdr, in.tex_coords).rgb;
+
+    let color = (hdr + bloom * post_process.bloom_intensity) * post_process.exposure;
+
+    // The sRGB surface applies the gamma conversion
+    var result: vec3<f32>;
+    if post_process.tonemapper == TONEMAPPER_ACES {
+        result = tonemap_aces(color);
+    } else {
+        result = tonemap_filmic(color);
+    }
+
+    return vec4<f32>(result, 1.0);
+}
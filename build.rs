@@ -1,16 +1,47 @@
 use anyhow::Result;
-use fs_extra::copy_items;
-use fs_extra::dir::CopyOptions;
 use std::env;
+use std::path::{Path, PathBuf};
 
+#[allow(dead_code)]
+#[path = "src/assets/asset_archive.rs"]
+mod asset_archive;
+
+const ASSETS_ROOT_PATH: &str = "assets";
+const ARCHIVE_FILE_NAME: &str = "assets.pak";
+/// Authoring files that the game never loads.
+const SKIPPED_EXTENSIONS: [&str; 2] = ["blend", "blend1"];
+
+/// Packs the assets directory into `OUT_DIR/assets.pak`, for the `embed_assets` feature
+/// and for the `package_assets` example, which copies it next to a binary.
+/// Set `ASSET_ARCHIVE_COMPRESSION=none` to store every file uncompressed.
 fn main() -> Result<()> {
-    println!("cargo:rerun-if-changed=assets/*");
+    println!("cargo:rerun-if-changed={}", ASSETS_ROOT_PATH);
+    println!("cargo:rerun-if-env-changed=ASSET_ARCHIVE_COMPRESSION");
+
+    let compress = env::var("ASSET_ARCHIVE_COMPRESSION").map_or(true, |value| value != "none");
+
+    let mut files = Vec::new();
+    for path in glob::glob(&format!("{}/**/*", ASSETS_ROOT_PATH))? {
+        let path = path?;
+        let is_skipped = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| SKIPPED_EXTENSIONS.contains(&extension));
+        if path.is_file() && !is_skipped {
+            files.push(path);
+        }
+    }
+    // Same archive for the same assets
+    files.sort();
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let archive_path = out_dir.join(ARCHIVE_FILE_NAME);
+    asset_archive::write(Path::new(ASSETS_ROOT_PATH), &files, compress, &archive_path)?;
 
-    let out_dir = env::var("OUT_DIR")?;
-    let mut copy_options = CopyOptions::new();
-    copy_options.overwrite = true;
-    let paths_to_copy = vec!["assets/"];
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    println!(
+        "cargo:rustc-env=ASSET_ARCHIVE_PATH={}",
+        archive_path.display()
+    );
 
     Ok(())
 }
@@ -0,0 +1,22 @@
+//! Copies the asset archive packed by `build.rs` into a directory, usually next to the game binary.
+//!
+//! `cargo run --release --example package_assets -- target/release`
+
+use std::path::PathBuf;
+
+const ARCHIVE_FILE_NAME: &str = "assets.pak";
+
+fn main() -> anyhow::Result<()> {
+    let Some(out_dir) = std::env::args().nth(1).map(PathBuf::from) else {
+        anyhow::bail!("Usage: package_assets <directory of the game binary>");
+    };
+    if !out_dir.is_dir() {
+        anyhow::bail!("{:?} is not a directory", out_dir);
+    }
+
+    let out_path = out_dir.join(ARCHIVE_FILE_NAME);
+    std::fs::copy(env!("ASSET_ARCHIVE_PATH"), &out_path)?;
+    println!("Wrote {:?}", out_path);
+
+    Ok(())
+}
@@ -0,0 +1,272 @@
+//! Packed asset archive, written by `build.rs` and read by `resources`.
+//!
+//! Layout, little-endian:
+//! - magic `b"LDPK"`, format version `u32`, entry count `u32`
+//! - per entry: path length `u32`, path (UTF-8, `/` separated, relative to the assets root),
+//!   data offset `u64`, stored size `u64`, size `u64`, compression `u8`
+//! - entry data, offsets are relative to the end of the index
+//!
+//! Only depends on std, anyhow and miniz_oxide, as the build script includes this file too.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: [u8; 4] = *b"LDPK";
+const VERSION: u32 = 1;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_DEFLATE: u8 = 1;
+const DEFLATE_LEVEL: u8 = 6;
+/// Formats that are already compressed, deflating them is wasted build time.
+const STORED_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "ogg"];
+
+/// Where the archive data is read from.
+enum ArchiveData {
+    /// Included in the binary, with the `embed_assets` feature.
+    #[cfg_attr(not(feature = "embed_assets"), allow(dead_code))]
+    Embedded(&'static [u8]),
+    /// Entries are read from the file on demand.
+    File(PathBuf),
+}
+
+pub struct AssetArchive {
+    data: ArchiveData,
+    /// Start of the entry data.
+    data_offset: u64,
+
+    entry_i_by_path: HashMap<String, usize>,
+    entry_offset: Vec<u64>,
+    entry_stored_size: Vec<u64>,
+    entry_size: Vec<u64>,
+    entry_compression: Vec<u8>,
+}
+
+impl AssetArchive {
+    /// Reads the index of an archive file.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read_index(&mut reader, ArchiveData::File(path.to_path_buf()))
+    }
+
+    /// Reads the index of an archive included in the binary.
+    #[cfg_attr(not(feature = "embed_assets"), allow(dead_code))]
+    pub fn from_embedded(bytes: &'static [u8]) -> anyhow::Result<Self> {
+        let mut reader = bytes;
+        Self::read_index(&mut reader, ArchiveData::Embedded(bytes))
+    }
+
+    fn read_index(reader: &mut impl Read, data: ArchiveData) -> anyhow::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            anyhow::bail!("Not an asset archive");
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            anyhow::bail!(
+                "Unsupported asset archive version {}, expected {}",
+                version,
+                VERSION
+            );
+        }
+
+        let entry_count = read_u32(reader)? as usize;
+        let mut archive = Self {
+            data,
+            data_offset: 12,
+            entry_i_by_path: HashMap::with_capacity(entry_count),
+            entry_offset: Vec::with_capacity(entry_count),
+            entry_stored_size: Vec::with_capacity(entry_count),
+            entry_size: Vec::with_capacity(entry_count),
+            entry_compression: Vec::with_capacity(entry_count),
+        };
+
+        for entry_i in 0..entry_count {
+            let path_len = read_u32(reader)?;
+            let mut path = vec![0; path_len as usize];
+            reader.read_exact(&mut path)?;
+
+            archive
+                .entry_i_by_path
+                .insert(String::from_utf8(path)?, entry_i);
+            archive.entry_offset.push(read_u64(reader)?);
+            archive.entry_stored_size.push(read_u64(reader)?);
+            archive.entry_size.push(read_u64(reader)?);
+            let mut compression = [0];
+            reader.read_exact(&mut compression)?;
+            archive.entry_compression.push(compression[0]);
+
+            archive.data_offset += 4 + path_len as u64 + 8 * 3 + 1;
+        }
+
+        Ok(archive)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entry_offset.len()
+    }
+
+    /// Reads and decompresses a file. Paths are relative to the assets root.
+    pub fn read(&self, file_name: &Path) -> anyhow::Result<Vec<u8>> {
+        let entry_i = *self
+            .entry_i_by_path
+            .get(&entry_path(file_name))
+            .ok_or_else(|| anyhow::anyhow!("{:?} is not in the asset archive", file_name))?;
+
+        let offset = self.data_offset + self.entry_offset[entry_i];
+        let stored_size = self.entry_stored_size[entry_i];
+        let stored = match &self.data {
+            ArchiveData::Embedded(bytes) => {
+                let start = offset as usize;
+                let end = start + stored_size as usize;
+                bytes
+                    .get(start..end)
+                    .ok_or_else(|| anyhow::anyhow!("Asset archive is truncated"))?
+                    .to_vec()
+            }
+            ArchiveData::File(path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut stored = vec![0; stored_size as usize];
+                file.read_exact(&mut stored)?;
+                stored
+            }
+        };
+
+        match self.entry_compression[entry_i] {
+            COMPRESSION_NONE => Ok(stored),
+            COMPRESSION_DEFLATE => miniz_oxide::inflate::decompress_to_vec_with_limit(
+                &stored,
+                self.entry_size[entry_i] as usize,
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to decompress {:?}: {:?}", file_name, e.status)),
+            compression => anyhow::bail!(
+                "Unknown compression {} for {:?} in the asset archive",
+                compression,
+                file_name
+            ),
+        }
+    }
+}
+
+/// Writes an archive of `files`, as paths relative to `root`.
+/// With `compress`, files are deflated when it makes them noticeably smaller.
+// Only used by the build script.
+#[allow(dead_code)]
+pub fn write(root: &Path, files: &[PathBuf], compress: bool, out: &Path) -> anyhow::Result<()> {
+    let mut index = Vec::new();
+    let mut data = Vec::new();
+
+    index.extend_from_slice(&MAGIC);
+    index.extend_from_slice(&VERSION.to_le_bytes());
+    index.extend_from_slice(&(files.len() as u32).to_le_bytes());
+
+    for file in files.iter() {
+        let path = entry_path(file.strip_prefix(root)?);
+        let bytes = std::fs::read(file)?;
+        let size = bytes.len();
+
+        let is_stored = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| STORED_EXTENSIONS.contains(&extension));
+        let deflated = if compress && !is_stored {
+            Some(miniz_oxide::deflate::compress_to_vec(&bytes, DEFLATE_LEVEL))
+        } else {
+            None
+        };
+        let (stored, compression) = match deflated {
+            // Small gains aren't worth decompressing for
+            Some(deflated) if deflated.len() < size * 9 / 10 => (deflated, COMPRESSION_DEFLATE),
+            _ => (bytes, COMPRESSION_NONE),
+        };
+
+        index.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        index.extend_from_slice(path.as_bytes());
+        index.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        index.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+        index.extend_from_slice(&(size as u64).to_le_bytes());
+        index.push(compression);
+
+        data.extend_from_slice(&stored);
+    }
+
+    let mut out = File::create(out)?;
+    out.write_all(&index)?;
+    out.write_all(&data)?;
+
+    Ok(())
+}
+
+/// Archive paths use `/` on every platform.
+fn entry_path(file_name: &Path) -> String {
+    file_name
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn read_u32(reader: &mut impl Read) -> anyhow::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> anyhow::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_files(root: &Path) -> Vec<PathBuf> {
+        std::fs::create_dir_all(root.join("models")).unwrap();
+        let files = vec![root.join("models/cube.obj"), root.join("noise.png")];
+        std::fs::write(&files[0], "v 1.0 1.0 1.0\n".repeat(100)).unwrap();
+        std::fs::write(&files[1], (0..=255).collect::<Vec<u8>>()).unwrap();
+        files
+    }
+
+    #[test]
+    fn written_archive_reads_back() {
+        let root = std::env::temp_dir().join(format!("asset_archive_{}", std::process::id()));
+        let files = write_files(&root);
+
+        for compress in [true, false] {
+            let archive_path = root.join("assets.pak");
+            write(&root, &files, compress, &archive_path).unwrap();
+            let archive = AssetArchive::open(&archive_path).unwrap();
+            assert_eq!(archive.len(), files.len());
+
+            // Text is deflated when compressing, images are always stored
+            let expected_compression = [
+                if compress {
+                    COMPRESSION_DEFLATE
+                } else {
+                    COMPRESSION_NONE
+                },
+                COMPRESSION_NONE,
+            ];
+            for (file, expected_compression) in files.iter().zip(expected_compression) {
+                let file_name = file.strip_prefix(&root).unwrap();
+                let entry_i = archive.entry_i_by_path[&entry_path(file_name)];
+                assert_eq!(archive.entry_compression[entry_i], expected_compression);
+                assert_eq!(
+                    archive.read(file_name).unwrap(),
+                    std::fs::read(file).unwrap()
+                );
+            }
+            assert!(archive.read(Path::new("missing.obj")).is_err());
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
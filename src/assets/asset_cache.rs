@@ -0,0 +1,206 @@
+use std::{collections::HashMap, fmt, hash, marker::PhantomData};
+
+/// Typed index of an asset in an `AssetCache`.
+pub struct Handle<T> {
+    index: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+// Implemented by hand, derives would require `T` to implement the traits too.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> hash::Hash for Handle<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+/// Assets of a single type, deduplicated by key (usually the file path) and reference counted.
+/// Slots of unloaded assets are not reused, so stale handles never point to another asset.
+pub struct AssetCache<T> {
+    key: Vec<String>,
+    asset: Vec<Option<T>>,
+    ref_count: Vec<u32>,
+
+    index_by_key: HashMap<String, usize>,
+}
+
+impl<T> AssetCache<T> {
+    pub fn new() -> Self {
+        Self {
+            key: Vec::new(),
+            asset: Vec::new(),
+            ref_count: Vec::new(),
+
+            index_by_key: HashMap::new(),
+        }
+    }
+
+    /// Adds an asset with a single reference.
+    /// Replaces the key of an existing asset, so callers should check `acquire_with_key` first.
+    pub fn add(&mut self, key: &str, asset: T) -> Handle<T> {
+        self.key.push(String::from(key));
+        self.asset.push(Some(asset));
+        self.ref_count.push(1);
+
+        let index = self.key.len() - 1;
+        self.index_by_key.insert(String::from(key), index);
+
+        Handle::new(index)
+    }
+
+    /// Returns the handle of a loaded asset without adding a reference.
+    pub fn get_with_key(&self, key: &str) -> Option<Handle<T>> {
+        self.index_by_key.get(key).map(|index| Handle::new(*index))
+    }
+
+    /// Returns the handle of a loaded asset and adds a reference to it.
+    pub fn acquire_with_key(&mut self, key: &str) -> Option<Handle<T>> {
+        let handle = self.get_with_key(key)?;
+        self.acquire(handle);
+
+        Some(handle)
+    }
+
+    pub fn acquire(&mut self, handle: Handle<T>) {
+        self.ref_count[handle.index] += 1;
+    }
+
+    /// Removes a reference. The asset stays loaded until `unload_unused`.
+    pub fn release(&mut self, handle: Handle<T>) {
+        let ref_count = &mut self.ref_count[handle.index];
+        *ref_count = ref_count.saturating_sub(1);
+    }
+
+    /// Panics if the asset was unloaded.
+    pub fn get(&self, handle: Handle<T>) -> &T {
+        self.asset[handle.index]
+            .as_ref()
+            .unwrap_or_else(|| panic!("Asset {:?} is unloaded", self.key[handle.index]))
+    }
+
+    /// Swaps the asset in place, e.g. on hot reload. Returns the previous asset.
+    pub fn replace(&mut self, handle: Handle<T>, asset: T) -> T {
+        self.asset[handle.index]
+            .replace(asset)
+            .unwrap_or_else(|| panic!("Asset {:?} is unloaded", self.key[handle.index]))
+    }
+
+    pub fn key(&self, handle: Handle<T>) -> &str {
+        &self.key[handle.index]
+    }
+
+    #[cfg(test)]
+    pub fn ref_count(&self, handle: Handle<T>) -> u32 {
+        self.ref_count[handle.index]
+    }
+
+    /// Handles of all loaded assets.
+    pub fn handles(&self) -> impl Iterator<Item = Handle<T>> + '_ {
+        self.asset
+            .iter()
+            .enumerate()
+            .filter(|(_, asset)| asset.is_some())
+            .map(|(index, _)| Handle::new(index))
+    }
+
+    /// Amount of loaded assets.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.asset.iter().filter(|asset| asset.is_some()).count()
+    }
+
+    /// Drops assets without references.
+    /// Returns them, so assets referencing other assets can release those too.
+    pub fn unload_unused(&mut self) -> Vec<T> {
+        let mut unloaded = Vec::new();
+
+        for index in 0..self.asset.len() {
+            if self.ref_count[index] > 0 {
+                continue;
+            }
+            let Some(asset) = self.asset[index].take() else {
+                continue;
+            };
+
+            if self.index_by_key.get(&self.key[index]) == Some(&index) {
+                self.index_by_key.remove(&self.key[index]);
+            }
+            unloaded.push(asset);
+        }
+
+        unloaded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssetCache;
+
+    #[test]
+    fn unload_unused_keeps_referenced_assets() {
+        let mut cache = AssetCache::new();
+        let a = cache.add("a", 1);
+        let b = cache.add("b", 2);
+
+        let acquired = cache.acquire_with_key("a").unwrap();
+        assert_eq!(acquired, a);
+        assert_eq!(cache.ref_count(a), 2);
+        // Looking up doesn't add a reference
+        assert_eq!(cache.get_with_key("b"), Some(b));
+        assert_eq!(cache.ref_count(b), 1);
+
+        cache.release(a);
+        cache.release(b);
+        // Releasing past zero references is ignored
+        cache.release(b);
+        assert_eq!(cache.ref_count(a), 1);
+        assert_eq!(cache.ref_count(b), 0);
+
+        let unloaded = cache.unload_unused();
+        assert_eq!(unloaded, vec![2]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(*cache.get(a), 1);
+        assert_eq!(cache.get_with_key("b"), None);
+        assert_eq!(cache.handles().collect::<Vec<_>>(), vec![a]);
+
+        // Reloading gets a new slot, the stale handle stays unloaded
+        let b_reloaded = cache.add("b", 3);
+        assert_ne!(b_reloaded, b);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.unload_unused().is_empty());
+    }
+}
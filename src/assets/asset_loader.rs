@@ -0,0 +1,442 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use kira::sound::static_sound::StaticSoundData;
+
+use crate::{
+    game::{
+        mesh_renderer::MeshInstancedRendererMgr,
+        model::ModelMgr,
+        terrain::heightfield::{Heightfield, HeightfieldSettings},
+    },
+    renderer::{
+        model::Model,
+        render_state::RenderState,
+        texture::{SamplerOptions, Texture, TextureKind},
+    },
+    resources::{self, ModelData},
+};
+
+use super::{asset_cache::Handle, asset_manager::AssetMgr};
+
+enum LoadRequest {
+    Texture { file_name: String, kind: TextureKind },
+    Model { file_name: String },
+    Sound { file_name: String },
+    Heightfield { settings: HeightfieldSettings },
+}
+
+/// Asset data loaded without GPU access.
+enum LoadedData {
+    Texture(Vec<image::RgbaImage>),
+    Model(ModelData),
+    Sound(StaticSoundData),
+    Heightfield(Heightfield),
+}
+
+struct LoadResult {
+    request_i: usize,
+    data: anyhow::Result<LoadedData>,
+}
+
+/// Loads assets in the background.
+/// Files are read and decoded, and heightfields generated, on worker threads.
+/// GPU uploads are done on the main thread in `update`.
+/// Loaded assets are added to the asset caches, so later loads of the same files are instant.
+pub struct AssetLoader {
+    /// Requested files. Textures also keep their load settings.
+    request_file: Vec<String>,
+    request_texture_kind: Vec<Option<TextureKind>>,
+    request_sampler_options: Vec<Option<SamplerOptions>>,
+    is_request_finished: Vec<bool>,
+    finished_count: usize,
+
+    /// Decoded models waiting for their textures, by request index.
+    waiting_model_request_i: Vec<usize>,
+    waiting_model_data: Vec<ModelData>,
+
+    /// References to the loaded assets, held until `release`.
+    textures: Vec<Handle<Texture>>,
+    models: Vec<Handle<Model>>,
+    sounds: Vec<Handle<StaticSoundData>>,
+    heightfields: Vec<Handle<Arc<Heightfield>>>,
+
+    last_finished_file: Option<String>,
+    errors: Vec<String>,
+
+    request_sender: Option<Sender<(usize, LoadRequest)>>,
+    result_receiver: Receiver<LoadResult>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AssetLoader {
+    pub fn new(worker_count: usize) -> Self {
+        let (request_sender, request_receiver) = mpsc::channel::<(usize, LoadRequest)>();
+        let (result_sender, result_receiver) = mpsc::channel::<LoadResult>();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+
+        let mut workers = Vec::with_capacity(worker_count);
+        for i in 0..usize::max(worker_count, 1) {
+            let request_receiver = request_receiver.clone();
+            let result_sender = result_sender.clone();
+
+            let worker = thread::Builder::new()
+                .name(format!("Asset loader worker {}", i))
+                .spawn(move || loop {
+                    // The lock is released as soon as a request is taken.
+                    let request = request_receiver.lock().unwrap().recv();
+                    let (request_i, request) = match request {
+                        Ok(request) => request,
+                        // The loader has been dropped
+                        Err(_) => break,
+                    };
+
+                    let data = pollster::block_on(Self::load(request));
+                    if result_sender.send(LoadResult { request_i, data }).is_err() {
+                        break;
+                    }
+                })
+                .unwrap();
+            workers.push(worker);
+        }
+
+        Self {
+            request_file: Vec::new(),
+            request_texture_kind: Vec::new(),
+            request_sampler_options: Vec::new(),
+            is_request_finished: Vec::new(),
+            finished_count: 0,
+
+            waiting_model_request_i: Vec::new(),
+            waiting_model_data: Vec::new(),
+
+            textures: Vec::new(),
+            models: Vec::new(),
+            sounds: Vec::new(),
+            heightfields: Vec::new(),
+
+            last_finished_file: None,
+            errors: Vec::new(),
+
+            request_sender: Some(request_sender),
+            result_receiver,
+            workers,
+        }
+    }
+
+    /// Runs on worker threads.
+    async fn load(request: LoadRequest) -> anyhow::Result<LoadedData> {
+        match request {
+            LoadRequest::Texture { file_name, kind } => {
+                let data = resources::load_binary(&file_name).await?;
+                Ok(LoadedData::Texture(Texture::decode_mip_chain(&data, kind)?))
+            }
+            LoadRequest::Model { file_name } => Ok(LoadedData::Model(
+                resources::load_model_data(&file_name).await?,
+            )),
+            LoadRequest::Sound { file_name } => Ok(LoadedData::Sound(
+                resources::load_static_sound_data(&file_name).await?,
+            )),
+            LoadRequest::Heightfield { settings } => {
+                Ok(LoadedData::Heightfield(settings.load().await?))
+            }
+        }
+    }
+
+    /// Queues a texture, unless it's already loaded or requested.
+    pub fn load_texture(
+        &mut self,
+        file_name: &str,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
+        asset_mgr: &AssetMgr,
+    ) {
+        if asset_mgr.get_texture(file_name, kind).is_some()
+            || self.request_index(file_name, Some(kind)).is_some()
+        {
+            return;
+        }
+
+        self.request(
+            file_name,
+            Some(kind),
+            Some(*sampler_options),
+            LoadRequest::Texture {
+                file_name: String::from(file_name),
+                kind,
+            },
+        );
+    }
+
+    /// Queues an OBJ model and its material textures, unless it's already loaded or requested.
+    pub fn load_model(&mut self, file_name: &str, model_mgr: &ModelMgr) {
+        if model_mgr.get_with_name(file_name).is_some()
+            || self.request_index(file_name, None).is_some()
+        {
+            return;
+        }
+
+        self.request(
+            file_name,
+            None,
+            None,
+            LoadRequest::Model {
+                file_name: String::from(file_name),
+            },
+        );
+    }
+
+    /// Queues a sound, unless it's already loaded or requested.
+    pub fn load_sound(&mut self, file_name: &str, asset_mgr: &AssetMgr) {
+        if asset_mgr.sounds.get_with_key(file_name).is_some()
+            || self.request_index(file_name, None).is_some()
+        {
+            return;
+        }
+
+        self.request(
+            file_name,
+            None,
+            None,
+            LoadRequest::Sound {
+                file_name: String::from(file_name),
+            },
+        );
+    }
+
+    /// Queues a heightfield, unless it's already loaded or requested.
+    /// It's requested by `HeightfieldSettings::name`, like `AssetMgr::load_heightfield`.
+    pub fn load_heightfield(&mut self, settings: &HeightfieldSettings, asset_mgr: &AssetMgr) {
+        let name = settings.name();
+        if asset_mgr.heightfields.get_with_key(&name).is_some()
+            || self.request_index(&name, None).is_some()
+        {
+            return;
+        }
+
+        self.request(
+            &name,
+            None,
+            None,
+            LoadRequest::Heightfield {
+                settings: settings.clone(),
+            },
+        );
+    }
+
+    fn request(
+        &mut self,
+        file_name: &str,
+        texture_kind: Option<TextureKind>,
+        sampler_options: Option<SamplerOptions>,
+        request: LoadRequest,
+    ) {
+        self.request_file.push(String::from(file_name));
+        self.request_texture_kind.push(texture_kind);
+        self.request_sampler_options.push(sampler_options);
+        self.is_request_finished.push(false);
+
+        let request_i = self.request_file.len() - 1;
+        self.request_sender
+            .as_ref()
+            .unwrap()
+            .send((request_i, request))
+            .unwrap();
+    }
+
+    fn request_index(&self, file_name: &str, texture_kind: Option<TextureKind>) -> Option<usize> {
+        (0..self.request_file.len()).find(|request_i| {
+            self.request_file[*request_i] == file_name
+                && self.request_texture_kind[*request_i] == texture_kind
+        })
+    }
+
+    fn finish(&mut self, request_i: usize) {
+        self.is_request_finished[request_i] = true;
+        self.finished_count += 1;
+        self.last_finished_file = Some(self.request_file[request_i].clone());
+    }
+
+    /// Uploads the assets decoded since the last update to the GPU and adds them to the caches.
+    pub fn update(
+        &mut self,
+        asset_mgr: &mut AssetMgr,
+        model_mgr: &mut ModelMgr,
+        render_state: &RenderState,
+        mesh_renderer_mgr: &MeshInstancedRendererMgr,
+    ) {
+        while let Ok(result) = self.result_receiver.try_recv() {
+            let request_i = result.request_i;
+            let file_name = self.request_file[request_i].clone();
+
+            match result.data {
+                Ok(LoadedData::Texture(mip_chain)) => {
+                    let texture = asset_mgr.add_texture_from_mip_chain(
+                        &file_name,
+                        &mip_chain,
+                        self.request_texture_kind[request_i].unwrap(),
+                        &self.request_sampler_options[request_i].unwrap(),
+                        &render_state.device,
+                        &render_state.queue,
+                    );
+                    self.textures.push(texture);
+                    self.finish(request_i);
+                }
+                Ok(LoadedData::Model(model_data)) => {
+                    // Material textures are decoded in the background too,
+                    // the model is created once they're loaded.
+                    for material in model_data.materials.iter() {
+                        self.load_texture(
+                            material.diffuse_texture.to_str().unwrap(),
+                            TextureKind::Color,
+                            &SamplerOptions::TILING,
+                            asset_mgr,
+                        );
+                        self.load_texture(
+                            material.normal_texture.to_str().unwrap(),
+                            TextureKind::NormalMap,
+                            &SamplerOptions::TILING,
+                            asset_mgr,
+                        );
+                    }
+                    self.waiting_model_request_i.push(request_i);
+                    self.waiting_model_data.push(model_data);
+                }
+                Ok(LoadedData::Sound(sound_data)) => {
+                    self.sounds.push(asset_mgr.sounds.add(&file_name, sound_data));
+                    self.finish(request_i);
+                }
+                Ok(LoadedData::Heightfield(heightfield)) => {
+                    self.heightfields.push(
+                        asset_mgr
+                            .heightfields
+                            .add(&file_name, Arc::new(heightfield)),
+                    );
+                    self.finish(request_i);
+                }
+                Err(e) => {
+                    println!("Failed to load {}: {}", file_name, e);
+                    self.errors.push(format!("{}: {}", file_name, e));
+                    self.finish(request_i);
+                }
+            }
+        }
+
+        let mut waiting_i = 0;
+        while waiting_i < self.waiting_model_data.len() {
+            let is_ready = self.waiting_model_data[waiting_i]
+                .materials
+                .iter()
+                .all(|material| {
+                    self.is_texture_finished(&material.diffuse_texture, TextureKind::Color)
+                        && self.is_texture_finished(&material.normal_texture, TextureKind::NormalMap)
+                });
+            if !is_ready {
+                waiting_i += 1;
+                continue;
+            }
+
+            let request_i = self.waiting_model_request_i.remove(waiting_i);
+            let model_data = self.waiting_model_data.remove(waiting_i);
+            let file_name = self.request_file[request_i].clone();
+
+            // Textures are already loaded, this only creates buffers and bind groups
+            let model = pollster::block_on(resources::create_model(
+                &file_name,
+                model_data,
+                asset_mgr,
+                &render_state.device,
+                &render_state.queue,
+                &mesh_renderer_mgr.texture_bind_group_layout,
+            ));
+            match model {
+                Ok(model) => self.models.push(model_mgr.add(model, &file_name)),
+                Err(e) => {
+                    println!("Failed to load {}: {}", file_name, e);
+                    self.errors.push(format!("{}: {}", file_name, e));
+                }
+            }
+            self.finish(request_i);
+        }
+    }
+
+    /// Textures loaded before the request count as finished.
+    fn is_texture_finished(&self, file_name: &std::path::Path, kind: TextureKind) -> bool {
+        match self.request_index(file_name.to_str().unwrap(), Some(kind)) {
+            Some(request_i) => self.is_request_finished[request_i],
+            None => true,
+        }
+    }
+
+    /// Whether every requested asset finished loading, or failed.
+    pub fn is_finished(&self) -> bool {
+        self.finished_count == self.request_file.len()
+    }
+
+    /// Fraction of finished requests, from 0 to 1.
+    /// Can decrease when loaded models request their textures.
+    pub fn progress(&self) -> f32 {
+        if self.request_file.is_empty() {
+            return 1.0;
+        }
+
+        self.finished_count as f32 / self.request_file.len() as f32
+    }
+
+    /// Removes the loader references to the loaded assets.
+    /// Call once the assets are in use, unused ones are then dropped by `unload_unused`.
+    pub fn release(&mut self, asset_mgr: &mut AssetMgr, model_mgr: &mut ModelMgr) {
+        for texture in self.textures.drain(..) {
+            asset_mgr.textures.release(texture);
+        }
+        for model in self.models.drain(..) {
+            model_mgr.release(model);
+        }
+        for sound in self.sounds.drain(..) {
+            asset_mgr.sounds.release(sound);
+        }
+        for heightfield in self.heightfields.drain(..) {
+            asset_mgr.heightfields.release(heightfield);
+        }
+    }
+
+    /// Full screen loading progress.
+    pub fn ui(&self, context: &egui::Context) {
+        egui::CentralPanel::default().show(context, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(ui.available_height() / 2.0 - 40.0);
+                ui.heading("Loading");
+                ui.add(
+                    egui::ProgressBar::new(self.progress())
+                        .desired_width(400.0)
+                        .text(format!(
+                            "{} / {}",
+                            self.finished_count,
+                            self.request_file.len()
+                        )),
+                );
+                if let Some(file) = &self.last_finished_file {
+                    ui.label(file);
+                }
+                for error in self.errors.iter() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+            });
+        });
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // Closing the request channel stops the workers
+        self.request_sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::{path::PathBuf, sync::Arc};
+
+use kira::sound::static_sound::StaticSoundData;
+
+use crate::{
+    game::terrain::heightfield::{Heightfield, HeightfieldSettings},
+    renderer::texture::{SamplerOptions, Texture, TextureKind},
+    resources::{self, AssetWatcher},
+};
+
+use super::asset_cache::{AssetCache, Handle};
+
+/// Shared textures, sounds and terrain heightfields, loaded once per file.
+/// Models are cached by `ModelMgr` and shaders by `ShaderMgr`.
+pub struct AssetMgr {
+    pub textures: AssetCache<Texture>,
+    /// Texture load settings, for hot reloading. Indexed by texture handle.
+    /// The path is `None` for generated textures.
+    texture_path: Vec<Option<PathBuf>>,
+    texture_kind: Vec<TextureKind>,
+    texture_sampler_options: Vec<SamplerOptions>,
+
+    pub sounds: AssetCache<StaticSoundData>,
+
+    /// Shared with the terrain chunk workers.
+    pub heightfields: AssetCache<Arc<Heightfield>>,
+}
+
+impl AssetMgr {
+    pub fn new() -> Self {
+        Self {
+            textures: AssetCache::new(),
+            texture_path: Vec::new(),
+            texture_kind: Vec::new(),
+            texture_sampler_options: Vec::new(),
+
+            sounds: AssetCache::new(),
+
+            heightfields: AssetCache::new(),
+        }
+    }
+
+    /// Loads a texture, or adds a reference to it if it's already loaded.
+    /// The same file can be loaded once per `TextureKind`, the first sampler options are kept.
+    pub async fn load_texture(
+        &mut self,
+        file_name: &str,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let key = Self::texture_key(file_name, kind);
+        if let Some(handle) = self.textures.acquire_with_key(&key) {
+            return Ok(handle);
+        }
+
+        let texture =
+            resources::load_texture(file_name, kind, sampler_options, device, queue).await?;
+
+        Ok(self.add_texture_with_path(
+            &key,
+            Some(PathBuf::from(file_name)),
+            texture,
+            kind,
+            sampler_options,
+        ))
+    }
+
+    /// Returns a loaded texture without adding a reference.
+    pub fn get_texture(&self, file_name: &str, kind: TextureKind) -> Option<Handle<Texture>> {
+        self.textures.get_with_key(&Self::texture_key(file_name, kind))
+    }
+
+    /// Adds a texture decoded on a loader thread with `Texture::decode_mip_chain`.
+    pub fn add_texture_from_mip_chain(
+        &mut self,
+        file_name: &str,
+        mip_chain: &[image::RgbaImage],
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Handle<Texture> {
+        let texture = Texture::from_mip_chain(
+            device,
+            queue,
+            mip_chain,
+            Some(file_name),
+            kind,
+            sampler_options,
+        );
+
+        self.add_texture_with_path(
+            &Self::texture_key(file_name, kind),
+            Some(PathBuf::from(file_name)),
+            texture,
+            kind,
+            sampler_options,
+        )
+    }
+
+    /// Adds a generated texture, which isn't hot reloaded.
+    /// Use `textures.acquire_with_key` to share it.
+    pub fn add_texture(
+        &mut self,
+        key: &str,
+        texture: Texture,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
+    ) -> Handle<Texture> {
+        self.add_texture_with_path(key, None, texture, kind, sampler_options)
+    }
+
+    fn add_texture_with_path(
+        &mut self,
+        key: &str,
+        path: Option<PathBuf>,
+        texture: Texture,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
+    ) -> Handle<Texture> {
+        let handle = self.textures.add(key, texture);
+        self.texture_path.push(path);
+        self.texture_kind.push(kind);
+        self.texture_sampler_options.push(*sampler_options);
+
+        handle
+    }
+
+    fn texture_key(file_name: &str, kind: TextureKind) -> String {
+        format!("{} ({:?})", file_name, kind)
+    }
+
+    /// Loads a sound, or adds a reference to it if it's already loaded.
+    pub async fn load_sound(&mut self, file_name: &str) -> anyhow::Result<Handle<StaticSoundData>> {
+        if let Some(handle) = self.sounds.acquire_with_key(file_name) {
+            return Ok(handle);
+        }
+
+        let sound_data = resources::load_static_sound_data(file_name).await?;
+
+        Ok(self.sounds.add(file_name, sound_data))
+    }
+
+    /// Loads or generates a heightfield, or adds a reference to it if it's already loaded.
+    /// Heightfields are cached by `HeightfieldSettings::name`.
+    pub async fn load_heightfield(
+        &mut self,
+        settings: &HeightfieldSettings,
+    ) -> anyhow::Result<Handle<Arc<Heightfield>>> {
+        let key = settings.name();
+        if let Some(handle) = self.heightfields.acquire_with_key(&key) {
+            return Ok(handle);
+        }
+
+        let heightfield = settings.load().await?;
+
+        Ok(self.heightfields.add(&key, Arc::new(heightfield)))
+    }
+
+    /// Reloads changed texture files in place, keeping their handles.
+    /// Bind groups using the returned textures must be recreated.
+    /// Textures that fail to load keep their previous version.
+    pub fn reload_changed(
+        &mut self,
+        asset_watcher: &AssetWatcher,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<Handle<Texture>> {
+        let mut reloaded = Vec::new();
+        if asset_watcher.changed_files().is_empty() {
+            return reloaded;
+        }
+
+        let handles: Vec<Handle<Texture>> = self.textures.handles().collect();
+        for handle in handles {
+            let Some(path) = &self.texture_path[handle.index()] else {
+                continue;
+            };
+            if !asset_watcher
+                .changed_files()
+                .iter()
+                .any(|changed| changed == path)
+            {
+                continue;
+            }
+
+            let texture = pollster::block_on(resources::load_texture(
+                path.to_str().unwrap(),
+                self.texture_kind[handle.index()],
+                &self.texture_sampler_options[handle.index()],
+                device,
+                queue,
+            ));
+            match texture {
+                Ok(texture) => {
+                    println!("Reloaded texture: {}", self.textures.key(handle));
+                    self.textures.replace(handle, texture);
+                    reloaded.push(handle);
+                }
+                Err(e) => println!(
+                    "Failed to reload texture {}: {}",
+                    self.textures.key(handle),
+                    e
+                ),
+            }
+        }
+
+        reloaded
+    }
+
+    /// Drops textures, sounds and heightfields without references.
+    pub fn unload_unused(&mut self) {
+        self.textures.unload_unused();
+        self.sounds.unload_unused();
+        self.heightfields.unload_unused();
+    }
+}
@@ -0,0 +1,4 @@
+pub mod asset_archive;
+pub mod asset_cache;
+pub mod asset_loader;
+pub mod asset_manager;
@@ -1,9 +1,15 @@
-use std::{f32::consts::FRAC_PI_2, time::Duration};
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    time::Duration,
+};
 
 use anyhow::Result;
-use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, One, Point3, Quaternion, Rad, Vector3, Zero};
 
-use crate::renderer::render_state::RenderState;
+use crate::{
+    assets::asset_manager::AssetMgr,
+    renderer::{model::InstanceRaw, render_state::RenderState},
+};
 
 use super::{
     aircraft_input::AircraftInputMgr,
@@ -11,11 +17,114 @@ use super::{
     inventory::InventoryMgr,
     mesh_renderer::MeshInstancedRendererMgr,
     model::ModelMgr,
-    transform::TransformMgr,
+    particles::particle_emitter::{ParticleBlendMode, ParticleEmitterMgr, ParticleEmitterSettings},
+    terrain::terrain::Terrain,
+    trails::ribbon_trail::{RibbonTrailMgr, RibbonTrailSettings},
+    transform::{TransformMgr, UNIT_SCALE},
 };
 
 const MAX_INSTANCE_COUNT: usize = 128;
 
+pub const MODEL_PATH: &str = "models/Aircraft_1.obj";
+
+/// Just above the fuselage of the aircraft model.
+const COCKPIT_CAMERA_OFFSET: Vector3<f32> = Vector3::new(0.0, 0.3, 0.2);
+
+/// Behind the tail of the aircraft model.
+const EXHAUST_OFFSET: Vector3<f32> = Vector3::new(0.0, 0.0, -0.7);
+
+const EXHAUST_PARTICLES: ParticleEmitterSettings = ParticleEmitterSettings {
+    spawn_rate: 120.0,
+    lifetime: 0.35,
+    direction: Vector3::new(0.0, 0.0, -1.0),
+    cone_angle: Rad(0.15),
+    min_speed: 4.0,
+    max_speed: 6.0,
+    gravity: 0.0,
+    drag: 2.0,
+    // Above 1 to bloom
+    start_color: [4.0, 1.6, 0.4, 1.0],
+    end_color: [0.6, 0.1, 0.02, 0.0],
+    start_size: 0.25,
+    end_size: 0.05,
+    blend_mode: ParticleBlendMode::Additive,
+};
+
+/// Multiplies the model colors of enemy aircraft.
+const ENEMY_TINT: [f32; 4] = [1.0, 0.35, 0.3, 1.0];
+
+/// Seconds the aircraft glows after a hit.
+const HIT_FLASH_DURATION: f32 = 0.3;
+const HIT_FLASH_COLOR: [f32; 3] = [3.0, 3.0, 3.0];
+
+/// Enemies within range and this cone in front of the player are locked and outlined.
+const LOCK_RANGE: f32 = 100.0;
+/// Cosine of the cone half angle, about 15 degrees.
+const LOCK_CONE_COS: f32 = 0.966;
+
+/// Trailing edges of the left and right wingtips.
+const WINGTIP_OFFSETS: [Vector3<f32>; 2] =
+    [Vector3::new(-1.0, 0.0, -0.5), Vector3::new(1.0, 0.0, -0.5)];
+
+const WINGTIP_TRAIL: RibbonTrailSettings = RibbonTrailSettings {
+    lifetime: 1.5,
+    sample_interval: 0.05,
+    start_color: [1.0, 1.0, 1.0, 0.6],
+    end_color: [1.0, 1.0, 1.0, 0.0],
+    start_width: 0.1,
+    end_width: 0.02,
+};
+
+const ENGINE_TRAIL: RibbonTrailSettings = RibbonTrailSettings {
+    lifetime: 4.0,
+    sample_interval: 0.1,
+    start_color: [0.9, 0.9, 0.9, 0.5],
+    end_color: [1.0, 1.0, 1.0, 0.0],
+    start_width: 0.3,
+    end_width: 1.5,
+};
+
+const SMOKE_PARTICLES: ParticleEmitterSettings = ParticleEmitterSettings {
+    spawn_rate: 40.0,
+    lifetime: 2.5,
+    direction: Vector3::new(0.0, 0.0, -1.0),
+    cone_angle: Rad(0.4),
+    min_speed: 1.0,
+    max_speed: 2.0,
+    gravity: -0.5,
+    drag: 0.5,
+    start_color: [0.1, 0.1, 0.1, 0.8],
+    end_color: [0.3, 0.3, 0.3, 0.0],
+    start_size: 0.3,
+    end_size: 2.0,
+    blend_mode: ParticleBlendMode::Alpha,
+};
+
+/// Sparks burst when the aircraft is first hit.
+const EXPLOSION_PARTICLES: ParticleEmitterSettings = ParticleEmitterSettings {
+    spawn_rate: 0.0,
+    lifetime: 0.8,
+    direction: Vector3::new(0.0, 1.0, 0.0),
+    cone_angle: Rad(PI),
+    min_speed: 2.0,
+    max_speed: 8.0,
+    gravity: 4.0,
+    drag: 1.5,
+    // Above 1 to bloom
+    start_color: [6.0, 3.0, 0.8, 1.0],
+    end_color: [0.8, 0.15, 0.02, 0.0],
+    start_size: 0.4,
+    end_size: 0.1,
+    blend_mode: ParticleBlendMode::Additive,
+};
+const EXPLOSION_PARTICLE_COUNT: usize = 150;
+
+/// Managers of the particle and trail effects attached to each aircraft.
+pub struct AircraftEffectMgrs<'a> {
+    pub particle_emitter_mgr: &'a mut ParticleEmitterMgr,
+    pub ribbon_trail_mgr: &'a mut RibbonTrailMgr,
+}
+
 /// Represents aircraft, both player and enemy.
 /// Aircraft index 0 is always the player.
 pub struct AircraftMgr {
@@ -37,6 +146,17 @@ pub struct AircraftMgr {
     start_position: Vec<Point3<f32>>,
     start_rotation: Vec<Quaternion<f32>>,
 
+    /// World units per second over the last update, zero after a transform reset.
+    pub velocity: Vec<Vector3<f32>>,
+
+    /// Set by mid-air collisions, until the transform is reset.
+    is_damaged: Vec<bool>,
+    /// Seconds left of the hit flash.
+    hit_flash_time: Vec<f32>,
+
+    /// Aircraft locked by the player.
+    pub locked_target_i: Option<usize>,
+
     // TODO: research a safer way to store references
     pub inventory_i: Vec<Option<usize>>,
 
@@ -44,6 +164,16 @@ pub struct AircraftMgr {
     pub collider_i: Vec<Option<usize>>,
     pub input_i: Vec<Option<usize>>,
     pub mesh_renderer_i: Vec<Option<usize>>,
+    /// Child transform the player camera is attached to.
+    pub camera_socket_i: Vec<Option<usize>>,
+    pub exhaust_emitter_i: Vec<Option<usize>>,
+    /// Emits while damaged.
+    pub smoke_emitter_i: Vec<Option<usize>>,
+    /// Only bursts, when first hit.
+    pub explosion_emitter_i: Vec<Option<usize>>,
+    /// Left and right.
+    pub wingtip_trail_i: Vec<Option<[usize; 2]>>,
+    pub engine_trail_i: Vec<Option<usize>>,
 }
 
 impl AircraftMgr {
@@ -67,12 +197,25 @@ impl AircraftMgr {
             start_position: Vec::with_capacity(MAX_INSTANCE_COUNT),
             start_rotation: Vec::with_capacity(MAX_INSTANCE_COUNT),
 
+            velocity: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            is_damaged: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            hit_flash_time: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            locked_target_i: None,
+
             inventory_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
 
             transform_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
             collider_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
             input_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
             mesh_renderer_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            camera_socket_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            exhaust_emitter_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            smoke_emitter_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            explosion_emitter_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            wingtip_trail_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            engine_trail_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
         })
     }
 
@@ -97,14 +240,21 @@ impl AircraftMgr {
         inventory_mgr: &mut InventoryMgr,
 
         model_mgr: &mut ModelMgr,
+        asset_mgr: &mut AssetMgr,
 
         transform_mgr: &mut TransformMgr,
         collider_mgr: &mut ColliderMgr,
         input_mgr: &mut AircraftInputMgr,
         mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
+        effect_mgrs: &mut AircraftEffectMgrs<'_>,
 
         render_state: &RenderState,
     ) -> Result<usize> {
+        let AircraftEffectMgrs {
+            particle_emitter_mgr,
+            ribbon_trail_mgr,
+        } = effect_mgrs;
+
         self.pilot_type.push(pilot_type.clone());
 
         self.throttle.push(min_speed);
@@ -123,22 +273,41 @@ impl AircraftMgr {
         self.start_position.push(start_position);
         self.start_rotation.push(start_rotation);
 
+        self.velocity.push(Vector3::zero());
+
+        self.is_damaged.push(false);
+        self.hit_flash_time.push(0.0);
+
         let index = self.len() - 1;
 
         self.inventory_i.push(Some(inventory_mgr.add().unwrap()));
 
-        self.transform_i
-            .push(Some(transform_mgr.add(start_position, start_rotation)));
+        self.transform_i.push(Some(transform_mgr.add(
+            start_position,
+            start_rotation,
+            UNIT_SCALE,
+        )));
         self.input_i
             .push(Some(input_mgr.add(pilot_type.clone(), index)));
 
         let transform_i = self.transform_i[index].unwrap();
-        let position = transform_mgr.position[transform_i];
-        let rotation = transform_mgr.rotation[transform_i];
+        let position = transform_mgr.position(transform_i);
+        let rotation = transform_mgr.rotation(transform_i);
+        let scale = transform_mgr.scale(transform_i);
+
+        let camera_socket_i = match pilot_type {
+            AircraftPilot::Player => Some(transform_mgr.add_child(
+                transform_i,
+                Point3::from_vec(COCKPIT_CAMERA_OFFSET),
+                Quaternion::one(),
+                UNIT_SCALE,
+            )),
+            AircraftPilot::Ai => None,
+        };
+        self.camera_socket_i.push(camera_socket_i);
 
-        let model_path = "models/Aircraft_1.obj";
         let model_i = model_mgr
-            .get_with_name_or_add(model_path, render_state, mesh_renderer_mgr)
+            .get_with_name_or_add(MODEL_PATH, asset_mgr, render_state, mesh_renderer_mgr)
             .await;
 
         self.collider_i.push(Some(
@@ -153,6 +322,7 @@ impl AircraftMgr {
                     render_state,
                     transform_mgr,
                     model_mgr,
+                    asset_mgr,
                     mesh_renderer_mgr,
                 )
                 .await
@@ -160,12 +330,51 @@ impl AircraftMgr {
         ));
 
         let mesh_renderer_i = match pilot_type {
-            AircraftPilot::Player | AircraftPilot::Ai => {
-                Some(mesh_renderer_mgr.add(render_state, model_i, position.to_vec(), rotation))
-            }
+            AircraftPilot::Player | AircraftPilot::Ai => Some(mesh_renderer_mgr.add(
+                render_state,
+                model_mgr,
+                model_i,
+                position.to_vec(),
+                rotation,
+                scale,
+                Self::team_tint(&pilot_type),
+                InstanceRaw::NO_EMISSIVE,
+                false,
+            )),
         };
         self.mesh_renderer_i.push(mesh_renderer_i);
 
+        self.exhaust_emitter_i.push(Some(particle_emitter_mgr.add(
+            &EXHAUST_PARTICLES,
+            true,
+            transform_i,
+            EXHAUST_OFFSET,
+        )));
+        self.smoke_emitter_i.push(Some(particle_emitter_mgr.add(
+            &SMOKE_PARTICLES,
+            false,
+            transform_i,
+            EXHAUST_OFFSET,
+        )));
+        self.explosion_emitter_i.push(Some(particle_emitter_mgr.add(
+            &EXPLOSION_PARTICLES,
+            false,
+            transform_i,
+            Vector3::zero(),
+        )));
+
+        self.wingtip_trail_i
+            .push(Some(WINGTIP_OFFSETS.map(|offset| {
+                ribbon_trail_mgr.add(&WINGTIP_TRAIL, true, transform_i, offset, transform_mgr)
+            })));
+        self.engine_trail_i.push(Some(ribbon_trail_mgr.add(
+            &ENGINE_TRAIL,
+            true,
+            transform_i,
+            EXHAUST_OFFSET,
+            transform_mgr,
+        )));
+
         Ok(index)
     }
 
@@ -206,6 +415,7 @@ impl AircraftMgr {
 
         // Update transform
         if input_reset_transform {
+            self.is_damaged[i] = false;
             Self::reset_transform(
                 transform_i,
                 self.start_position[i],
@@ -304,14 +514,14 @@ impl AircraftMgr {
 
     pub fn update_ai(&mut self, index: usize, transform_mgr: &mut TransformMgr, dt: f32) {
         let transform_i = self.transform_i[index].unwrap();
-        let position_point = transform_mgr.position[transform_i];
+        let position_point = transform_mgr.position(transform_i);
         let position = position_point.to_vec();
-        let rotation = transform_mgr.rotation[transform_i];
+        let rotation = transform_mgr.rotation(transform_i);
         let mut forward = transform_mgr.forward(transform_i);
 
         let player_index = self.get_player_aircraft_index();
         let player_transform_i = self.transform_i[player_index].unwrap();
-        let player_position: Vector3<f32> = transform_mgr.position[player_transform_i].to_vec();
+        let player_position: Vector3<f32> = transform_mgr.position(player_transform_i).to_vec();
         let player_position_leveled =
             Vector3::new(player_position.x, position.y, player_position.z);
 
@@ -324,7 +534,7 @@ impl AircraftMgr {
             .normalize()
             .slerp(target_rotation.normalize(), 3.0 * dt);
 
-        transform_mgr.rotation[transform_i] = new_rotation;
+        transform_mgr.set_rotation(transform_i, new_rotation);
 
         // Vertical translation
         let y_diff = player_position.y - position.y;
@@ -346,35 +556,146 @@ impl AircraftMgr {
         &mut self,
         transform_mgr: &mut TransformMgr,
         input_mgr: &mut AircraftInputMgr,
+        collider_mgr: &ColliderMgr,
         mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
+        particle_emitter_mgr: &mut ParticleEmitterMgr,
+        ribbon_trail_mgr: &mut RibbonTrailMgr,
+        model_mgr: &ModelMgr,
         render_state: &RenderState,
         dt: Duration,
     ) {
         let dt = dt.as_secs_f32();
 
+        self.locked_target_i = self.find_lock_target(transform_mgr);
+
         for i in 0..self.len() {
             let transform_i = self.transform_i[i].unwrap();
 
+            self.hit_flash_time[i] = f32::max(self.hit_flash_time[i] - dt, 0.0);
+            if self.is_colliding_with_aircraft(i, collider_mgr) {
+                // Explodes on the first hit only, collisions last several updates
+                if !self.is_damaged[i] {
+                    if let Some(explosion_emitter_i) = self.explosion_emitter_i[i] {
+                        particle_emitter_mgr
+                            .emit_burst(explosion_emitter_i, EXPLOSION_PARTICLE_COUNT);
+                    }
+                }
+                self.is_damaged[i] = true;
+                self.hit_flash_time[i] = HIT_FLASH_DURATION;
+            }
+
+            // Trails would otherwise stretch to the start position
+            let is_resetting_transform = self.is_resetting_transform(i, input_mgr);
+            if is_resetting_transform {
+                self.clear_trails(i, ribbon_trail_mgr);
+            }
+
+            let last_position = transform_mgr.position(transform_i);
             match self.pilot_type[i] {
                 AircraftPilot::Player => self.update_player(i, transform_mgr, input_mgr, dt),
                 AircraftPilot::Ai => self.update_ai(i, transform_mgr, dt),
             }
+            self.velocity[i] = if is_resetting_transform || dt <= 0.0 {
+                Vector3::zero()
+            } else {
+                (transform_mgr.position(transform_i) - last_position) / dt
+            };
 
             // Update mesh renderer
             // TODO: might be better off in render method
             if let Some(mesh_renderer_i) = self.mesh_renderer_i[i] {
-                let position = transform_mgr.position[transform_i];
-                let rotation = transform_mgr.rotation[transform_i];
+                let position = transform_mgr.position(transform_i);
+                let rotation = transform_mgr.rotation(transform_i);
+                let scale = transform_mgr.scale(transform_i);
                 mesh_renderer_mgr.update_instance_position(
                     mesh_renderer_i,
                     position.to_vec(),
                     rotation,
+                    scale,
+                    model_mgr,
                     render_state,
                 );
+
+                let flash = self.hit_flash_time[i] / HIT_FLASH_DURATION;
+                mesh_renderer_mgr.update_instance_style(
+                    mesh_renderer_i,
+                    Self::team_tint(&self.pilot_type[i]),
+                    HIT_FLASH_COLOR.map(|channel| channel * flash),
+                    self.locked_target_i == Some(i),
+                    render_state,
+                );
+            };
+
+            if let Some(smoke_emitter_i) = self.smoke_emitter_i[i] {
+                particle_emitter_mgr.set_emitting(smoke_emitter_i, self.is_damaged[i]);
+            }
+        }
+    }
+
+    fn team_tint(pilot_type: &AircraftPilot) -> [f32; 4] {
+        match pilot_type {
+            AircraftPilot::Player => InstanceRaw::NO_TINT,
+            AircraftPilot::Ai => ENEMY_TINT,
+        }
+    }
+
+    /// Nearest enemy within the lock cone of the player, if any.
+    fn find_lock_target(&self, transform_mgr: &TransformMgr) -> Option<usize> {
+        let player_transform_i = self.transform_i[self.get_player_aircraft_index()]?;
+        let player_position = transform_mgr.position(player_transform_i);
+        let player_forward = transform_mgr.forward(player_transform_i);
+
+        let mut locked_target = None;
+        let mut locked_distance = LOCK_RANGE;
+        for i in 0..self.len() {
+            if !matches!(self.pilot_type[i], AircraftPilot::Ai) {
+                continue;
+            }
+            let Some(transform_i) = self.transform_i[i] else {
+                continue;
             };
+
+            let direction = transform_mgr.position(transform_i) - player_position;
+            let distance = direction.magnitude();
+            if distance < locked_distance
+                && player_forward.dot(direction / distance) > LOCK_CONE_COS
+            {
+                locked_target = Some(i);
+                locked_distance = distance;
+            }
+        }
+
+        locked_target
+    }
+
+    fn is_resetting_transform(&self, index: usize, input_mgr: &AircraftInputMgr) -> bool {
+        match self.pilot_type[index] {
+            AircraftPilot::Player => {
+                self.input_i[index].is_some_and(|input_i| input_mgr.input_reset_transform[input_i])
+            }
+            AircraftPilot::Ai => false,
         }
     }
 
+    fn clear_trails(&self, index: usize, ribbon_trail_mgr: &mut RibbonTrailMgr) {
+        let wingtip_trail_i = self.wingtip_trail_i[index].into_iter().flatten();
+        for trail_i in wingtip_trail_i.chain(self.engine_trail_i[index]) {
+            ribbon_trail_mgr.clear(trail_i);
+        }
+    }
+
+    /// Uses the collisions of the last collider update.
+    fn is_colliding_with_aircraft(&self, index: usize, collider_mgr: &ColliderMgr) -> bool {
+        let Some(collider_i) = self.collider_i[index] else {
+            return false;
+        };
+
+        collider_mgr.colliding_indices[collider_i]
+            .iter()
+            .filter(|other_collider_i| **other_collider_i >= 0)
+            .any(|other_collider_i| self.collider_i.contains(&Some(*other_collider_i as usize)))
+    }
+
     fn calculate_accumulated_speed(
         current_speed: f32,
         input: f32,
@@ -401,6 +722,7 @@ impl AircraftMgr {
 
     pub fn ui(
         &self,
+        terrain: &Terrain,
         transform_mgr: &TransformMgr,
         collider_mgr: &ColliderMgr,
         context: &egui::Context,
@@ -411,13 +733,14 @@ impl AircraftMgr {
         let collider_i = self.collider_i[index].unwrap();
 
         let throttle = self.throttle[index];
-        let position = transform_mgr.position[transform_i];
-        let rotation = transform_mgr.rotation[transform_i];
+        let position = transform_mgr.position(transform_i);
+        let rotation = transform_mgr.rotation(transform_i);
         let forward = transform_mgr.forward(transform_i);
         let up = transform_mgr.up(transform_i);
         let right = transform_mgr.right(transform_i);
 
         let colliding_indices = collider_mgr.colliding_indices[collider_i];
+        let ground_height = terrain.height_at(transform_mgr, position.x, position.z);
 
         let throttle_str = format!("Throttle: {:?}", throttle);
         let position_str = format!("Position: {:?}", position);
@@ -426,6 +749,11 @@ impl AircraftMgr {
         let up_str = format!("Up: {:?}", up);
         let right_str = format!("Right: {:?}", right);
         let collisions_str = format!("Colliding indices: {:?}", colliding_indices);
+        let altitude_str = match ground_height {
+            Some(ground_height) => format!("Altitude (ground): {:?}", position.y - ground_height),
+            None => String::from("Altitude (ground): -"),
+        };
+        let locked_target_str = format!("Locked target: {:?}", self.locked_target_i);
 
         egui::SidePanel::left("Player Aircraft")
             .resizable(false)
@@ -440,6 +768,8 @@ impl AircraftMgr {
                 ui.label(up_str);
                 ui.label(right_str);
                 ui.label(collisions_str);
+                ui.label(altitude_str);
+                ui.label(locked_target_str);
             });
     }
 
@@ -449,8 +779,8 @@ impl AircraftMgr {
         start_rotation: Quaternion<f32>,
         transform_mgr: &mut TransformMgr,
     ) {
-        transform_mgr.position[transform_i] = start_position;
-        transform_mgr.rotation[transform_i] = start_rotation;
+        transform_mgr.set_position(transform_i, start_position);
+        transform_mgr.set_rotation(transform_i, start_rotation);
     }
 }
 
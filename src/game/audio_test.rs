@@ -1,20 +1,23 @@
 use kira::sound::static_sound::{PlaybackState, StaticSoundData, StaticSoundHandle};
 use winit::event::{ElementState, MouseButton};
 
-use crate::{audio::audio_manager::AudioMgr, resources};
+use crate::{
+    assets::{asset_cache::Handle, asset_manager::AssetMgr},
+    audio::audio_manager::AudioMgr,
+};
+
+pub const SOUND_PATH: &str = "audio/Cursor_tones/cursor_style_2.ogg";
 
 pub struct AudioTest {
-    sound_data: StaticSoundData,
+    sound_data: Handle<StaticSoundData>,
     playing_sound_data: Option<StaticSoundHandle>,
 
     mouse_pressed: bool,
 }
 
 impl AudioTest {
-    pub async fn new() -> Self {
-        let sound_data = resources::load_static_sound_data("audio/Cursor_tones/cursor_style_2.ogg")
-            .await
-            .unwrap();
+    pub async fn new(asset_mgr: &mut AssetMgr) -> Self {
+        let sound_data = asset_mgr.load_sound(SOUND_PATH).await.unwrap();
 
         Self {
             sound_data,
@@ -42,14 +45,18 @@ impl AudioTest {
         }
     }
 
-    pub fn update(&mut self, audio_mgr: &mut AudioMgr) {
+    pub fn update(&mut self, audio_mgr: &mut AudioMgr, asset_mgr: &AssetMgr) {
         let is_playing = match &self.playing_sound_data {
             Some(sound_handle) => sound_handle.state() == PlaybackState::Playing,
             None => false,
         };
 
         if self.mouse_pressed && !is_playing {
-            self.playing_sound_data = Some(audio_mgr.play(self.sound_data.clone()).unwrap());
+            self.playing_sound_data = Some(
+                audio_mgr
+                    .play(asset_mgr.sounds.get(self.sound_data).clone())
+                    .unwrap(),
+            );
         }
     }
 }
@@ -1,16 +1,45 @@
 use anyhow::Result;
-use cgmath::{EuclideanSpace, Point3, Quaternion};
+use cgmath::{EuclideanSpace, Point3, Quaternion, Rad, Vector3};
 
 use crate::{
+    assets::asset_manager::AssetMgr,
     game::{
-        inventory::InventoryMgr, mesh_renderer::MeshInstancedRendererMgr, model::ModelMgr,
-        transform::TransformMgr,
+        inventory::InventoryMgr,
+        mesh_renderer::MeshInstancedRendererMgr,
+        model::ModelMgr,
+        particles::particle_emitter::{
+            ParticleBlendMode, ParticleEmitterMgr, ParticleEmitterSettings,
+        },
+        transform::{TransformMgr, UNIT_SCALE},
     },
-    renderer::render_state::RenderState,
+    renderer::{model::InstanceRaw, render_state::RenderState},
 };
 
 const MAX_INSTANCE_COUNT: usize = 128;
 
+pub const MODEL_PATH: &str = "models/cube.obj";
+/// Transparent unit cylinder, scaled by the supply range.
+pub const SUPPLY_ZONE_MODEL_PATH: &str = "models/supply_zone.obj";
+
+/// On a corner of the cube model roof.
+const CHIMNEY_OFFSET: Vector3<f32> = Vector3::new(0.6, 1.2, 0.6);
+
+const CHIMNEY_SMOKE_PARTICLES: ParticleEmitterSettings = ParticleEmitterSettings {
+    spawn_rate: 15.0,
+    lifetime: 6.0,
+    direction: Vector3::new(0.0, 1.0, 0.0),
+    cone_angle: Rad(0.2),
+    min_speed: 1.0,
+    max_speed: 1.5,
+    gravity: -0.3,
+    drag: 0.2,
+    start_color: [0.5, 0.5, 0.5, 0.6],
+    end_color: [0.7, 0.7, 0.7, 0.0],
+    start_size: 0.5,
+    end_size: 4.0,
+    blend_mode: ParticleBlendMode::Alpha,
+};
+
 pub struct BuildingMgr {
     pub building_type: Vec<BuildingType>,
 
@@ -21,6 +50,8 @@ pub struct BuildingMgr {
 
     pub transform_i: Vec<Option<usize>>,
     pub mesh_renderer_i: Vec<Option<usize>>,
+    pub supply_zone_mesh_renderer_i: Vec<Option<usize>>,
+    pub particle_emitter_i: Vec<Option<usize>>,
 }
 
 impl BuildingMgr {
@@ -34,6 +65,8 @@ impl BuildingMgr {
 
             transform_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
             mesh_renderer_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            supply_zone_mesh_renderer_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            particle_emitter_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
         }
     }
 
@@ -52,7 +85,9 @@ impl BuildingMgr {
 
         transform_mgr: &mut TransformMgr,
         model_mgr: &mut ModelMgr,
+        asset_mgr: &mut AssetMgr,
         mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
+        particle_emitter_mgr: &mut ParticleEmitterMgr,
         render_state: &RenderState,
     ) -> Result<usize> {
         self.building_type.push(building_type.clone());
@@ -62,17 +97,64 @@ impl BuildingMgr {
         self.supply_range.push(supply_range);
         self.supply_period_ms.push(supply_period_ms);
 
-        self.transform_i
-            .push(Some(transform_mgr.add(position, rotation)));
+        let transform_i = transform_mgr.add(position, rotation, UNIT_SCALE);
+        self.transform_i.push(Some(transform_i));
 
         let model_i = model_mgr
-            .get_with_name_or_add("models/cube.obj", render_state, mesh_renderer_mgr)
+            .get_with_name_or_add(MODEL_PATH, asset_mgr, render_state, mesh_renderer_mgr)
             .await;
 
-        let mesh_renderer_i =
-            Some(mesh_renderer_mgr.add(render_state, model_i, position.to_vec(), rotation));
+        let mesh_renderer_i = Some(mesh_renderer_mgr.add(
+            render_state,
+            model_mgr,
+            model_i,
+            position.to_vec(),
+            rotation,
+            UNIT_SCALE,
+            InstanceRaw::NO_TINT,
+            InstanceRaw::NO_EMISSIVE,
+            false,
+        ));
         self.mesh_renderer_i.push(mesh_renderer_i);
 
+        let supply_zone_mesh_renderer_i = match supply_range {
+            Some(supply_range) => {
+                let model_i = model_mgr
+                    .get_with_name_or_add(
+                        SUPPLY_ZONE_MODEL_PATH,
+                        asset_mgr,
+                        render_state,
+                        mesh_renderer_mgr,
+                    )
+                    .await;
+
+                Some(mesh_renderer_mgr.add(
+                    render_state,
+                    model_mgr,
+                    model_i,
+                    position.to_vec(),
+                    rotation,
+                    Vector3::new(supply_range, supply_range, supply_range),
+                    InstanceRaw::NO_TINT,
+                    InstanceRaw::NO_EMISSIVE,
+                    false,
+                ))
+            }
+            None => None,
+        };
+        self.supply_zone_mesh_renderer_i
+            .push(supply_zone_mesh_renderer_i);
+
+        let particle_emitter_i = match building_type {
+            BuildingType::Factory => Some(particle_emitter_mgr.add(
+                &CHIMNEY_SMOKE_PARTICLES,
+                true,
+                transform_i,
+                CHIMNEY_OFFSET,
+            )),
+        };
+        self.particle_emitter_i.push(particle_emitter_i);
+
         let index = self.len() - 1;
         Ok(index)
     }
@@ -0,0 +1,92 @@
+use std::f32::consts::FRAC_PI_2;
+
+use cgmath::{InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    input::{cursor_manager::CursorMgr, keyboard_manager::KeyboardMgr},
+    renderer::camera::Camera,
+};
+
+/// World units per second.
+const MOVE_SPEED: f32 = 10.0;
+/// Move speed multipliers, while holding shift or control.
+const FAST_MULTIPLIER: f32 = 5.0;
+const SLOW_MULTIPLIER: f32 = 0.2;
+/// Radians per second, for the arrow keys.
+const LOOK_SPEED: f32 = 1.5;
+/// Radians per unit of mouse motion.
+const MOUSE_SENSITIVITY: f32 = 0.003;
+/// Just short of straight up or down, where yaw is undefined.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.05;
+
+/// Debug camera detached from the scene.
+/// Moves with WASD, down and up with Q and E, and looks around with the mouse or the arrow keys.
+/// Shift moves faster and control slower.
+pub struct FreeFlyCamera {
+    pub position: Point3<f32>,
+    /// Around the world up axis, zero looks along +z.
+    pub yaw: Rad<f32>,
+    /// Positive looks up.
+    pub pitch: Rad<f32>,
+}
+
+impl FreeFlyCamera {
+    pub fn new() -> Self {
+        Self {
+            position: Point3::new(0.0, 0.0, 0.0),
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+        }
+    }
+
+    /// Continues from the current camera view, without roll.
+    pub fn set_from_camera(&mut self, camera: &Camera) {
+        let forward = camera.rotation.rotate_vector(Vector3::unit_z()).normalize();
+
+        self.position = camera.position;
+        self.yaw = Rad(f32::atan2(forward.x, forward.z));
+        self.pitch = Rad(f32::asin(forward.y.clamp(-1.0, 1.0)).clamp(-MAX_PITCH, MAX_PITCH));
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        Quaternion::from_angle_y(self.yaw) * Quaternion::from_angle_x(-self.pitch)
+    }
+
+    pub fn update(&mut self, keyboard_mgr: &KeyboardMgr, cursor_mgr: &CursorMgr, dt: f32) {
+        let is_pressed = |key: VirtualKeyCode| keyboard_mgr.key_pressed[key as usize];
+        let axis = |negative: VirtualKeyCode, positive: VirtualKeyCode| {
+            is_pressed(positive) as i32 as f32 - is_pressed(negative) as i32 as f32
+        };
+
+        // Look
+        self.yaw -= Rad(
+            axis(VirtualKeyCode::Left, VirtualKeyCode::Right) * LOOK_SPEED * dt
+                + cursor_mgr.delta_x * MOUSE_SENSITIVITY,
+        );
+        self.pitch += Rad(
+            axis(VirtualKeyCode::Down, VirtualKeyCode::Up) * LOOK_SPEED * dt
+                - cursor_mgr.delta_y * MOUSE_SENSITIVITY,
+        );
+        self.pitch = Rad(self.pitch.0.clamp(-MAX_PITCH, MAX_PITCH));
+
+        // Move, relative to the view
+        let rotation = self.rotation();
+        let forward = rotation.rotate_vector(Vector3::unit_z());
+        let screen_right = forward.cross(Vector3::unit_y()).normalize();
+
+        let direction = forward * axis(VirtualKeyCode::S, VirtualKeyCode::W)
+            + screen_right * axis(VirtualKeyCode::A, VirtualKeyCode::D)
+            + Vector3::unit_y() * axis(VirtualKeyCode::Q, VirtualKeyCode::E);
+        let mut speed = MOVE_SPEED;
+        if is_pressed(VirtualKeyCode::LShift) || is_pressed(VirtualKeyCode::RShift) {
+            speed *= FAST_MULTIPLIER;
+        }
+        if is_pressed(VirtualKeyCode::LControl) || is_pressed(VirtualKeyCode::RControl) {
+            speed *= SLOW_MULTIPLIER;
+        }
+        if direction.magnitude2() > 0.0 {
+            self.position += direction.normalize() * speed * dt;
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod free_fly_camera;
 pub mod player_camera;
@@ -1,25 +1,412 @@
+use std::time::Duration;
+
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix3, Point3, Quaternion, Rad, Vector3, Zero};
+use winit::event::VirtualKeyCode;
+
 use crate::{
     game::{aircraft::AircraftMgr, transform::TransformMgr},
-    renderer::camera::Camera,
+    input::{cursor_manager::CursorMgr, keyboard_manager::KeyboardMgr},
+    renderer::camera::{Camera, Projection, ProjectionMode},
 };
 
-pub struct PlayerCameraController {}
+use super::free_fly_camera::FreeFlyCamera;
+
+/// Seconds to blend from the previous camera view into a new mode.
+const TRANSITION_DURATION: f32 = 0.6;
+
+/// Behind and above the aircraft, along its forward axis.
+const CHASE_DISTANCE: f32 = 4.0;
+const CHASE_HEIGHT: f32 = 1.2;
+/// Seconds the spring takes to catch up, roughly.
+const CHASE_SMOOTH_TIME: f32 = 0.2;
+/// The camera looks where the aircraft will be in this many seconds.
+const CHASE_LOOK_AHEAD_TIME: f32 = 0.5;
+/// How quickly the view turns towards the look ahead point, per second.
+const CHASE_ROTATION_SHARPNESS: f32 = 8.0;
+/// Further than this from its target, the chase camera cuts instead of following.
+const CHASE_SNAP_DISTANCE: f32 = 30.0;
+
+const ORBIT_DISTANCE: f32 = 6.0;
+const ORBIT_HEIGHT: f32 = 2.0;
+/// Radians per second.
+const ORBIT_SPEED: f32 = 0.4;
+
+/// Straight above the aircraft, with an orthographic view this many world units tall.
+const TOP_DOWN_HEIGHT: f32 = 60.0;
+const TOP_DOWN_VIEW_HEIGHT: f32 = 80.0;
+
+/// Perspective field of view, widened from the default with the aircraft speed
+/// in the chase and cockpit cameras.
+const MAX_FOVY: Deg<f32> = Deg(95.0);
+/// World units per second at which the field of view is the widest.
+const MAX_FOVY_SPEED: f32 = 6.0;
+/// How quickly the field of view follows the speed, per second.
+const FOVY_SHARPNESS: f32 = 4.0;
+/// Closer than this to its target, the field of view stops changing.
+const FOVY_EPSILON: Rad<f32> = Rad(0.0001);
+
+/// Fly-by cameras are placed ahead of the aircraft, off to the side and above its path.
+const FLY_BY_LEAD_DISTANCE: f32 = 15.0;
+const FLY_BY_SIDE_OFFSET: f32 = 4.0;
+const FLY_BY_HEIGHT: f32 = 1.5;
+/// Once the aircraft is this far, the camera is placed ahead of it again.
+const FLY_BY_MAX_DISTANCE: f32 = 20.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CameraMode {
+    /// Spring-damped follow from behind, looking ahead of the aircraft.
+    Chase,
+    /// Circles around the aircraft.
+    Orbit,
+    Cockpit,
+    /// Cinematic static shots the aircraft flies past.
+    FlyBy,
+    /// Debug camera, detached from the aircraft.
+    FreeFly,
+    /// Orthographic map view, north up.
+    TopDown,
+}
+
+const MODE_KEYS: [(VirtualKeyCode, CameraMode); 6] = [
+    (VirtualKeyCode::Key1, CameraMode::Chase),
+    (VirtualKeyCode::Key2, CameraMode::Orbit),
+    (VirtualKeyCode::Key3, CameraMode::Cockpit),
+    (VirtualKeyCode::Key4, CameraMode::FlyBy),
+    (VirtualKeyCode::Key5, CameraMode::FreeFly),
+    (VirtualKeyCode::Key6, CameraMode::TopDown),
+];
+
+/// Pauses the simulation while in the free-fly camera.
+const PAUSE_KEY: VirtualKeyCode = VirtualKeyCode::P;
+
+/// Drives the render camera from the player aircraft, in one of several modes.
+/// Modes are switched with the number keys, blending from the previous view.
+pub struct PlayerCameraController {
+    pub mode: CameraMode,
+
+    /// View when the mode was switched, and seconds since.
+    transition_position: Point3<f32>,
+    transition_rotation: Quaternion<f32>,
+    transition_time: f32,
+
+    /// `None` until the chase camera is placed behind the aircraft.
+    chase_position: Option<Point3<f32>>,
+    chase_velocity: Vector3<f32>,
+    chase_rotation: Quaternion<f32>,
+
+    orbit_angle: Rad<f32>,
+
+    fovy: Rad<f32>,
+
+    /// `None` until the fly-by camera is placed ahead of the aircraft.
+    fly_by_position: Option<Point3<f32>>,
+
+    free_fly_camera: FreeFlyCamera,
+    /// Only while in the free-fly camera, the simulation resumes when leaving it.
+    is_simulation_paused: bool,
+}
 
 impl PlayerCameraController {
     pub fn new() -> PlayerCameraController {
-        PlayerCameraController {}
+        PlayerCameraController {
+            mode: CameraMode::Chase,
+
+            transition_position: Point3::origin(),
+            transition_rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            transition_time: TRANSITION_DURATION,
+
+            chase_position: None,
+            chase_velocity: Vector3::zero(),
+            chase_rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+
+            orbit_angle: Rad(0.0),
+
+            fovy: Projection::DEFAULT_FOVY.into(),
+
+            fly_by_position: None,
+
+            free_fly_camera: FreeFlyCamera::new(),
+            is_simulation_paused: false,
+        }
+    }
+
+    /// Whether the keyboard drives the camera instead of the player aircraft.
+    pub fn is_free_fly(&self) -> bool {
+        self.mode == CameraMode::FreeFly
+    }
+
+    pub fn is_simulation_paused(&self) -> bool {
+        self.is_simulation_paused
+    }
+
+    /// Switches mode, blending from the current camera view.
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &Camera) {
+        if mode == self.mode {
+            return;
+        }
+
+        self.mode = mode;
+        self.transition_position = camera.position;
+        self.transition_rotation = camera.rotation;
+        self.transition_time = 0.0;
+
+        // Modes start over from the aircraft, the transition hides the cut
+        self.chase_position = None;
+        self.fly_by_position = None;
+        if mode == CameraMode::FreeFly {
+            self.free_fly_camera.set_from_camera(camera);
+        }
+        self.is_simulation_paused = false;
     }
 
     pub fn update(
         &mut self,
         camera: &mut Camera,
+        projection: &mut Projection,
         aircraft_mgr: &AircraftMgr,
         transform_mgr: &TransformMgr,
+        keyboard_mgr: &KeyboardMgr,
+        cursor_mgr: &CursorMgr,
+        dt: Duration,
     ) {
+        let dt = dt.as_secs_f32();
+
+        for (key, mode) in MODE_KEYS {
+            if keyboard_mgr.key_down[key as usize] {
+                self.set_mode(mode, camera);
+            }
+        }
+        if self.is_free_fly() && keyboard_mgr.key_down[PAUSE_KEY as usize] {
+            self.is_simulation_paused = !self.is_simulation_paused;
+        }
+
         let i = aircraft_mgr.get_player_aircraft_index();
-        // let i = 1;
         let transform_i = aircraft_mgr.transform_i[i].unwrap();
+        let velocity = aircraft_mgr.velocity[i];
+
+        let (position, rotation) = match self.mode {
+            CameraMode::Chase => self.update_chase(transform_mgr, transform_i, velocity, dt),
+            CameraMode::Orbit => self.update_orbit(transform_mgr, transform_i, dt),
+            CameraMode::Cockpit => {
+                let camera_transform_i = aircraft_mgr.camera_socket_i[i].unwrap_or(transform_i);
+                (
+                    transform_mgr.position(camera_transform_i),
+                    transform_mgr.rotation(camera_transform_i),
+                )
+            }
+            CameraMode::FlyBy => self.update_fly_by(transform_mgr, transform_i, velocity),
+            CameraMode::FreeFly => {
+                self.free_fly_camera.update(keyboard_mgr, cursor_mgr, dt);
+                (
+                    self.free_fly_camera.position,
+                    self.free_fly_camera.rotation(),
+                )
+            }
+            CameraMode::TopDown => (
+                transform_mgr.position(transform_i) + Vector3::unit_y() * TOP_DOWN_HEIGHT,
+                look_rotation(-Vector3::unit_y(), Vector3::unit_z()),
+            ),
+        };
 
-        camera.set_from_transform_mgr(transform_mgr, transform_i);
+        // Blends from the view of the previous mode
+        self.transition_time = f32::min(self.transition_time + dt, TRANSITION_DURATION);
+        let t = smoothstep(self.transition_time / TRANSITION_DURATION);
+        camera.position = self.transition_position + (position - self.transition_position) * t;
+        camera.rotation = self.transition_rotation.slerp(rotation, t);
+
+        self.update_projection(projection, velocity, t, dt);
+    }
+
+    fn update_projection(
+        &mut self,
+        projection: &mut Projection,
+        velocity: Vector3<f32>,
+        transition_t: f32,
+        dt: f32,
+    ) {
+        let base_fovy: Rad<f32> = Projection::DEFAULT_FOVY.into();
+        let max_fovy: Rad<f32> = MAX_FOVY.into();
+        let target_fovy = match self.mode {
+            CameraMode::Chase | CameraMode::Cockpit => {
+                let speed_t = f32::min(velocity.magnitude() / MAX_FOVY_SPEED, 1.0);
+                base_fovy + (max_fovy - base_fovy) * speed_t
+            }
+            _ => base_fovy,
+        };
+        // Leaves the projection alone once settled, so other code can still change it
+        if self.fovy != target_fovy {
+            let sharpness = 1.0 - f32::exp(-FOVY_SHARPNESS * dt);
+            self.fovy += (target_fovy - self.fovy) * sharpness;
+            if (target_fovy - self.fovy).0.abs() < FOVY_EPSILON.0 {
+                self.fovy = target_fovy;
+            }
+            projection.set_fovy(self.fovy);
+        }
+
+        // Cuts halfway through the transition, where the view moves the fastest
+        let mode = match self.mode {
+            CameraMode::TopDown => ProjectionMode::Orthographic,
+            _ => ProjectionMode::Perspective,
+        };
+        if transition_t >= 0.5 && projection.mode != mode {
+            projection.mode = mode;
+            if mode == ProjectionMode::Orthographic {
+                projection.set_ortho_height(TOP_DOWN_VIEW_HEIGHT);
+            }
+        }
     }
+
+    fn update_chase(
+        &mut self,
+        transform_mgr: &TransformMgr,
+        transform_i: usize,
+        velocity: Vector3<f32>,
+        dt: f32,
+    ) -> (Point3<f32>, Quaternion<f32>) {
+        let aircraft_position = transform_mgr.position(transform_i);
+        let target = aircraft_position - transform_mgr.forward(transform_i) * CHASE_DISTANCE
+            + Vector3::unit_y() * CHASE_HEIGHT;
+        let look_at = aircraft_position + velocity * CHASE_LOOK_AHEAD_TIME;
+
+        let position = match self.chase_position {
+            Some(position) if (position - target).magnitude() < CHASE_SNAP_DISTANCE => {
+                let position = smooth_damp(
+                    position,
+                    target,
+                    &mut self.chase_velocity,
+                    CHASE_SMOOTH_TIME,
+                    dt,
+                );
+                let rotation = look_rotation(look_at - position, Vector3::unit_y());
+                let sharpness = 1.0 - f32::exp(-CHASE_ROTATION_SHARPNESS * dt);
+                self.chase_rotation = self.chase_rotation.slerp(rotation, sharpness);
+                position
+            }
+            // First update, or the aircraft was reset
+            _ => {
+                self.chase_velocity = Vector3::zero();
+                self.chase_rotation = look_rotation(look_at - target, Vector3::unit_y());
+                target
+            }
+        };
+        self.chase_position = Some(position);
+
+        (position, self.chase_rotation)
+    }
+
+    fn update_orbit(
+        &mut self,
+        transform_mgr: &TransformMgr,
+        transform_i: usize,
+        dt: f32,
+    ) -> (Point3<f32>, Quaternion<f32>) {
+        self.orbit_angle += Rad(ORBIT_SPEED * dt);
+
+        let aircraft_position = transform_mgr.position(transform_i);
+        let offset = Vector3::new(
+            f32::sin(self.orbit_angle.0) * ORBIT_DISTANCE,
+            ORBIT_HEIGHT,
+            f32::cos(self.orbit_angle.0) * ORBIT_DISTANCE,
+        );
+        let position = aircraft_position + offset;
+
+        (position, look_rotation(-offset, Vector3::unit_y()))
+    }
+
+    fn update_fly_by(
+        &mut self,
+        transform_mgr: &TransformMgr,
+        transform_i: usize,
+        velocity: Vector3<f32>,
+    ) -> (Point3<f32>, Quaternion<f32>) {
+        let aircraft_position = transform_mgr.position(transform_i);
+
+        let position = match self.fly_by_position {
+            Some(position) if (position - aircraft_position).magnitude() < FLY_BY_MAX_DISTANCE => {
+                position
+            }
+            _ => {
+                // Ahead along the flight path, the forward axis when hovering
+                let direction = if velocity.magnitude2() > f32::EPSILON {
+                    velocity.normalize()
+                } else {
+                    transform_mgr.forward(transform_i)
+                };
+                let side = direction.cross(Vector3::unit_y());
+                let side = if side.magnitude2() > f32::EPSILON {
+                    side.normalize()
+                } else {
+                    Vector3::unit_x()
+                };
+
+                aircraft_position
+                    + direction * FLY_BY_LEAD_DISTANCE
+                    + side * FLY_BY_SIDE_OFFSET
+                    + Vector3::unit_y() * FLY_BY_HEIGHT
+            }
+        };
+        self.fly_by_position = Some(position);
+
+        (
+            position,
+            look_rotation(aircraft_position - position, Vector3::unit_y()),
+        )
+    }
+
+    pub fn ui(&self, context: &egui::Context) {
+        let mode_str = format!("Camera: {:?} (1-6 to switch)", self.mode);
+        let free_fly_str = match self.is_simulation_paused {
+            true => "Paused (P to resume)",
+            false => "WASD, QE and mouse to fly, shift and control for speed, P to pause",
+        };
+
+        egui::TopBottomPanel::bottom("Camera")
+            .resizable(false)
+            .show(context, |ui| {
+                ui.label(mode_str);
+                if self.is_free_fly() {
+                    ui.label(free_fly_str);
+                }
+            });
+    }
+}
+
+/// Rotation whose forward (+z) axis points along `forward`, with its up axis towards `up`.
+fn look_rotation(forward: Vector3<f32>, up: Vector3<f32>) -> Quaternion<f32> {
+    let forward = forward.normalize();
+    let right = up.cross(forward);
+    // Looking straight up or down
+    let right = if right.magnitude2() > f32::EPSILON {
+        right.normalize()
+    } else {
+        Vector3::unit_x()
+    };
+    let up = forward.cross(right);
+
+    Quaternion::from(Matrix3::from_cols(right, up, forward))
+}
+
+/// Critically damped spring towards `target`, stable for any `dt`.
+/// `velocity` carries over between updates.
+fn smooth_damp(
+    current: Point3<f32>,
+    target: Point3<f32>,
+    velocity: &mut Vector3<f32>,
+    smooth_time: f32,
+    dt: f32,
+) -> Point3<f32> {
+    let omega = 2.0 / smooth_time;
+    let x = omega * dt;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+
+    let change = current - target;
+    let temp = (*velocity + change * omega) * dt;
+    *velocity = (*velocity - temp * omega) * decay;
+
+    target + (change + temp) * decay
+}
+
+fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
 }
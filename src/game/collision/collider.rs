@@ -1,11 +1,17 @@
+use std::sync::Arc;
+
 use anyhow::Result;
-use cgmath::{EuclideanSpace, Point3};
+use cgmath::{ElementWise, EuclideanSpace, Point3, Vector3};
 use wgpu::util::DeviceExt;
 
 use crate::{
-    game::{mesh_renderer::MeshInstancedRendererMgr, model::ModelMgr, transform::TransformMgr},
+    assets::{asset_cache::Handle, asset_manager::AssetMgr},
+    game::{
+        mesh_renderer::MeshInstancedRendererMgr, model::ModelMgr,
+        terrain::heightfield::Heightfield, transform::TransformMgr,
+    },
     renderer::{
-        model::{self, ModelVertex},
+        model::{self, Model, ModelVertex},
         render_state::{self, RenderState},
     },
 };
@@ -32,8 +38,8 @@ pub struct ColliderMgr {
 
     // References
     transform_i: Vec<usize>,
-    model_i: Vec<Option<usize>>,
-    bounding_box_model_i: Vec<usize>,
+    model_i: Vec<Option<Handle<Model>>>,
+    bounding_box_model_i: Vec<Handle<Model>>,
     bounding_box_mesh_renderer_i: Vec<usize>,
 }
 
@@ -61,7 +67,7 @@ impl ColliderMgr {
 
     pub async fn add_from_model(
         &mut self,
-        model_i: usize,
+        model_i: Handle<Model>,
         transform_i: usize,
         collider_type: ColliderType,
         is_collision_source: bool,
@@ -70,9 +76,10 @@ impl ColliderMgr {
         render_state: &RenderState,
         transform_mgr: &TransformMgr,
         model_mgr: &mut ModelMgr,
+        asset_mgr: &mut AssetMgr,
         mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
     ) -> Result<usize> {
-        let model = &model_mgr.model[model_i];
+        let model = model_mgr.get(model_i);
 
         let bbox_min = Point3 {
             x: model.min_x,
@@ -85,6 +92,42 @@ impl ColliderMgr {
             z: model.max_z,
         };
 
+        self.add_from_bounds(
+            bbox_min,
+            bbox_max,
+            Some(model_i),
+            transform_i,
+            collider_type,
+            is_collision_source,
+            is_collision_target,
+            render_bounding_box,
+            render_state,
+            transform_mgr,
+            model_mgr,
+            asset_mgr,
+            mesh_renderer_mgr,
+        )
+        .await
+    }
+
+    /// Adds a collider with an explicit bounding box, for colliders without a loaded model.
+    /// `ColliderType::Vertex` colliders need a model.
+    pub async fn add_from_bounds(
+        &mut self,
+        bbox_min: Point3<f32>,
+        bbox_max: Point3<f32>,
+        model_i: Option<Handle<Model>>,
+        transform_i: usize,
+        collider_type: ColliderType,
+        is_collision_source: bool,
+        is_collision_target: bool,
+        render_bounding_box: bool,
+        render_state: &RenderState,
+        transform_mgr: &TransformMgr,
+        model_mgr: &mut ModelMgr,
+        asset_mgr: &mut AssetMgr,
+        mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
+    ) -> Result<usize> {
         self.bounding_box_min.push(bbox_min);
         self.bounding_box_max.push(bbox_max);
 
@@ -98,19 +141,35 @@ impl ColliderMgr {
         self.colliding_indices.push([-1; MAX_COLLISIONS]);
 
         self.transform_i.push(transform_i);
-        self.model_i.push(Some(model_i));
+        self.model_i.push(model_i);
 
-        let position = transform_mgr.position[transform_i];
-        let rotation = transform_mgr.rotation[transform_i];
+        let position = transform_mgr.position(transform_i);
+        let rotation = transform_mgr.rotation(transform_i);
+        let scale = transform_mgr.scale(transform_i);
         let bbox_model = self
-            .create_bounding_box_model(&bbox_min, &bbox_max, &render_state, mesh_renderer_mgr)
+            .create_bounding_box_model(
+                &bbox_min,
+                &bbox_max,
+                asset_mgr,
+                &render_state,
+                mesh_renderer_mgr,
+            )
             .await;
         let bbox_model_i = model_mgr.add(
             bbox_model,
             format!("Collider bbox {}", self.len() - 1).as_str(),
         );
-        let bbox_mesh_renderer_i =
-            mesh_renderer_mgr.add(&render_state, bbox_model_i, position.to_vec(), rotation);
+        let bbox_mesh_renderer_i = mesh_renderer_mgr.add(
+            &render_state,
+            model_mgr,
+            bbox_model_i,
+            position.to_vec(),
+            rotation,
+            scale,
+            model::InstanceRaw::NO_TINT,
+            model::InstanceRaw::NO_EMISSIVE,
+            false,
+        );
         self.bounding_box_model_i.push(bbox_model_i);
         self.bounding_box_mesh_renderer_i.push(bbox_mesh_renderer_i);
 
@@ -141,10 +200,11 @@ impl ColliderMgr {
         model_mgr: &ModelMgr,
     ) -> [isize; MAX_COLLISIONS] {
         let transform_i = self.transform_i[index];
-        let position = transform_mgr.position[transform_i];
+        let position = transform_mgr.position(transform_i);
+        let scale = transform_mgr.scale(transform_i);
 
-        let min_pos = self.get_translated_min_pos(index, position);
-        let max_pos = self.get_translated_max_pos(index, position);
+        let min_pos = self.get_translated_min_pos(index, position, scale);
+        let max_pos = self.get_translated_max_pos(index, position, scale);
 
         // OPTIMIZE: check the other instance for an already ocurring collision with this instance.
 
@@ -159,9 +219,12 @@ impl ColliderMgr {
             }
 
             let other_transform_i = self.transform_i[other_index];
-            let other_position = transform_mgr.position[other_transform_i];
-            let other_min_pos = self.get_translated_min_pos(other_index, other_position);
-            let other_max_pos = self.get_translated_max_pos(other_index, other_position);
+            let other_position = transform_mgr.position(other_transform_i);
+            let other_scale = transform_mgr.scale(other_transform_i);
+            let other_min_pos =
+                self.get_translated_min_pos(other_index, other_position, other_scale);
+            let other_max_pos =
+                self.get_translated_max_pos(other_index, other_position, other_scale);
 
             // Box collision check
             if min_pos.x <= other_max_pos.x
@@ -181,7 +244,25 @@ impl ColliderMgr {
                     (ColliderType::Vertex, ColliderType::Box) => {
                         self.check_collision_box_mesh(other_index, index, transform_mgr, model_mgr)
                     }
+                    (ColliderType::Box, ColliderType::Heightfield(heightfield)) => self
+                        .check_collision_box_heightfield(
+                            index,
+                            other_index,
+                            heightfield,
+                            transform_mgr,
+                        ),
+                    (ColliderType::Heightfield(heightfield), ColliderType::Box) => self
+                        .check_collision_box_heightfield(
+                            other_index,
+                            index,
+                            heightfield,
+                            transform_mgr,
+                        ),
                     (ColliderType::Vertex, ColliderType::Vertex) => todo!(),
+                    // Static terrain doesn't collide with other static terrain.
+                    (ColliderType::Vertex, ColliderType::Heightfield(_))
+                    | (ColliderType::Heightfield(_), ColliderType::Vertex)
+                    | (ColliderType::Heightfield(_), ColliderType::Heightfield(_)) => false,
                 };
                 if is_colliding {
                     collisions[collisions_found] = other_index as isize;
@@ -207,14 +288,15 @@ impl ColliderMgr {
         model_mgr: &ModelMgr,
     ) -> bool {
         let transform_i = self.transform_i[index];
-        let position = transform_mgr.position[transform_i];
+        let position = transform_mgr.position(transform_i);
+        let scale = transform_mgr.scale(transform_i);
 
         // OPTIMIZE: these are already calculated in calling function.
-        let min_pos = self.get_translated_min_pos(index, position);
-        let max_pos = self.get_translated_max_pos(index, position);
+        let min_pos = self.get_translated_min_pos(index, position, scale);
+        let max_pos = self.get_translated_max_pos(index, position, scale);
 
         let other_model_i = self.model_i[other_index].unwrap();
-        let other_model = &model_mgr.model[other_model_i];
+        let other_model = model_mgr.get(other_model_i);
 
         let mut checks = 0;
         for mesh in (other_model.meshes).iter() {
@@ -238,20 +320,73 @@ impl ColliderMgr {
         false
     }
 
+    /// index: box
+    /// other_index: heightfield
+    fn check_collision_box_heightfield(
+        &self,
+        index: usize,
+        other_index: usize,
+        heightfield: &Heightfield,
+        transform_mgr: &TransformMgr,
+    ) -> bool {
+        let position = transform_mgr.position(self.transform_i[index]);
+        let scale = transform_mgr.scale(self.transform_i[index]);
+        let other_position = transform_mgr.position(self.transform_i[other_index]);
+
+        // Heightfield local space, heightfields aren't scaled
+        let min_pos = self.get_translated_min_pos(index, position, scale) - other_position.to_vec();
+        let max_pos = self.get_translated_max_pos(index, position, scale) - other_position.to_vec();
+
+        match heightfield.max_height_in_area(min_pos.x, min_pos.z, max_pos.x, max_pos.z) {
+            Some(ground_height) => min_pos.y <= ground_height,
+            None => false,
+        }
+    }
+
     // fn check_collision_mesh_mesh(&self, index: usize, other_index: usize) -> bool {
     //     false
     // }
 
-    /// Gets a translated minimum position, the [reference_position] argument is usually the transform's position.
-    fn get_translated_min_pos(&self, index: usize, reference_position: Point3<f32>) -> Point3<f32> {
-        let bounding_box_min = self.bounding_box_min[index];
-        self.get_translated_position(reference_position, bounding_box_min)
+    /// Gets a scaled and translated minimum position, the [reference_position] and [scale]
+    /// arguments are usually the transform's.
+    fn get_translated_min_pos(
+        &self,
+        index: usize,
+        reference_position: Point3<f32>,
+        scale: Vector3<f32>,
+    ) -> Point3<f32> {
+        let (bounding_box_min, bounding_box_max) = self.get_scaled_bounds(index, scale);
+        let offset = Point3 {
+            x: f32::min(bounding_box_min.x, bounding_box_max.x),
+            y: f32::min(bounding_box_min.y, bounding_box_max.y),
+            z: f32::min(bounding_box_min.z, bounding_box_max.z),
+        };
+        self.get_translated_position(reference_position, offset)
+    }
+
+    /// Gets a scaled and translated maximum position, the [reference_position] and [scale]
+    /// arguments are usually the transform's.
+    fn get_translated_max_pos(
+        &self,
+        index: usize,
+        reference_position: Point3<f32>,
+        scale: Vector3<f32>,
+    ) -> Point3<f32> {
+        let (bounding_box_min, bounding_box_max) = self.get_scaled_bounds(index, scale);
+        let offset = Point3 {
+            x: f32::max(bounding_box_min.x, bounding_box_max.x),
+            y: f32::max(bounding_box_min.y, bounding_box_max.y),
+            z: f32::max(bounding_box_min.z, bounding_box_max.z),
+        };
+        self.get_translated_position(reference_position, offset)
     }
 
-    /// Gets a translated maximum position, the [reference_position] argument is usually the transform's position.
-    fn get_translated_max_pos(&self, index: usize, reference_position: Point3<f32>) -> Point3<f32> {
-        let bounding_box_max = self.bounding_box_max[index];
-        self.get_translated_position(reference_position, bounding_box_max)
+    /// Bounding box corners scaled per axis. Negative scales swap the corners on that axis.
+    fn get_scaled_bounds(&self, index: usize, scale: Vector3<f32>) -> (Point3<f32>, Point3<f32>) {
+        (
+            self.bounding_box_min[index].mul_element_wise(Point3::from_vec(scale)),
+            self.bounding_box_max[index].mul_element_wise(Point3::from_vec(scale)),
+        )
     }
 
     fn get_translated_position(
@@ -274,6 +409,7 @@ impl ColliderMgr {
         &self,
         bbox_min: &Point3<f32>,
         bbox_max: &Point3<f32>,
+        asset_mgr: &mut AssetMgr,
         render_state: &RenderState,
         mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
     ) -> model::Model {
@@ -340,6 +476,7 @@ impl ColliderMgr {
 
         model::Model::new_from_single_mesh(
             mesh,
+            asset_mgr,
             render_state,
             &mesh_renderer_mgr.texture_bind_group_layout,
         )
@@ -387,5 +524,9 @@ pub enum ColliderType {
     /// Bounding box collision check.
     Box,
     /// Per-vertex collision check.
+    /// Terrains were the only mesh colliders, until they became heightfields.
+    #[allow(dead_code)]
     Vertex,
+    /// Ground height collision check against a terrain heightfield.
+    Heightfield(Arc<Heightfield>),
 }
@@ -1,22 +1,30 @@
 use wgpu::util::DeviceExt;
 
-use crate::renderer::{
-    render_state::{create_render_pipeline, RenderState},
-    texture,
-    vertex::Vertex,
+use crate::{
+    assets::asset_cache::Handle,
+    renderer::{
+        render_state::{create_render_pipeline_from_source, RenderState},
+        texture,
+        vertex::Vertex,
+    },
+    resources::ShaderSource,
 };
 
 use super::axis_vertex::{AxisVertex, AXIS_VERTICES};
 
+const SHADER_FILE: &str = "shaders/axis.wgsl";
+
 /// Renders coordinates axis.
 // TODO: support multiple instances. This may need a `position: Vec<Point3<f32>>`
 pub struct AxisRendererMgr {
     vertex_buffer: wgpu::Buffer,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
+    shader: Handle<ShaderSource>,
 }
 
 impl AxisRendererMgr {
-    pub fn new(render_state: &RenderState) -> Self {
+    pub fn new(render_state: &mut RenderState) -> Self {
         let vertex_buffer =
             render_state
                 .device
@@ -40,30 +48,54 @@ impl AxisRendererMgr {
                     push_constant_ranges: &[],
                 });
 
-        let render_pipeline = {
-            let shader_module_descriptor = wgpu::ShaderModuleDescriptor {
-                label: Some("Axis shader"),
-                source: wgpu::ShaderSource::Wgsl(
-                    include_str!("../../renderer/shaders/axis.wgsl").into(), // TODO: load shaders as resource
-                ),
-            };
-            create_render_pipeline(
-                &render_state.device,
-                &render_pipeline_layout,
-                render_state.config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[AxisVertex::desc()],
-                shader_module_descriptor,
-                None,
-            )
-        };
+        let shader = render_state.shader_mgr.load(SHADER_FILE).unwrap();
+        let render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
 
         Self {
             vertex_buffer,
+            render_pipeline_layout,
             render_pipeline,
+            shader,
         }
     }
 
+    fn create_pipeline(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        create_render_pipeline_from_source(
+            device,
+            render_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+            Some(texture::Texture::DEPTH_FORMAT),
+            true,
+            sample_count,
+            &[AxisVertex::desc()],
+            shader,
+            None,
+        )
+    }
+
+    /// Rebuilds the render pipeline if its shader was reloaded.
+    pub fn reload_shaders(&mut self, render_state: &mut RenderState) {
+        let device = &render_state.device;
+        let sample_count = render_state.sample_count;
+        render_state
+            .shader_mgr
+            .reload_pipeline(self.shader, &mut self.render_pipeline, |shader| {
+                Self::create_pipeline(device, &self.render_pipeline_layout, sample_count, shader)
+            });
+    }
+
     pub fn render(
         &mut self,
         render_state: &RenderState,
@@ -72,14 +104,9 @@ impl AxisRendererMgr {
     ) -> anyhow::Result<(), wgpu::SurfaceError> {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Axis render pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
+            color_attachments: &[Some(
+                render_state.color_attachment(view, wgpu::LoadOp::Load),
+            )],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &render_state.depth_texture.view,
                 depth_ops: Some(wgpu::Operations {
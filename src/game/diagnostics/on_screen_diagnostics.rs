@@ -4,6 +4,9 @@ pub struct OnScreenDiagnostics {
     frame_time: f32,
     fps: f32,
 
+    drawn_count: usize,
+    culled_count: usize,
+
     update_period: f32,
     update_timer: f32,
 }
@@ -13,6 +16,8 @@ impl OnScreenDiagnostics {
         Self {
             frame_time: 0.0,
             fps: 0.0,
+            drawn_count: 0,
+            culled_count: 0,
             update_period,
             update_timer: update_period,
         }
@@ -31,10 +36,18 @@ impl OnScreenDiagnostics {
         }
     }
 
+    /// Mesh instances drawn and frustum culled in the last frame.
+    pub fn set_render_counts(&mut self, drawn_count: usize, culled_count: usize) {
+        self.drawn_count = drawn_count;
+        self.culled_count = culled_count;
+    }
+
     pub fn ui(&self, context: &egui::Context) {
         let title_str = format!("Diagnostics (every {}s):", self.update_period);
         let frame_time_str = format!("FT: {}ms", self.frame_time);
         let fps_str = format!("FPS: {}", self.fps);
+        let drawn_str = format!("Drawn: {}", self.drawn_count);
+        let culled_str = format!("Culled: {}", self.culled_count);
 
         egui::SidePanel::right("right_panel")
             .resizable(false)
@@ -44,6 +57,8 @@ impl OnScreenDiagnostics {
                 ui.label("----------------------");
                 ui.label(frame_time_str);
                 ui.label(fps_str);
+                ui.label(drawn_str);
+                ui.label(culled_str);
             });
     }
 }
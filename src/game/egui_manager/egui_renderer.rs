@@ -17,7 +17,7 @@ impl EguiRenderer {
             &render_state.device,
             render_state.config.format,
             Some(texture::Texture::DEPTH_FORMAT),
-            1,
+            render_state.sample_count,
         );
 
         let context = egui::Context::default();
@@ -25,7 +25,7 @@ impl EguiRenderer {
         let platform = egui_winit::State::new(
             context.viewport_id(),
             event_loop,
-            Some(pixels_per_point(&context, &render_state.window)),
+            Some(pixels_per_point(&context, render_state.window())),
             Some(1024),
         );
 
@@ -68,12 +68,12 @@ impl EguiRenderer {
             let full_output = self.full_output.as_ref().unwrap();
 
             self.platform.handle_platform_output(
-                &render_state.window,
+                render_state.window(),
                 &self.context,
                 full_output.platform_output.clone(),
             );
 
-            let pixels_per_point = pixels_per_point(&self.context, &render_state.window);
+            let pixels_per_point = pixels_per_point(&self.context, render_state.window());
 
             let paint_jobs = self
                 .context
@@ -103,14 +103,9 @@ impl EguiRenderer {
 
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("egui render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
+                color_attachments: &[Some(
+                    render_state.surface_color_attachment(view, wgpu::LoadOp::Load),
+                )],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &render_state.depth_texture.view,
 
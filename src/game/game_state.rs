@@ -3,9 +3,11 @@ use std::time::Duration;
 use winit::{event::Event, event_loop::EventLoop, window::Window};
 
 use crate::{
+    assets::{asset_loader::AssetLoader, asset_manager::AssetMgr},
     audio::audio_manager::AudioMgr,
     input::{cursor_manager::CursorMgr, keyboard_manager::KeyboardMgr},
     renderer::render_state::RenderState,
+    resources::AssetWatcher,
 };
 
 use super::{
@@ -20,7 +22,10 @@ use super::{
     inventory::InventoryMgr,
     mesh_renderer::MeshInstancedRendererMgr,
     model::ModelMgr,
+    particles::{particle_emitter::ParticleEmitterMgr, particle_renderer::ParticleRendererMgr},
     sample_scene,
+    terrain::{terrain::Terrain, terrain_renderer::TerrainRendererMgr},
+    trails::{ribbon_trail::RibbonTrailMgr, trail_renderer::TrailRendererMgr},
     transform::TransformMgr,
 };
 
@@ -40,19 +45,35 @@ pub struct GameState {
     building_mgr: BuildingMgr,
     inventory_mgr: InventoryMgr,
 
+    /// `None` while the scene assets are loading.
+    terrain: Option<Terrain>,
+
     model_mgr: ModelMgr,
+    asset_mgr: AssetMgr,
+    /// Background loads of the scene assets.
+    /// Kept, with its loading screen, until the terrain around the camera is streamed in.
+    asset_loader: Option<AssetLoader>,
 
     egui_renderer: EguiRenderer,
     on_screen_diagnostics: OnScreenDiagnostics,
+    asset_watcher: AssetWatcher,
     mesh_instanced_renderer_mgr: MeshInstancedRendererMgr,
+    terrain_renderer_mgr: TerrainRendererMgr,
+    particle_emitter_mgr: ParticleEmitterMgr,
+    particle_renderer_mgr: ParticleRendererMgr,
+    ribbon_trail_mgr: RibbonTrailMgr,
+    trail_renderer_mgr: TrailRendererMgr,
     audio_mgr: AudioMgr,
 
-    audio_test: AudioTest,
+    audio_test: Option<AudioTest>,
 }
 
+const ASSET_LOADER_WORKER_COUNT: usize = 4;
+
 impl GameState {
-    pub async fn new<T>(event_loop: &EventLoop<T>, render_state: &mut RenderState) -> GameState {
-        let cursor_mgr = CursorMgr::new(&mut render_state.window);
+    /// The scene is created once its assets are loaded, with a loading screen in the meantime.
+    pub fn new<T>(event_loop: &EventLoop<T>, render_state: &mut RenderState) -> GameState {
+        let cursor_mgr = CursorMgr::new(render_state.window.as_mut().unwrap());
         let keyboard_mgr = KeyboardMgr::new();
 
         let player_camera = PlayerCameraController::new();
@@ -60,33 +81,18 @@ impl GameState {
 
         let egui_renderer = EguiRenderer::new(event_loop, render_state);
         let on_screen_diagnostics = OnScreenDiagnostics::new(0.1);
-        let mut mesh_instanced_renderer_mgr = MeshInstancedRendererMgr::new(render_state);
+        let asset_watcher = AssetWatcher::new();
+        let mesh_instanced_renderer_mgr = MeshInstancedRendererMgr::new(render_state);
+        let terrain_renderer_mgr = TerrainRendererMgr::new(render_state);
+        let particle_renderer_mgr = ParticleRendererMgr::new(render_state);
+        let trail_renderer_mgr = TrailRendererMgr::new(render_state);
         let audio_mgr = AudioMgr::new();
 
-        let audio_test = AudioTest::new().await;
-
-        let mut transform_mgr = TransformMgr::new();
-        let mut collider_mgr = ColliderMgr::new();
-
-        let mut aircraft_mgr = AircraftMgr::new().unwrap();
-        let mut aircraft_input_mgr = AircraftInputMgr::new();
-        let mut building_mgr = BuildingMgr::new();
-        let mut inventory_mgr = InventoryMgr::new();
+        let asset_mgr = AssetMgr::new();
+        let model_mgr = ModelMgr::new();
 
-        let mut model_mgr = ModelMgr::new();
-
-        sample_scene::create(
-            &mut aircraft_mgr,
-            &mut transform_mgr,
-            &mut collider_mgr,
-            &mut aircraft_input_mgr,
-            &mut building_mgr,
-            &mut inventory_mgr,
-            &mut model_mgr,
-            render_state,
-            &mut mesh_instanced_renderer_mgr,
-        )
-        .await;
+        let mut asset_loader = AssetLoader::new(ASSET_LOADER_WORKER_COUNT);
+        sample_scene::preload(&mut asset_loader, &asset_mgr, &model_mgr);
 
         Self {
             cursor_mgr,
@@ -95,25 +101,60 @@ impl GameState {
             player_camera,
             axis_renderer_mgr,
 
-            transform_mgr,
-            collider_mgr,
+            transform_mgr: TransformMgr::new(),
+            collider_mgr: ColliderMgr::new(),
 
-            aircraft_mgr,
-            aircraft_input_mgr,
-            building_mgr,
-            inventory_mgr,
+            aircraft_mgr: AircraftMgr::new().unwrap(),
+            aircraft_input_mgr: AircraftInputMgr::new(),
+            building_mgr: BuildingMgr::new(),
+            inventory_mgr: InventoryMgr::new(),
+
+            terrain: None,
 
             model_mgr,
+            asset_mgr,
+            asset_loader: Some(asset_loader),
 
             egui_renderer,
             on_screen_diagnostics,
+            asset_watcher,
             mesh_instanced_renderer_mgr,
+            terrain_renderer_mgr,
+            particle_emitter_mgr: ParticleEmitterMgr::new(),
+            particle_renderer_mgr,
+            ribbon_trail_mgr: RibbonTrailMgr::new(),
+            trail_renderer_mgr,
             audio_mgr,
 
-            audio_test,
+            audio_test: None,
         }
     }
 
+    /// Creates the sample scene, from the assets loaded by the asset loader.
+    fn create_scene(&mut self, render_state: &mut RenderState) {
+        let terrain = pollster::block_on(sample_scene::create(
+            &mut self.aircraft_mgr,
+            &mut self.transform_mgr,
+            &mut self.collider_mgr,
+            &mut self.aircraft_input_mgr,
+            &mut self.building_mgr,
+            &mut self.inventory_mgr,
+            &mut self.model_mgr,
+            &mut self.asset_mgr,
+            render_state,
+            &mut self.mesh_instanced_renderer_mgr,
+            &mut self.terrain_renderer_mgr,
+            &mut self.particle_emitter_mgr,
+            &mut self.ribbon_trail_mgr,
+        ));
+
+        // Draw distance follows the streamed terrain
+        render_state.projection.set_zfar(terrain.view_distance());
+
+        self.terrain = Some(terrain);
+        self.audio_test = Some(pollster::block_on(AudioTest::new(&mut self.asset_mgr)));
+    }
+
     /// Handle component inputs
     /// Returs `true` if any event has been processed.
     pub fn input<T>(&mut self, event: &Event<T>, window: &Window) {
@@ -121,18 +162,78 @@ impl GameState {
         self.keyboard_mgr.input(event, window);
 
         self.egui_renderer.input(event, window);
-        self.audio_test.input(event, window);
+        if let Some(audio_test) = &mut self.audio_test {
+            audio_test.input(event, window);
+        }
     }
 
     /// Handle component updates
     pub fn update(&mut self, render_state: &mut RenderState, dt: Duration) {
-        self.aircraft_input_mgr.update(&self.keyboard_mgr);
+        // Asset hot reload
+        self.asset_watcher.update();
+        render_state.shader_mgr.update(&self.asset_watcher);
+        self.mesh_instanced_renderer_mgr
+            .reload_shaders(render_state);
+        self.terrain_renderer_mgr.reload_shaders(render_state);
+        self.axis_renderer_mgr.reload_shaders(render_state);
+        self.particle_renderer_mgr.reload_shaders(render_state);
+        self.trail_renderer_mgr.reload_shaders(render_state);
+        render_state
+            .post_process
+            .reload_shaders(&mut render_state.shader_mgr, &render_state.device);
+        let reloaded_textures = self.asset_mgr.reload_changed(
+            &self.asset_watcher,
+            &render_state.device,
+            &render_state.queue,
+        );
+        let reloaded_models = self.model_mgr.reload_changed(
+            &self.asset_watcher,
+            &mut self.asset_mgr,
+            render_state,
+            &self.mesh_instanced_renderer_mgr,
+        );
+        for model_i in reloaded_models.iter() {
+            self.mesh_instanced_renderer_mgr
+                .update_model_bounds(&self.model_mgr, *model_i);
+        }
+        self.terrain_renderer_mgr.reload_textures(
+            &reloaded_textures,
+            &self.asset_mgr,
+            render_state,
+        );
+        // Reloaded models may have released their previous textures
+        if !reloaded_models.is_empty() {
+            self.model_mgr.unload_unused(&mut self.asset_mgr);
+            self.asset_mgr.unload_unused();
+        }
+
+        if self.asset_loader.is_some() {
+            self.update_loading(render_state);
+
+            // Key presses and mouse motion while loading would otherwise reach the scene at once
+            self.keyboard_mgr.end_frame();
+            self.cursor_mgr.end_frame();
+            return;
+        }
+
+        // The free-fly camera takes over the keyboard, and may pause the simulation
+        if !self.player_camera.is_free_fly() {
+            self.aircraft_input_mgr.update(&self.keyboard_mgr);
+        }
+        let simulation_dt = match self.player_camera.is_simulation_paused() {
+            true => Duration::ZERO,
+            false => dt,
+        };
         self.aircraft_mgr.update(
             &mut self.transform_mgr,
             &mut self.aircraft_input_mgr,
+            &self.collider_mgr,
             &mut self.mesh_instanced_renderer_mgr,
+            &mut self.particle_emitter_mgr,
+            &mut self.ribbon_trail_mgr,
+            &self.model_mgr,
             render_state,
-            dt,
+            simulation_dt,
         );
 
         self.transform_mgr.update();
@@ -141,23 +242,111 @@ impl GameState {
 
         self.player_camera.update(
             &mut render_state.camera,
+            &mut render_state.projection,
             &self.aircraft_mgr,
             &self.transform_mgr,
+            &self.keyboard_mgr,
+            &self.cursor_mgr,
+            dt,
+        );
+        self.cursor_mgr
+            .set_grabbed(render_state.window(), self.player_camera.is_free_fly());
+        self.terrain.as_mut().unwrap().update(
+            &render_state.camera,
+            &self.transform_mgr,
+            &mut self.terrain_renderer_mgr,
+            render_state,
         );
-        self.audio_test.update(&mut self.audio_mgr);
+        self.particle_emitter_mgr
+            .update(&self.transform_mgr, simulation_dt);
+        self.particle_renderer_mgr
+            .update(&self.particle_emitter_mgr, render_state);
+        self.ribbon_trail_mgr
+            .update(&self.transform_mgr, simulation_dt);
+        self.trail_renderer_mgr
+            .update(&self.ribbon_trail_mgr, render_state);
+        self.audio_test
+            .as_mut()
+            .unwrap()
+            .update(&mut self.audio_mgr, &self.asset_mgr);
         self.on_screen_diagnostics.update(dt);
+
+        self.keyboard_mgr.end_frame();
+        self.cursor_mgr.end_frame();
     }
 
-    /// Handle component UI layout
-    pub fn ui(&mut self, render_state: &mut RenderState) {
-        self.egui_renderer.ui_begin_frame(&render_state.window);
+    /// Loads the scene assets, then creates the scene and streams in the terrain around the camera.
+    fn update_loading(&mut self, render_state: &mut RenderState) {
+        if self.terrain.is_none() {
+            let asset_loader = self.asset_loader.as_mut().unwrap();
+            asset_loader.update(
+                &mut self.asset_mgr,
+                &mut self.model_mgr,
+                render_state,
+                &self.mesh_instanced_renderer_mgr,
+            );
+            if !asset_loader.is_finished() {
+                return;
+            }
+
+            self.create_scene(render_state);
+
+            // Assets the scene didn't use are dropped
+            self.asset_loader
+                .as_mut()
+                .unwrap()
+                .release(&mut self.asset_mgr, &mut self.model_mgr);
+            self.model_mgr.unload_unused(&mut self.asset_mgr);
+            self.asset_mgr.unload_unused();
+
+            // Places the camera, so the first chunks are the ones around the player
+            self.transform_mgr.update();
+            self.player_camera.update(
+                &mut render_state.camera,
+                &mut render_state.projection,
+                &self.aircraft_mgr,
+                &self.transform_mgr,
+                &self.keyboard_mgr,
+                &self.cursor_mgr,
+                Duration::ZERO,
+            );
+        }
 
-        self.aircraft_mgr.ui(
+        let terrain = self.terrain.as_mut().unwrap();
+        terrain.update(
+            &render_state.camera,
             &self.transform_mgr,
-            &self.collider_mgr,
-            &self.egui_renderer.context,
+            &mut self.terrain_renderer_mgr,
+            render_state,
         );
-        self.on_screen_diagnostics.ui(&self.egui_renderer.context);
+        if terrain.is_streaming_idle() {
+            self.asset_loader = None;
+        }
+    }
+
+    /// Handle component UI layout
+    pub fn ui(&mut self, render_state: &mut RenderState) {
+        self.egui_renderer.ui_begin_frame(render_state.window());
+
+        match &self.asset_loader {
+            Some(asset_loader) => asset_loader.ui(&self.egui_renderer.context),
+            None => {
+                self.aircraft_mgr.ui(
+                    self.terrain.as_ref().unwrap(),
+                    &self.transform_mgr,
+                    &self.collider_mgr,
+                    &self.egui_renderer.context,
+                );
+                self.on_screen_diagnostics.set_render_counts(
+                    self.mesh_instanced_renderer_mgr.drawn_count,
+                    self.mesh_instanced_renderer_mgr.culled_count,
+                );
+                self.on_screen_diagnostics.ui(&self.egui_renderer.context);
+                self.player_camera.ui(&self.egui_renderer.context);
+                render_state.post_process.ui(&self.egui_renderer.context);
+            }
+        }
+        render_state.shader_mgr.ui(&self.egui_renderer.context);
 
         self.egui_renderer.ui_end_frame();
     }
@@ -169,15 +358,40 @@ impl GameState {
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
     ) {
+        // Scene renders into the HDR texture
+        let hdr_view = &render_state.post_process.hdr_texture.view;
+
         self.mesh_instanced_renderer_mgr
-            .render(&self.model_mgr, render_state, encoder, view)
+            .render(&self.model_mgr, render_state, encoder, hdr_view)
             .unwrap();
 
-        self.egui_renderer
-            .render(render_state, encoder, view)
+        self.terrain_renderer_mgr
+            .render(&self.model_mgr, render_state, encoder, hdr_view)
+            .unwrap();
+
+        self.mesh_instanced_renderer_mgr
+            .render_transparent(&self.model_mgr, render_state, encoder, hdr_view)
+            .unwrap();
+
+        self.trail_renderer_mgr
+            .render(render_state, encoder, hdr_view)
+            .unwrap();
+
+        self.particle_renderer_mgr
+            .render(render_state, encoder, hdr_view)
             .unwrap();
 
         self.axis_renderer_mgr
+            .render(render_state, encoder, hdr_view)
+            .unwrap();
+
+        render_state.post_process.render(
+            &render_state.queue,
+            encoder,
+            render_state.surface_color_attachment(view, wgpu::LoadOp::Clear(wgpu::Color::BLACK)),
+        );
+
+        self.egui_renderer
             .render(render_state, encoder, view)
             .unwrap();
     }
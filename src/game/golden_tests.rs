@@ -0,0 +1,426 @@
+//! Golden image rendering tests.
+//! Canned scenes are rendered offscreen and compared with the reference images in `tests/golden`.
+//! Run with `GOLDEN_UPDATE=1` to write new reference images after intended visual changes.
+//! Tests fail when no graphics adapter is available, a software adapter like llvmpipe is enough.
+
+use std::{path::PathBuf, thread, time::Duration};
+
+use cgmath::{Deg, EuclideanSpace, Point3, Quaternion, Rad, Rotation3};
+
+use crate::{
+    assets::asset_manager::AssetMgr,
+    renderer::{
+        model::InstanceRaw,
+        render_state::RenderState,
+        terrain_material::{TerrainBlendRules, TerrainLayer},
+    },
+};
+
+use super::{
+    collision::collider::ColliderMgr,
+    diagnostics::axis_renderer::AxisRendererMgr,
+    mesh_renderer::MeshInstancedRendererMgr,
+    model::ModelMgr,
+    terrain::{
+        heightfield::{HeightfieldSettings, HeightfieldSource},
+        terrain::Terrain,
+        terrain_chunk::TerrainStreamingSettings,
+        terrain_generator::{TerrainNoiseSettings, TerrainPlateau},
+        terrain_renderer::TerrainRendererMgr,
+    },
+    transform::{TransformMgr, UNIT_SCALE},
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+
+const REFERENCE_DIR: &str = "tests/golden";
+const OUTPUT_DIR: &str = "target/golden";
+
+/// Per pixel perceptual difference above which pixels count as different, from 0 to 1.
+const PIXEL_THRESHOLD: f32 = 0.1;
+/// Fraction of different pixels allowed before a test fails.
+const MAX_DIFFERENT_PIXELS: f32 = 0.01;
+/// Different pixels allowed inside a `Region`.
+const MAX_DIFFERENT_REGION_PIXELS: usize = 2;
+
+/// Image area around a subject too small to fail the whole image tolerance when missing.
+#[derive(Debug)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+struct Scene {
+    render_state: RenderState,
+    transform_mgr: TransformMgr,
+    collider_mgr: ColliderMgr,
+    model_mgr: ModelMgr,
+    asset_mgr: AssetMgr,
+    mesh_renderer_mgr: MeshInstancedRendererMgr,
+    terrain_renderer_mgr: TerrainRendererMgr,
+    axis_renderer_mgr: AxisRendererMgr,
+}
+
+impl Scene {
+    fn new() -> Self {
+        // Single sampled, so results don't depend on the MSAA implementation
+        let mut render_state = pollster::block_on(RenderState::new_headless(WIDTH, HEIGHT, 1))
+            .expect("Golden image tests need a graphics adapter");
+
+        let mesh_renderer_mgr = MeshInstancedRendererMgr::new(&mut render_state);
+        let terrain_renderer_mgr = TerrainRendererMgr::new(&mut render_state);
+        let axis_renderer_mgr = AxisRendererMgr::new(&mut render_state);
+
+        Self {
+            render_state,
+            transform_mgr: TransformMgr::new(),
+            collider_mgr: ColliderMgr::new(),
+            model_mgr: ModelMgr::new(),
+            asset_mgr: AssetMgr::new(),
+            mesh_renderer_mgr,
+            terrain_renderer_mgr,
+            axis_renderer_mgr,
+        }
+    }
+
+    fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>) {
+        let direction = target - position;
+        let yaw = f32::atan2(direction.x, direction.z);
+        let pitch = f32::atan2(
+            -direction.y,
+            f32::sqrt(direction.x * direction.x + direction.z * direction.z),
+        );
+
+        self.render_state.camera.position = position;
+        self.render_state.camera.rotation =
+            Quaternion::from_angle_y(Rad(yaw)) * Quaternion::from_angle_x(Rad(pitch));
+        // Updates the camera uniform, the light doesn't move without elapsed time
+        self.render_state.update(Duration::ZERO);
+    }
+
+    fn add_model(&mut self, model_path: &str, position: Point3<f32>, rotation: Quaternion<f32>) {
+        let model_i = pollster::block_on(self.model_mgr.get_with_name_or_add(
+            model_path,
+            &mut self.asset_mgr,
+            &self.render_state,
+            &self.mesh_renderer_mgr,
+        ));
+        self.mesh_renderer_mgr.add(
+            &self.render_state,
+            &self.model_mgr,
+            model_i,
+            position.to_vec(),
+            rotation,
+            UNIT_SCALE,
+            InstanceRaw::NO_TINT,
+            InstanceRaw::NO_EMISSIVE,
+            false,
+        );
+    }
+
+    /// Small procedural terrain, fully streamed in at full detail.
+    fn add_terrain(&mut self, noise_settings: &TerrainNoiseSettings) -> Terrain {
+        let layers = [
+            ([0.32, 0.45, 0.18], 20.0),
+            ([0.42, 0.31, 0.2], 20.0),
+            ([0.85, 0.8, 0.6], 20.0),
+            ([0.45, 0.43, 0.42], 20.0),
+        ]
+        .map(|(tint, tiling)| TerrainLayer {
+            texture_path: "textures/yellow.png",
+            tint,
+            tiling,
+        });
+        let blend_rules = TerrainBlendRules {
+            sand_max_height: 2.0,
+            sand_blend_height: 3.0,
+            cliff_min_slope: 0.3,
+            cliff_max_slope: 0.5,
+        };
+        let streaming_settings = TerrainStreamingSettings {
+            chunk_cells: 32,
+            view_distance_chunks: 16.0,
+            lod_distance_chunks: 16.0,
+            max_lod: 0,
+            skirt_depth: 2.0,
+            worker_count: 2,
+        };
+
+        let heightfield_settings = HeightfieldSettings {
+            source: HeightfieldSource::Noise {
+                settings: noise_settings.clone(),
+                seed: 7,
+            },
+            resolution: 129,
+            horizontal_scale: 400.0,
+            vertical_scale: 40.0,
+        };
+
+        let mut terrain = pollster::block_on(Terrain::new(
+            &heightfield_settings,
+            &streaming_settings,
+            None,
+            &layers,
+            &blend_rules,
+            Point3::new(0.0, 0.0, 0.0),
+            &mut self.transform_mgr,
+            &mut self.collider_mgr,
+            &mut self.model_mgr,
+            &mut self.asset_mgr,
+            &mut self.mesh_renderer_mgr,
+            &mut self.terrain_renderer_mgr,
+            &self.render_state,
+        ));
+
+        // The first update requests every chunk, later updates upload them
+        loop {
+            terrain.update(
+                &self.render_state.camera,
+                &self.transform_mgr,
+                &mut self.terrain_renderer_mgr,
+                &self.render_state,
+            );
+            if terrain.is_streaming_idle() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        terrain
+    }
+
+    fn render(&mut self) -> image::RgbaImage {
+        let Self {
+            render_state,
+            model_mgr,
+            mesh_renderer_mgr,
+            terrain_renderer_mgr,
+            axis_renderer_mgr,
+            ..
+        } = self;
+
+        render_state
+            .render_to_image(|render_state, encoder, view| {
+                let hdr_view = &render_state.post_process.hdr_texture.view;
+
+                mesh_renderer_mgr
+                    .render(model_mgr, render_state, encoder, hdr_view)
+                    .unwrap();
+                terrain_renderer_mgr
+                    .render(model_mgr, render_state, encoder, hdr_view)
+                    .unwrap();
+                mesh_renderer_mgr
+                    .render_transparent(model_mgr, render_state, encoder, hdr_view)
+                    .unwrap();
+                axis_renderer_mgr
+                    .render(render_state, encoder, hdr_view)
+                    .unwrap();
+
+                render_state.post_process.render(
+                    &render_state.queue,
+                    encoder,
+                    render_state
+                        .surface_color_attachment(view, wgpu::LoadOp::Clear(wgpu::Color::BLACK)),
+                );
+            })
+            .unwrap()
+    }
+}
+
+fn flat_terrain_settings() -> TerrainNoiseSettings {
+    TerrainNoiseSettings {
+        octaves: 0,
+        frequency: 0.0,
+        lacunarity: 2.0,
+        persistence: 0.5,
+        mountain_weight: 0.0,
+        mountain_frequency: 0.0,
+        valley_exponent: 1.0,
+        plateaus: Vec::new(),
+    }
+}
+
+/// Perceived difference between two colors, from 0 to 1.
+/// Weighted YIQ distance, as used by pixelmatch.
+fn color_difference(a: &image::Rgba<u8>, b: &image::Rgba<u8>) -> f32 {
+    let yiq = |pixel: &image::Rgba<u8>| {
+        let [r, g, b, _] = pixel.0.map(|channel| channel as f32);
+        (
+            r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_23,
+            r * 0.595_978 - g * 0.274_176_1 - b * 0.321_801_9,
+            r * 0.211_470_17 - g * 0.522_617_1 + b * 0.311_146_94,
+        )
+    };
+    let (y_a, i_a, q_a) = yiq(a);
+    let (y_b, i_b, q_b) = yiq(b);
+
+    let delta =
+        0.5053 * (y_a - y_b).powi(2) + 0.299 * (i_a - i_b).powi(2) + 0.1957 * (q_a - q_b).powi(2);
+
+    // Maximum possible delta
+    delta / 35215.0
+}
+
+/// Amount of pixels in a region that differ between two images of the same size.
+fn count_different_pixels(
+    reference: &image::RgbaImage,
+    image: &image::RgbaImage,
+    region: &Region,
+) -> usize {
+    let mut count = 0;
+    for y in region.y..region.y + region.height {
+        for x in region.x..region.x + region.width {
+            let difference = color_difference(reference.get_pixel(x, y), image.get_pixel(x, y));
+            if difference > PIXEL_THRESHOLD * PIXEL_THRESHOLD {
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+/// Compares `image` with the reference image called `name`.
+/// `regions` around small subjects are also checked with `MAX_DIFFERENT_REGION_PIXELS`.
+/// Failing images are written to `target/golden` for inspection.
+fn assert_matches_reference(name: &str, image: &image::RgbaImage, regions: &[Region]) {
+    let reference_path = PathBuf::from(REFERENCE_DIR).join(format!("{}.png", name));
+
+    if std::env::var("GOLDEN_UPDATE").is_ok() {
+        std::fs::create_dir_all(REFERENCE_DIR).unwrap();
+        image.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.to_rgba8(),
+        Err(e) => panic!(
+            "Missing reference image {:?} ({}), run with GOLDEN_UPDATE=1 to create it",
+            reference_path, e
+        ),
+    };
+    assert_eq!(
+        reference.dimensions(),
+        image.dimensions(),
+        "Reference image {:?} has a different size",
+        reference_path
+    );
+
+    let whole_image = Region {
+        x: 0,
+        y: 0,
+        width: image.width(),
+        height: image.height(),
+    };
+    let different_pixels = count_different_pixels(&reference, image, &whole_image);
+    let different_fraction = different_pixels as f32 / (image.width() * image.height()) as f32;
+
+    let mut failure = None;
+    if different_fraction > MAX_DIFFERENT_PIXELS {
+        failure = Some(format!(
+            "{:.2}% of the pixels differ",
+            different_fraction * 100.0
+        ));
+    }
+    for region in regions.iter() {
+        let different_pixels = count_different_pixels(&reference, image, region);
+        if different_pixels > MAX_DIFFERENT_REGION_PIXELS {
+            failure = Some(format!(
+                "{} pixels differ in {:?}",
+                different_pixels, region
+            ));
+        }
+    }
+
+    if let Some(failure) = failure {
+        std::fs::create_dir_all(OUTPUT_DIR).unwrap();
+        let output_path = PathBuf::from(OUTPUT_DIR).join(format!("{}.png", name));
+        image.save(&output_path).unwrap();
+
+        panic!(
+            "{} from {:?}, output written to {:?}",
+            failure, reference_path, output_path
+        );
+    }
+}
+
+#[test]
+fn terrain_and_aircraft() {
+    let mut scene = Scene::new();
+
+    let mut noise_settings = flat_terrain_settings();
+    noise_settings.octaves = 4;
+    noise_settings.frequency = 0.01;
+    noise_settings.plateaus.push(TerrainPlateau {
+        center_x: 0.0,
+        center_z: 0.0,
+        half_width: 10.0,
+        half_length: 10.0,
+        falloff: 20.0,
+        height: Some(0.5),
+    });
+
+    // Close enough for the aircraft to fill a large part of the image
+    scene.look_at(Point3::new(1.5, 27.0, -3.0), Point3::new(0.0, 25.0, 0.0));
+    let _terrain = scene.add_terrain(&noise_settings);
+    scene.add_model(
+        "models/Aircraft_1.obj",
+        Point3::new(0.0, 25.0, 0.0),
+        Quaternion::from_angle_y(Deg(30.0)),
+    );
+
+    let image = scene.render();
+    assert_matches_reference("terrain_and_aircraft", &image, &[]);
+}
+
+#[test]
+fn axis_gizmo() {
+    let mut scene = Scene::new();
+
+    scene.look_at(Point3::new(2.0, 2.0, -3.0), Point3::new(0.0, 0.0, 0.0));
+
+    let image = scene.render();
+    // The gizmo is drawn in the bottom left corner
+    let gizmo = Region {
+        x: 0,
+        y: HEIGHT - 32,
+        width: 32,
+        height: 32,
+    };
+    assert_matches_reference("axis_gizmo", &image, &[gizmo]);
+}
+
+/// Cubes before, inside and past the fog range, over flat terrain.
+#[test]
+fn fog_distances() {
+    let mut scene = Scene::new();
+
+    scene.look_at(Point3::new(0.0, 23.0, -150.0), Point3::new(0.0, 21.0, 0.0));
+    let _terrain = scene.add_terrain(&flat_terrain_settings());
+    for (x, distance) in [(-3.0, 20.0), (0.0, 110.0), (3.0, 160.0)] {
+        scene.add_model(
+            "models/cube.obj",
+            Point3::new(x, 21.0, distance - 150.0),
+            Quaternion::from_angle_y(Deg(45.0)),
+        );
+    }
+
+    let image = scene.render();
+    // The cubes are small, so each gets its own region
+    let near_cube = Region {
+        x: 136,
+        y: 126,
+        width: 32,
+        height: 32,
+    };
+    let fogged_cubes = Region {
+        x: 118,
+        y: 120,
+        width: 18,
+        height: 16,
+    };
+    assert_matches_reference("fog_distances", &image, &[near_cube, fogged_cubes]);
+}
@@ -1,11 +1,18 @@
-use cgmath::{Quaternion, Vector3};
+use cgmath::{ElementWise, EuclideanSpace, MetricSpace, Point3, Quaternion, Rotation, Vector3};
 use wgpu::util::DeviceExt;
 
-use crate::renderer::{
-    model::{self, DrawModel},
-    render_state::{create_render_pipeline, create_texture_bind_group_layout, RenderState},
-    texture,
-    vertex::Vertex,
+use crate::{
+    assets::asset_cache::Handle,
+    renderer::{
+        frustum::Frustum,
+        model::{self, DrawModel, Model},
+        render_state::{
+            create_render_pipeline_from_source, create_texture_bind_group_layout, RenderState,
+        },
+        texture,
+        vertex::Vertex,
+    },
+    resources::ShaderSource,
 };
 
 use super::model::ModelMgr;
@@ -13,24 +20,47 @@ use super::model::ModelMgr;
 const MAX_MESH_COUNT: usize = 128;
 const MAX_INSTANCE_COUNT: usize = 256;
 
+const SHADER_FILE: &str = "shaders/standard.wgsl";
+const OUTLINE_SHADER_FILE: &str = "shaders/outline.wgsl";
+
 // TODO: Currently the component supports just a single instance per mesh.
 pub struct MeshInstancedRendererMgr {
-    model_i: Vec<usize>,
+    model_i: Vec<Handle<Model>>,
     position: Vec<Vector3<f32>>,
     rotation: Vec<Quaternion<f32>>,
+    scale: Vec<Vector3<f32>>,
+
+    /// World space bounds, used for frustum culling.
+    bounds_min: Vec<Point3<f32>>,
+    bounds_max: Vec<Point3<f32>>,
 
     instance_raw: Vec<Vec<model::InstanceRaw>>,
     instance_buffer: Vec<wgpu::Buffer>,
 
+    /// Instances drawn and culled in the last render.
+    pub drawn_count: usize,
+    pub culled_count: usize,
+
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
+    /// Alpha blended, without depth writes.
+    transparent_render_pipeline: wgpu::RenderPipeline,
+    /// Inverted hulls of outlined instances.
+    outline_render_pipeline: wgpu::RenderPipeline,
+    shader: Handle<ShaderSource>,
+    outline_shader: Handle<ShaderSource>,
 }
 
 impl MeshInstancedRendererMgr {
-    pub fn new(render_state: &RenderState) -> Self {
+    pub fn new(render_state: &mut RenderState) -> Self {
         let model_i = Vec::with_capacity(MAX_MESH_COUNT);
         let position = Vec::with_capacity(MAX_MESH_COUNT);
         let rotation = Vec::with_capacity(MAX_MESH_COUNT);
+        let scale = Vec::with_capacity(MAX_MESH_COUNT);
+
+        let bounds_min = Vec::with_capacity(MAX_MESH_COUNT);
+        let bounds_max = Vec::with_capacity(MAX_MESH_COUNT);
 
         let instance_raw = Vec::with_capacity(MAX_MESH_COUNT);
         let instance_buffer = Vec::with_capacity(MAX_MESH_COUNT);
@@ -49,53 +79,171 @@ impl MeshInstancedRendererMgr {
                     push_constant_ranges: &[],
                 });
 
-        let render_pipeline = {
-            let shader_module_descriptor = wgpu::ShaderModuleDescriptor {
-                label: Some("Mesh renderer shader"),
-                source: wgpu::ShaderSource::Wgsl(
-                    include_str!("../renderer/shaders/standard.wgsl").into(), // TODO: load shaders as resource
-                ),
-            };
-            create_render_pipeline(
-                &render_state.device,
-                &render_pipeline_layout,
-                render_state.config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
-                shader_module_descriptor,
-                Some(wgpu::Face::Back),
-            )
-        };
+        let shader = render_state.shader_mgr.load(SHADER_FILE).unwrap();
+        let render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            false,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
+        let transparent_render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            true,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
+
+        let outline_shader = render_state.shader_mgr.load(OUTLINE_SHADER_FILE).unwrap();
+        let outline_render_pipeline = Self::create_outline_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            render_state.shader_mgr.get(outline_shader),
+        )
+        .unwrap();
 
         Self {
             model_i,
             position,
             rotation,
+            scale,
+
+            bounds_min,
+            bounds_max,
 
             instance_raw,
             instance_buffer,
 
+            drawn_count: 0,
+            culled_count: 0,
+
             texture_bind_group_layout,
+            render_pipeline_layout,
             render_pipeline,
+            transparent_render_pipeline,
+            outline_render_pipeline,
+            shader,
+            outline_shader,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        is_transparent: bool,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        let blend = if is_transparent {
+            wgpu::BlendState::ALPHA_BLENDING
+        } else {
+            wgpu::BlendState::REPLACE
+        };
+
+        create_render_pipeline_from_source(
+            device,
+            render_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            blend,
+            Some(texture::Texture::DEPTH_FORMAT),
+            !is_transparent,
+            sample_count,
+            &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+            shader,
+            Some(wgpu::Face::Back),
+        )
+    }
+
+    fn create_outline_pipeline(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        create_render_pipeline_from_source(
+            device,
+            render_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+            Some(texture::Texture::DEPTH_FORMAT),
+            true,
+            sample_count,
+            &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+            shader,
+            // Only the back of the hull, so the model stays visible inside it
+            Some(wgpu::Face::Front),
+        )
+    }
+
+    /// Rebuilds the render pipelines if their shader was reloaded.
+    pub fn reload_shaders(&mut self, render_state: &mut RenderState) {
+        let device = &render_state.device;
+        let sample_count = render_state.sample_count;
+        for (render_pipeline, is_transparent) in [
+            (&mut self.render_pipeline, false),
+            (&mut self.transparent_render_pipeline, true),
+        ] {
+            render_state
+                .shader_mgr
+                .reload_pipeline(self.shader, render_pipeline, |shader| {
+                    Self::create_pipeline(
+                        device,
+                        &self.render_pipeline_layout,
+                        sample_count,
+                        is_transparent,
+                        shader,
+                    )
+                });
         }
+        render_state.shader_mgr.reload_pipeline(
+            self.outline_shader,
+            &mut self.outline_render_pipeline,
+            |shader| {
+                Self::create_outline_pipeline(
+                    device,
+                    &self.render_pipeline_layout,
+                    sample_count,
+                    shader,
+                )
+            },
+        );
     }
 
     /// Returns instance index
     pub fn add(
         &mut self,
         render_state: &RenderState,
-        model_i: usize,
+        model_mgr: &ModelMgr,
+        model_i: Handle<Model>,
         position: Vector3<f32>,
         rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+        tint: [f32; 4],
+        emissive: [f32; 3],
+        is_outlined: bool,
     ) -> usize {
         self.model_i.push(model_i);
         self.position.push(position);
         self.rotation.push(rotation);
+        self.scale.push(scale);
+
+        let (bounds_min, bounds_max) =
+            Self::calculate_world_bounds(model_mgr, model_i, position, rotation, scale);
+        self.bounds_min.push(bounds_min);
+        self.bounds_max.push(bounds_max);
 
         let index = self.len() - 1;
 
         let mut mesh_instances = Vec::with_capacity(MAX_INSTANCE_COUNT);
-        mesh_instances.push(model::InstanceRaw::new(position, rotation));
+        let mut instance_raw = model::InstanceRaw::new(position, rotation, scale);
+        instance_raw.tint = tint;
+        instance_raw.emissive = emissive;
+        instance_raw.outline = if is_outlined { 1.0 } else { 0.0 };
+        mesh_instances.push(instance_raw);
         self.instance_raw.push(mesh_instances);
 
         let instance_buffer = self.create_instance_buffer(index, render_state);
@@ -118,19 +266,106 @@ impl MeshInstancedRendererMgr {
             })
     }
 
+    /// Axis aligned box enclosing the scaled and rotated model bounds.
+    fn calculate_world_bounds(
+        model_mgr: &ModelMgr,
+        model_i: Handle<Model>,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> (Point3<f32>, Point3<f32>) {
+        let model = model_mgr.get(model_i);
+
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for x in [model.min_x, model.max_x] {
+            for y in [model.min_y, model.max_y] {
+                for z in [model.min_z, model.max_z] {
+                    let corner = rotation
+                        .rotate_vector(Vector3::new(x, y, z).mul_element_wise(scale))
+                        + position;
+
+                    min.x = f32::min(min.x, corner.x);
+                    min.y = f32::min(min.y, corner.y);
+                    min.z = f32::min(min.z, corner.z);
+                    max.x = f32::max(max.x, corner.x);
+                    max.y = f32::max(max.y, corner.y);
+                    max.z = f32::max(max.z, corner.z);
+                }
+            }
+        }
+
+        (min, max)
+    }
+
+    /// Recalculates the bounds of the instances of `model_i`, after the model is reloaded.
+    pub fn update_model_bounds(&mut self, model_mgr: &ModelMgr, model_i: Handle<Model>) {
+        for index in 0..self.len() {
+            if self.model_i[index] != model_i {
+                continue;
+            }
+
+            let (bounds_min, bounds_max) = Self::calculate_world_bounds(
+                model_mgr,
+                model_i,
+                self.position[index],
+                self.rotation[index],
+                self.scale[index],
+            );
+            self.bounds_min[index] = bounds_min;
+            self.bounds_max[index] = bounds_max;
+        }
+    }
+
     pub fn update_instance_position(
         &mut self,
         index: usize,
         position: Vector3<f32>,
         rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+        model_mgr: &ModelMgr,
         render_state: &RenderState,
     ) {
-        self.instance_raw[index][0].update(position, rotation);
+        self.position[index] = position;
+        self.rotation[index] = rotation;
+        self.scale[index] = scale;
+
+        let (bounds_min, bounds_max) =
+            Self::calculate_world_bounds(model_mgr, self.model_i[index], position, rotation, scale);
+        self.bounds_min[index] = bounds_min;
+        self.bounds_max[index] = bounds_max;
+
+        self.instance_raw[index][0].update(position, rotation, scale);
         // TODO: use queue.write_buffer instead of recreating the buffer
         let instance_buffer = self.create_instance_buffer(index, render_state);
         self.instance_buffer[index] = instance_buffer;
     }
 
+    /// Sets the tint, emissive color and outline of the instance.
+    pub fn update_instance_style(
+        &mut self,
+        index: usize,
+        tint: [f32; 4],
+        emissive: [f32; 3],
+        is_outlined: bool,
+        render_state: &RenderState,
+    ) {
+        let instance_raw = &mut self.instance_raw[index][0];
+        let outline = if is_outlined { 1.0 } else { 0.0 };
+        if instance_raw.tint == tint
+            && instance_raw.emissive == emissive
+            && instance_raw.outline == outline
+        {
+            return;
+        }
+
+        instance_raw.tint = tint;
+        instance_raw.emissive = emissive;
+        instance_raw.outline = outline;
+        let instance_buffer = self.create_instance_buffer(index, render_state);
+        self.instance_buffer[index] = instance_buffer;
+    }
+
     pub fn render(
         &mut self,
         model_mgr: &ModelMgr,
@@ -140,14 +375,9 @@ impl MeshInstancedRendererMgr {
     ) -> Result<(), wgpu::SurfaceError> {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Mesh render pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(render_state.clear_color),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
+            color_attachments: &[Some(
+                render_state.color_attachment(view, wgpu::LoadOp::Clear(render_state.clear_color)),
+            )],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &render_state.depth_texture.view,
                 depth_ops: Some(wgpu::Operations {
@@ -161,12 +391,42 @@ impl MeshInstancedRendererMgr {
         });
         render_pass.set_pipeline(&self.render_pipeline);
 
+        let frustum = Frustum::new(&render_state.camera, &render_state.projection);
+        self.drawn_count = 0;
+        self.culled_count = 0;
+
+        let mut outlined = Vec::new();
         for i in 0..self.len() {
-            let model_i = self.model_i[i];
-            let model = &model_mgr.model[model_i];
+            if !frustum.intersects_aabb(self.bounds_min[i], self.bounds_max[i]) {
+                self.culled_count += 1;
+                continue;
+            }
+            self.drawn_count += 1;
+            if self.instance_raw[i]
+                .iter()
+                .any(|instance_raw| instance_raw.outline > 0.0)
+            {
+                outlined.push(i);
+            }
 
+            let model = model_mgr.get(self.model_i[i]);
             render_pass.set_vertex_buffer(1, self.instance_buffer[i].slice(..));
+            Self::draw_meshes(
+                &mut render_pass,
+                model,
+                false,
+                &self.instance_raw[i],
+                render_state,
+            );
+        }
 
+        // After the opaque meshes, so the hulls only show past their silhouettes
+        if !outlined.is_empty() {
+            render_pass.set_pipeline(&self.outline_render_pipeline);
+        }
+        for i in outlined {
+            let model = model_mgr.get(self.model_i[i]);
+            render_pass.set_vertex_buffer(1, self.instance_buffer[i].slice(..));
             render_pass.draw_model_instanced(
                 model,
                 0..self.instance_raw[i].len() as u32,
@@ -177,4 +437,94 @@ impl MeshInstancedRendererMgr {
 
         Ok(())
     }
+
+    /// Draws the meshes with transparent materials, blended back to front over the scene.
+    /// Must be rendered after the opaque passes, as it keeps their depth.
+    pub fn render_transparent(
+        &mut self,
+        model_mgr: &ModelMgr,
+        render_state: &RenderState,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> Result<(), wgpu::SurfaceError> {
+        let frustum = Frustum::new(&render_state.camera, &render_state.projection);
+        let camera_position = render_state.camera.position;
+
+        let mut sorted = Vec::new();
+        for i in 0..self.len() {
+            let model = model_mgr.get(self.model_i[i]);
+            if !model
+                .materials
+                .iter()
+                .any(|material| material.is_transparent)
+                || !frustum.intersects_aabb(self.bounds_min[i], self.bounds_max[i])
+            {
+                continue;
+            }
+
+            let center = self.bounds_min[i].midpoint(self.bounds_max[i]);
+            sorted.push((camera_position.distance2(center), i));
+        }
+        if sorted.is_empty() {
+            return Ok(());
+        }
+        // Back to front, so nearer surfaces are blended over farther ones
+        sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent mesh render pass"),
+            color_attachments: &[Some(
+                render_state.color_attachment(view, wgpu::LoadOp::Load),
+            )],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &render_state.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.transparent_render_pipeline);
+
+        for (_, i) in sorted {
+            let model = model_mgr.get(self.model_i[i]);
+            render_pass.set_vertex_buffer(1, self.instance_buffer[i].slice(..));
+            Self::draw_meshes(
+                &mut render_pass,
+                model,
+                true,
+                &self.instance_raw[i],
+                render_state,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Draws the meshes of `model` whose material transparency matches `is_transparent`.
+    fn draw_meshes<'a>(
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        is_transparent: bool,
+        instance_raw: &[model::InstanceRaw],
+        render_state: &'a RenderState,
+    ) {
+        for mesh in &model.meshes {
+            let material = model.materials.get(mesh.material);
+            if material.is_some_and(|material| material.is_transparent) != is_transparent {
+                continue;
+            }
+
+            render_pass.draw_mesh_instanced(
+                mesh,
+                material,
+                0..instance_raw.len() as u32,
+                &render_state.camera_bind_group,
+                &render_state.light_bind_group,
+            );
+        }
+    }
 }
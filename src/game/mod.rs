@@ -3,13 +3,19 @@ mod aircraft_input;
 mod audio_test;
 mod buildings;
 mod camera;
+mod collision;
 mod diagnostics;
 mod egui_manager;
 pub mod game_state;
+#[cfg(test)]
+mod golden_tests;
 mod inventory;
-mod mesh_renderer;
-mod model;
-mod collision;
+pub mod mesh_renderer;
+pub mod model;
+mod particles;
 mod sample_scene;
 pub mod terrain;
+mod trails;
 pub mod transform;
+#[cfg(test)]
+mod transform_benchmark;
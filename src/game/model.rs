@@ -1,39 +1,43 @@
 use crate::{
+    assets::{
+        asset_cache::{AssetCache, Handle},
+        asset_manager::AssetMgr,
+    },
     renderer::{model::Model, render_state::RenderState},
-    resources,
+    resources::{self, AssetWatcher},
 };
 
 use super::mesh_renderer::MeshInstancedRendererMgr;
 
-const MAX_INSTANCE_COUNT: usize = 128;
-
+/// Models keyed by name, which is the file path for loaded models.
 pub struct ModelMgr {
-    pub name: Vec<String>,
-    pub model: Vec<Model>,
+    models: AssetCache<Model>,
 }
 
 impl ModelMgr {
     pub fn new() -> Self {
-        let name = Vec::with_capacity(MAX_INSTANCE_COUNT);
-        let model = Vec::with_capacity(MAX_INSTANCE_COUNT);
-        Self { name, model }
+        Self {
+            models: AssetCache::new(),
+        }
     }
 
-    pub fn add(&mut self, model: Model, name: &str) -> usize {
-        self.name.push(String::from(name));
-        self.model.push(model);
-
-        self.len() - 1
+    /// Adds a model with a single reference.
+    pub fn add(&mut self, model: Model, name: &str) -> Handle<Model> {
+        self.models.add(name, model)
     }
 
+    /// Loads a model with a single reference, even if it's already loaded.
+    /// Use `get_with_name_or_add` to share loaded models.
     pub async fn add_from_file(
         &mut self,
         model_path: &str,
+        asset_mgr: &mut AssetMgr,
         render_state: &RenderState,
         mesh_renderer_mgr: &MeshInstancedRendererMgr,
-    ) -> usize {
+    ) -> Handle<Model> {
         let model = resources::load_model_obj(
             model_path,
+            asset_mgr,
             &render_state.device,
             &render_state.queue,
             &mesh_renderer_mgr.texture_bind_group_layout,
@@ -44,32 +48,90 @@ impl ModelMgr {
         self.add(model, model_path)
     }
 
-    pub fn len(&self) -> usize {
-        self.model.len()
-    }
+    /// Reloads models whose source files changed, keeping their handles valid.
+    /// Models that fail to load keep their previous version.
+    /// Returns the handles of the reloaded models.
+    pub fn reload_changed(
+        &mut self,
+        asset_watcher: &AssetWatcher,
+        asset_mgr: &mut AssetMgr,
+        render_state: &RenderState,
+        mesh_renderer_mgr: &MeshInstancedRendererMgr,
+    ) -> Vec<Handle<Model>> {
+        let mut reloaded = Vec::new();
+        if asset_watcher.changed_files().is_empty() {
+            return reloaded;
+        }
 
-    pub fn get_with_name(&self, name: &str) -> Option<usize> {
-        for (index, model_name) in self.name.iter().enumerate() {
-            if model_name.eq(name) {
-                return Some(index);
+        let handles: Vec<Handle<Model>> = self.models.handles().collect();
+        for handle in handles {
+            if !asset_watcher.is_changed(&self.models.get(handle).source_files) {
+                continue;
+            }
+
+            let name = self.models.key(handle).to_string();
+            let model = pollster::block_on(resources::load_model_obj(
+                &name,
+                asset_mgr,
+                &render_state.device,
+                &render_state.queue,
+                &mesh_renderer_mgr.texture_bind_group_layout,
+            ));
+            match model {
+                Ok(model) => {
+                    println!("Reloaded model: {}", name);
+                    let old_model = self.models.replace(handle, model);
+                    Self::release_textures(&old_model, asset_mgr);
+                    reloaded.push(handle);
+                }
+                Err(e) => println!("Failed to reload model {}: {}", name, e),
             }
         }
 
-        None
+        reloaded
+    }
+
+    pub fn get(&self, model_i: Handle<Model>) -> &Model {
+        self.models.get(model_i)
+    }
+
+    /// Returns the handle of a loaded model without adding a reference.
+    pub fn get_with_name(&self, name: &str) -> Option<Handle<Model>> {
+        self.models.get_with_key(name)
     }
 
+    /// Returns a loaded model, or loads it. Adds a reference either way.
     pub async fn get_with_name_or_add(
         &mut self,
         model_path: &str,
+        asset_mgr: &mut AssetMgr,
         render_state: &RenderState,
         mesh_renderer_mgr: &MeshInstancedRendererMgr,
-    ) -> usize {
-        match self.get_with_name(model_path) {
-            Some(index) => index,
+    ) -> Handle<Model> {
+        match self.models.acquire_with_key(model_path) {
+            Some(model_i) => model_i,
             None => {
-                self.add_from_file(model_path, render_state, mesh_renderer_mgr)
+                self.add_from_file(model_path, asset_mgr, render_state, mesh_renderer_mgr)
                     .await
             }
         }
     }
+
+    /// Removes a reference. The model stays loaded until `unload_unused`.
+    pub fn release(&mut self, model_i: Handle<Model>) {
+        self.models.release(model_i);
+    }
+
+    /// Drops models without references, and releases their textures.
+    pub fn unload_unused(&mut self, asset_mgr: &mut AssetMgr) {
+        for model in self.models.unload_unused() {
+            Self::release_textures(&model, asset_mgr);
+        }
+    }
+
+    fn release_textures(model: &Model, asset_mgr: &mut AssetMgr) {
+        for material in model.materials.iter() {
+            material.release_textures(asset_mgr);
+        }
+    }
 }
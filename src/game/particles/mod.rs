@@ -0,0 +1,2 @@
+pub mod particle_emitter;
+pub mod particle_renderer;
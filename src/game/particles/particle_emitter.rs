@@ -0,0 +1,319 @@
+use std::{f32::consts::PI, time::Duration};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Rad, Rotation, Vector3};
+
+use crate::game::transform::TransformMgr;
+
+const MAX_EMITTER_COUNT: usize = 128;
+pub const MAX_PARTICLE_COUNT: usize = 8192;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ParticleBlendMode {
+    /// Smoke and dust, drawn back to front.
+    Alpha,
+    /// Fire and glow, order independent.
+    Additive,
+}
+
+#[derive(Clone, Copy)]
+pub struct ParticleEmitterSettings {
+    /// Particles per second. Zero for emitters only used with `emit_burst`.
+    pub spawn_rate: f32,
+    /// Seconds.
+    pub lifetime: f32,
+
+    /// Emission direction, in the local space of the emitter transform.
+    pub direction: Vector3<f32>,
+    /// Half angle of the emission cone around `direction`.
+    pub cone_angle: Rad<f32>,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Downwards acceleration. Negative values make particles rise.
+    pub gravity: f32,
+    /// Fraction of the velocity lost per second.
+    pub drag: f32,
+
+    /// Linear RGBA, interpolated over the particle lifetime.
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    /// World units, interpolated over the particle lifetime.
+    pub start_size: f32,
+    pub end_size: f32,
+
+    pub blend_mode: ParticleBlendMode,
+}
+
+/// Particle emitters attached to transforms, and the particles they spawned.
+/// Particles are simulated on the CPU, in world space.
+pub struct ParticleEmitterMgr {
+    pub settings: Vec<ParticleEmitterSettings>,
+    pub is_emitting: Vec<bool>,
+    /// Offset from the transform position, in its local space.
+    offset: Vec<Vector3<f32>>,
+    spawn_accumulator: Vec<f32>,
+    /// Particles to spawn at once in the next update.
+    burst_count: Vec<usize>,
+    /// Emitter position in the last update, particles are spawned along the path since then.
+    last_position: Vec<Option<Point3<f32>>>,
+
+    pub transform_i: Vec<usize>,
+
+    pub particle_position: Vec<Point3<f32>>,
+    particle_velocity: Vec<Vector3<f32>>,
+    particle_age: Vec<f32>,
+    pub particle_emitter_i: Vec<usize>,
+
+    rng: oorandom::Rand32,
+}
+
+impl ParticleEmitterMgr {
+    pub fn new() -> Self {
+        Self {
+            settings: Vec::with_capacity(MAX_EMITTER_COUNT),
+            is_emitting: Vec::with_capacity(MAX_EMITTER_COUNT),
+            offset: Vec::with_capacity(MAX_EMITTER_COUNT),
+            spawn_accumulator: Vec::with_capacity(MAX_EMITTER_COUNT),
+            burst_count: Vec::with_capacity(MAX_EMITTER_COUNT),
+            last_position: Vec::with_capacity(MAX_EMITTER_COUNT),
+
+            transform_i: Vec::with_capacity(MAX_EMITTER_COUNT),
+
+            particle_position: Vec::with_capacity(MAX_PARTICLE_COUNT),
+            particle_velocity: Vec::with_capacity(MAX_PARTICLE_COUNT),
+            particle_age: Vec::with_capacity(MAX_PARTICLE_COUNT),
+            particle_emitter_i: Vec::with_capacity(MAX_PARTICLE_COUNT),
+
+            rng: oorandom::Rand32::new(1234),
+        }
+    }
+
+    /// Returns emitter index
+    pub fn add(
+        &mut self,
+        settings: &ParticleEmitterSettings,
+        is_emitting: bool,
+        transform_i: usize,
+        offset: Vector3<f32>,
+    ) -> usize {
+        self.settings.push(*settings);
+        self.is_emitting.push(is_emitting);
+        self.offset.push(offset);
+        self.spawn_accumulator.push(0.0);
+        self.burst_count.push(0);
+        self.last_position.push(None);
+
+        self.transform_i.push(transform_i);
+
+        self.len() - 1
+    }
+
+    /// Returns the amount of emitters.
+    pub fn len(&self) -> usize {
+        self.settings.len()
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particle_position.len()
+    }
+
+    /// Stopped emitters keep their spawned particles until they die.
+    pub fn set_emitting(&mut self, index: usize, is_emitting: bool) {
+        self.is_emitting[index] = is_emitting;
+    }
+
+    /// Spawns `count` particles at once in the next update, e.g. for explosions.
+    /// Works whether the emitter is emitting or not.
+    pub fn emit_burst(&mut self, index: usize, count: usize) {
+        self.burst_count[index] += count;
+    }
+
+    pub fn update(&mut self, transform_mgr: &TransformMgr, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        self.simulate(dt);
+
+        for i in 0..self.len() {
+            let transform_i = self.transform_i[i];
+            let rotation = transform_mgr.rotation(transform_i);
+            let position =
+                transform_mgr.local_to_world(transform_i, Point3::from_vec(self.offset[i]));
+            let last_position = self.last_position[i].unwrap_or(position);
+            self.last_position[i] = Some(position);
+
+            let settings = self.settings[i];
+            let direction = rotation.rotate_vector(settings.direction).normalize();
+
+            let burst_count = std::mem::take(&mut self.burst_count[i]);
+            for _ in 0..burst_count {
+                if self.particle_count() >= MAX_PARTICLE_COUNT {
+                    break;
+                }
+                self.spawn_particle(i, position, direction, 0.0);
+            }
+
+            if !self.is_emitting[i] {
+                self.spawn_accumulator[i] = 0.0;
+                continue;
+            }
+
+            self.spawn_accumulator[i] += settings.spawn_rate * dt;
+            let spawn_count = self.spawn_accumulator[i].floor();
+            self.spawn_accumulator[i] -= spawn_count;
+
+            let spawn_count = spawn_count as usize;
+            for spawn_i in 0..spawn_count {
+                if self.particle_count() >= MAX_PARTICLE_COUNT {
+                    break;
+                }
+
+                // Spread over the frame, so moving emitters leave a continuous trail
+                let t = (spawn_i as f32 + self.rng.rand_float()) / spawn_count as f32;
+                let age = (1.0 - t) * dt;
+                self.spawn_particle(
+                    i,
+                    last_position + (position - last_position) * t,
+                    direction,
+                    age,
+                );
+            }
+        }
+    }
+
+    /// Spawns a particle `age` seconds ago at `position`, moving within the emission cone.
+    fn spawn_particle(
+        &mut self,
+        emitter_i: usize,
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        age: f32,
+    ) {
+        let settings = self.settings[emitter_i];
+        let velocity = self.random_cone_direction(direction, settings.cone_angle)
+            * (settings.min_speed
+                + (settings.max_speed - settings.min_speed) * self.rng.rand_float());
+
+        self.particle_position.push(position + velocity * age);
+        self.particle_velocity.push(velocity);
+        self.particle_age.push(age);
+        self.particle_emitter_i.push(emitter_i);
+    }
+
+    fn simulate(&mut self, dt: f32) {
+        let mut i = 0;
+        while i < self.particle_count() {
+            let settings = &self.settings[self.particle_emitter_i[i]];
+
+            self.particle_age[i] += dt;
+            if self.particle_age[i] >= settings.lifetime {
+                self.particle_position.swap_remove(i);
+                self.particle_velocity.swap_remove(i);
+                self.particle_age.swap_remove(i);
+                self.particle_emitter_i.swap_remove(i);
+                continue;
+            }
+
+            let velocity = &mut self.particle_velocity[i];
+            velocity.y -= settings.gravity * dt;
+            *velocity *= f32::max(1.0 - settings.drag * dt, 0.0);
+            self.particle_position[i] += *velocity * dt;
+
+            i += 1;
+        }
+    }
+
+    /// Uniformly distributed unit vector within `angle` of `axis`.
+    fn random_cone_direction(&mut self, axis: Vector3<f32>, angle: Rad<f32>) -> Vector3<f32> {
+        let cos_theta = 1.0 - self.rng.rand_float() * (1.0 - f32::cos(angle.0));
+        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * self.rng.rand_float();
+
+        // Any vector perpendicular to the axis
+        let reference = if f32::abs(axis.y) < 0.99 {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_x()
+        };
+        let tangent = axis.cross(reference).normalize();
+        let bitangent = axis.cross(tangent);
+
+        axis * cos_theta + (tangent * f32::cos(phi) + bitangent * f32::sin(phi)) * sin_theta
+    }
+
+    /// Particle age from 0 to 1.
+    fn particle_life_fraction(&self, particle_i: usize) -> f32 {
+        let settings = &self.settings[self.particle_emitter_i[particle_i]];
+        self.particle_age[particle_i] / settings.lifetime
+    }
+
+    pub fn particle_color(&self, particle_i: usize) -> [f32; 4] {
+        let settings = &self.settings[self.particle_emitter_i[particle_i]];
+        let t = self.particle_life_fraction(particle_i);
+
+        let mut color = settings.start_color;
+        for (channel, end) in color.iter_mut().zip(settings.end_color) {
+            *channel += (end - *channel) * t;
+        }
+
+        color
+    }
+
+    pub fn particle_size(&self, particle_i: usize) -> f32 {
+        let settings = &self.settings[self.particle_emitter_i[particle_i]];
+        let t = self.particle_life_fraction(particle_i);
+
+        settings.start_size + (settings.end_size - settings.start_size) * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::PI, time::Duration};
+
+    use cgmath::{Deg, Point3, Quaternion, Rad, Rotation3, Vector3};
+
+    use crate::game::transform::{TransformMgr, UNIT_SCALE};
+
+    use super::{ParticleBlendMode, ParticleEmitterMgr, ParticleEmitterSettings};
+
+    #[test]
+    fn emit_burst_spawns_once_from_stopped_emitters() {
+        let mut transform_mgr = TransformMgr::new();
+        let transform_i = transform_mgr.add(
+            Point3::new(1.0, 2.0, 3.0),
+            Quaternion::from_angle_y(Deg(0.0)),
+            UNIT_SCALE,
+        );
+
+        let settings = ParticleEmitterSettings {
+            spawn_rate: 0.0,
+            lifetime: 1.0,
+            direction: Vector3::unit_y(),
+            cone_angle: Rad(PI),
+            min_speed: 1.0,
+            max_speed: 2.0,
+            gravity: 0.0,
+            drag: 0.0,
+            start_color: [1.0; 4],
+            end_color: [1.0; 4],
+            start_size: 1.0,
+            end_size: 1.0,
+            blend_mode: ParticleBlendMode::Additive,
+        };
+        let mut particle_emitter_mgr = ParticleEmitterMgr::new();
+        let emitter_i = particle_emitter_mgr.add(&settings, false, transform_i, Vector3::unit_y());
+
+        particle_emitter_mgr.emit_burst(emitter_i, 10);
+        particle_emitter_mgr.emit_burst(emitter_i, 5);
+        particle_emitter_mgr.update(&transform_mgr, Duration::from_secs_f32(0.1));
+        assert_eq!(particle_emitter_mgr.particle_count(), 15);
+        for position in particle_emitter_mgr.particle_position.iter() {
+            assert_eq!(*position, Point3::new(1.0, 3.0, 3.0));
+        }
+
+        // Bursts don't repeat, the particles die after their lifetime
+        particle_emitter_mgr.update(&transform_mgr, Duration::from_secs_f32(0.5));
+        assert_eq!(particle_emitter_mgr.particle_count(), 15);
+        particle_emitter_mgr.update(&transform_mgr, Duration::from_secs_f32(0.6));
+        assert_eq!(particle_emitter_mgr.particle_count(), 0);
+    }
+}
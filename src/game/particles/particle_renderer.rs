@@ -0,0 +1,335 @@
+use std::mem::size_of;
+
+use cgmath::{InnerSpace, MetricSpace, Rotation, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    assets::asset_cache::Handle,
+    renderer::{
+        render_state::{create_render_pipeline_from_source, RenderState},
+        texture,
+        vertex::Vertex,
+    },
+    resources::ShaderSource,
+};
+
+use super::particle_emitter::{ParticleBlendMode, ParticleEmitterMgr, MAX_PARTICLE_COUNT};
+
+const SHADER_FILE: &str = "shaders/particle.wgsl";
+
+/// Quad corners are generated in the vertex shader.
+const VERTICES_PER_PARTICLE: u32 = 6;
+
+const ADDITIVE_BLENDING: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::SrcAlpha,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParticleInstanceRaw {
+    position_size: [f32; 4],
+    color: [f32; 4],
+}
+
+impl Vertex for ParticleInstanceRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<ParticleInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // Position and size
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Color
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Camera axes for billboarding.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleUniform {
+    camera_right: [f32; 4],
+    camera_up: [f32; 4],
+}
+
+/// Renders particles as camera facing quads, depth tested against the scene without writing depth.
+/// Alpha blended particles are sorted back to front, additive ones are drawn after them.
+pub struct ParticleRendererMgr {
+    instance_raw: Vec<ParticleInstanceRaw>,
+    instance_buffer: wgpu::Buffer,
+    /// Alpha blended instances come first in the instance buffer.
+    alpha_instance_count: u32,
+    additive_instance_count: u32,
+
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    render_pipeline_layout: wgpu::PipelineLayout,
+    alpha_render_pipeline: wgpu::RenderPipeline,
+    additive_render_pipeline: wgpu::RenderPipeline,
+    shader: Handle<ShaderSource>,
+}
+
+impl ParticleRendererMgr {
+    pub fn new(render_state: &mut RenderState) -> Self {
+        let device = &render_state.device;
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle instance buffer"),
+            size: (MAX_PARTICLE_COUNT * size_of::<ParticleInstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle uniform buffer"),
+            contents: bytemuck::cast_slice(&[ParticleUniform {
+                camera_right: [1.0, 0.0, 0.0, 0.0],
+                camera_up: [0.0, 1.0, 0.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Particle bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Particle render pipeline layout"),
+                bind_group_layouts: &[&render_state.camera_bind_group_layout, &bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let shader = render_state.shader_mgr.load(SHADER_FILE).unwrap();
+        let alpha_render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            ParticleBlendMode::Alpha,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
+        let additive_render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            ParticleBlendMode::Additive,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
+
+        Self {
+            instance_raw: Vec::with_capacity(MAX_PARTICLE_COUNT),
+            instance_buffer,
+            alpha_instance_count: 0,
+            additive_instance_count: 0,
+
+            uniform_buffer,
+            bind_group,
+
+            render_pipeline_layout,
+            alpha_render_pipeline,
+            additive_render_pipeline,
+            shader,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        blend_mode: ParticleBlendMode,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        let blend = match blend_mode {
+            ParticleBlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            ParticleBlendMode::Additive => ADDITIVE_BLENDING,
+        };
+
+        create_render_pipeline_from_source(
+            device,
+            render_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            blend,
+            Some(texture::Texture::DEPTH_FORMAT),
+            false,
+            sample_count,
+            &[ParticleInstanceRaw::desc()],
+            shader,
+            None,
+        )
+    }
+
+    /// Rebuilds the render pipelines if their shader was reloaded.
+    pub fn reload_shaders(&mut self, render_state: &mut RenderState) {
+        let device = &render_state.device;
+        let sample_count = render_state.sample_count;
+        for (render_pipeline, blend_mode) in [
+            (&mut self.alpha_render_pipeline, ParticleBlendMode::Alpha),
+            (
+                &mut self.additive_render_pipeline,
+                ParticleBlendMode::Additive,
+            ),
+        ] {
+            render_state
+                .shader_mgr
+                .reload_pipeline(self.shader, render_pipeline, |shader| {
+                    Self::create_pipeline(
+                        device,
+                        &self.render_pipeline_layout,
+                        sample_count,
+                        blend_mode,
+                        shader,
+                    )
+                });
+        }
+    }
+
+    /// Uploads the particles of `particle_emitter_mgr` and the camera axes.
+    pub fn update(
+        &mut self,
+        particle_emitter_mgr: &ParticleEmitterMgr,
+        render_state: &RenderState,
+    ) {
+        let camera = &render_state.camera;
+        let camera_right = camera.rotation.rotate_vector(Vector3::unit_x()).normalize();
+        let camera_up = camera.rotation.rotate_vector(Vector3::unit_y()).normalize();
+
+        render_state.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ParticleUniform {
+                camera_right: camera_right.extend(0.0).into(),
+                camera_up: camera_up.extend(0.0).into(),
+            }]),
+        );
+
+        // Back to front, so nearer alpha blended particles are drawn over farther ones
+        let mut alpha_particles = Vec::new();
+        let mut additive_particles = Vec::new();
+        for particle_i in 0..particle_emitter_mgr.particle_count() {
+            let emitter_i = particle_emitter_mgr.particle_emitter_i[particle_i];
+            match particle_emitter_mgr.settings[emitter_i].blend_mode {
+                ParticleBlendMode::Alpha => {
+                    let distance = camera
+                        .position
+                        .distance2(particle_emitter_mgr.particle_position[particle_i]);
+                    alpha_particles.push((distance, particle_i));
+                }
+                ParticleBlendMode::Additive => additive_particles.push(particle_i),
+            }
+        }
+        alpha_particles.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        self.instance_raw.clear();
+        for particle_i in alpha_particles
+            .iter()
+            .map(|(_, particle_i)| *particle_i)
+            .chain(additive_particles.iter().copied())
+        {
+            let position = particle_emitter_mgr.particle_position[particle_i];
+            self.instance_raw.push(ParticleInstanceRaw {
+                position_size: [
+                    position.x,
+                    position.y,
+                    position.z,
+                    particle_emitter_mgr.particle_size(particle_i),
+                ],
+                color: particle_emitter_mgr.particle_color(particle_i),
+            });
+        }
+        self.alpha_instance_count = alpha_particles.len() as u32;
+        self.additive_instance_count = additive_particles.len() as u32;
+
+        render_state.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instance_raw),
+        );
+    }
+
+    /// Must be rendered after the opaque passes, as it keeps their depth.
+    pub fn render(
+        &mut self,
+        render_state: &RenderState,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> Result<(), wgpu::SurfaceError> {
+        if self.instance_raw.is_empty() {
+            return Ok(());
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Particle render pass"),
+            color_attachments: &[Some(
+                render_state.color_attachment(view, wgpu::LoadOp::Load),
+            )],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &render_state.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+
+        let alpha_end = self.alpha_instance_count;
+        let additive_end = alpha_end + self.additive_instance_count;
+        if alpha_end > 0 {
+            render_pass.set_pipeline(&self.alpha_render_pipeline);
+            render_pass.draw(0..VERTICES_PER_PARTICLE, 0..alpha_end);
+        }
+        if additive_end > alpha_end {
+            render_pass.set_pipeline(&self.additive_render_pipeline);
+            render_pass.draw(0..VERTICES_PER_PARTICLE, alpha_end..additive_end);
+        }
+
+        Ok(())
+    }
+}
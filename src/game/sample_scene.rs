@@ -1,19 +1,149 @@
 use cgmath::Rotation3;
 
-use crate::renderer::render_state::RenderState;
+use crate::{
+    assets::{asset_loader::AssetLoader, asset_manager::AssetMgr},
+    renderer::{
+        model::DEFAULT_TEXTURE_PATH,
+        render_state::RenderState,
+        terrain_material::{TerrainBlendRules, TerrainLayer, TERRAIN_LAYER_COUNT},
+        texture::{SamplerOptions, TextureKind},
+    },
+};
 
 use super::{
-    aircraft::{AircraftMgr, AircraftPilot},
+    aircraft::{self, AircraftEffectMgrs, AircraftMgr, AircraftPilot},
     aircraft_input::AircraftInputMgr,
-    buildings::building::{BuildingMgr, BuildingType},
+    audio_test,
+    buildings::building::{self, BuildingMgr, BuildingType},
     collision::collider::ColliderMgr,
     inventory::InventoryMgr,
     mesh_renderer::MeshInstancedRendererMgr,
     model::ModelMgr,
-    terrain::terrain::Terrain,
+    particles::particle_emitter::ParticleEmitterMgr,
+    terrain::{
+        heightfield::{HeightfieldSettings, HeightfieldSource},
+        terrain::Terrain,
+        terrain_chunk::TerrainStreamingSettings,
+        terrain_generator::{TerrainNoiseSettings, TerrainPlateau},
+        terrain_renderer::TerrainRendererMgr,
+    },
+    trails::ribbon_trail::RibbonTrailMgr,
     transform::TransformMgr,
 };
 
+/// Seed for the procedural terrain and the randomly placed aircraft.
+const SEED: u64 = 1234;
+
+/// Samples per terrain side, about 4 world units apart.
+/// The whole grid is kept in memory, for ground height queries and collisions.
+const TERRAIN_RESOLUTION: u32 = 2049;
+const TERRAIN_SIZE: f32 = 8000.0;
+const TERRAIN_HEIGHT: f32 = 120.0;
+
+/// Command line flag replacing the procedural terrain with the authored heightmap.
+const HEIGHTMAP_FLAG: &str = "--heightmap";
+const HEIGHTMAP_PATH: &str = "textures/heightmap.png";
+
+const TERRAIN_LAYERS: [TerrainLayer; TERRAIN_LAYER_COUNT] = [
+    TerrainLayer {
+        texture_path: "textures/terrain/grass/grass_01_ao.png",
+        tint: [0.32, 0.45, 0.18],
+        tiling: 240.0,
+    },
+    TerrainLayer {
+        texture_path: "textures/terrain/dirt/dirt_02_ao.png",
+        tint: [0.42, 0.31, 0.2],
+        tiling: 200.0,
+    },
+    TerrainLayer {
+        texture_path: "textures/terrain/sand/sand_04_albedo.png",
+        tint: [1.0, 1.0, 1.0],
+        tiling: 300.0,
+    },
+    TerrainLayer {
+        texture_path: "textures/terrain/cliff/cliff_01_ao.png",
+        tint: [0.45, 0.43, 0.42],
+        tiling: 120.0,
+    },
+];
+
+/// The procedural terrain from `SEED`, or the authored heightmap with `HEIGHTMAP_FLAG`.
+/// Both cover the same area, so they share the layer tiling and streaming settings.
+fn terrain_heightfield_settings() -> HeightfieldSettings {
+    let source = if std::env::args().any(|arg| arg == HEIGHTMAP_FLAG) {
+        HeightfieldSource::Heightmap(String::from(HEIGHTMAP_PATH))
+    } else {
+        let noise_settings = TerrainNoiseSettings {
+            octaves: 6,
+            frequency: 0.0015,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            mountain_weight: 0.8,
+            mountain_frequency: 0.0004,
+            valley_exponent: 1.8,
+            plateaus: vec![
+                // Factory
+                TerrainPlateau {
+                    center_x: 0.0,
+                    center_z: 0.0,
+                    half_width: 40.0,
+                    half_length: 40.0,
+                    falloff: 80.0,
+                    height: Some(0.1),
+                },
+                // Runway
+                TerrainPlateau {
+                    center_x: 0.0,
+                    center_z: 200.0,
+                    half_width: 25.0,
+                    half_length: 150.0,
+                    falloff: 100.0,
+                    height: Some(0.1),
+                },
+            ],
+        };
+
+        HeightfieldSource::Noise {
+            settings: noise_settings,
+            seed: SEED,
+        }
+    };
+
+    HeightfieldSettings {
+        source,
+        resolution: TERRAIN_RESOLUTION,
+        horizontal_scale: TERRAIN_SIZE,
+        vertical_scale: TERRAIN_HEIGHT,
+    }
+}
+
+/// Queues the assets used by the scene, so `create` finds them loaded.
+pub fn preload(asset_loader: &mut AssetLoader, asset_mgr: &AssetMgr, model_mgr: &ModelMgr) {
+    asset_loader.load_heightfield(&terrain_heightfield_settings(), asset_mgr);
+    for layer in TERRAIN_LAYERS.iter() {
+        asset_loader.load_texture(
+            layer.texture_path,
+            TextureKind::Color,
+            &SamplerOptions::TILING,
+            asset_mgr,
+        );
+    }
+    // Collider bounding boxes use the default material
+    for kind in [TextureKind::Color, TextureKind::NormalMap] {
+        asset_loader.load_texture(
+            DEFAULT_TEXTURE_PATH,
+            kind,
+            &SamplerOptions::CLAMP,
+            asset_mgr,
+        );
+    }
+
+    asset_loader.load_model(aircraft::MODEL_PATH, model_mgr);
+    asset_loader.load_model(building::MODEL_PATH, model_mgr);
+    asset_loader.load_model(building::SUPPLY_ZONE_MODEL_PATH, model_mgr);
+    asset_loader.load_sound(audio_test::SOUND_PATH, asset_mgr);
+}
+
 pub async fn create(
     aircraft_mgr: &mut AircraftMgr,
     transform_mgr: &mut TransformMgr,
@@ -22,31 +152,55 @@ pub async fn create(
     building_mgr: &mut BuildingMgr,
     inventory_mgr: &mut InventoryMgr,
     model_mgr: &mut ModelMgr,
+    asset_mgr: &mut AssetMgr,
     render_state: &RenderState,
     mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
-) {
+    terrain_renderer_mgr: &mut TerrainRendererMgr,
+    particle_emitter_mgr: &mut ParticleEmitterMgr,
+    ribbon_trail_mgr: &mut RibbonTrailMgr,
+) -> Terrain {
     // Load terrain
     let position_terrain = cgmath::Point3 {
         x: 0.0,
         y: 0.0,
         z: 0.0,
     };
-    let rotation_terrain =
-        cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0));
 
-    let _terrain = Terrain::new(
-        "models/Terrain_1.obj",
+    let terrain_blend_rules = TerrainBlendRules {
+        sand_max_height: 2.0,
+        sand_blend_height: 3.0,
+        cliff_min_slope: 0.3,
+        cliff_max_slope: 0.5,
+    };
+
+    let terrain_streaming_settings = TerrainStreamingSettings {
+        chunk_cells: 32,
+        view_distance_chunks: 8.0,
+        lod_distance_chunks: 2.0,
+        max_lod: 3,
+        skirt_depth: 5.0,
+        worker_count: 2,
+    };
+
+    let terrain = Terrain::new(
+        &terrain_heightfield_settings(),
+        &terrain_streaming_settings,
+        None,
+        &TERRAIN_LAYERS,
+        &terrain_blend_rules,
         position_terrain,
-        rotation_terrain,
         transform_mgr,
         collider_mgr,
         model_mgr,
+        asset_mgr,
         mesh_renderer_mgr,
+        terrain_renderer_mgr,
         render_state,
     )
     .await;
 
     // Player aircraft
+    let ground_height = terrain.height_at(transform_mgr, 0.0, 10.0).unwrap_or(0.0);
     aircraft_mgr
         .add(
             AircraftPilot::Player,
@@ -59,22 +213,28 @@ pub async fn create(
             6.0,
             cgmath::Point3 {
                 x: 0.0,
-                y: 6.0,
+                y: ground_height + 6.0,
                 z: 10.0,
             },
             cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0)),
             inventory_mgr,
             model_mgr,
+            asset_mgr,
             transform_mgr,
             collider_mgr,
             aircraft_input_mgr,
             mesh_renderer_mgr,
+            &mut AircraftEffectMgrs {
+                particle_emitter_mgr,
+                ribbon_trail_mgr,
+            },
             render_state,
         )
         .await
         .unwrap();
 
     // Enemy aircraft
+    let ground_height = terrain.height_at(transform_mgr, 30.0, 30.0).unwrap_or(0.0);
     aircraft_mgr
         .add(
             AircraftPilot::Ai,
@@ -87,24 +247,33 @@ pub async fn create(
             6.0,
             cgmath::Point3 {
                 x: 30.0,
-                y: 6.0,
+                y: ground_height + 6.0,
                 z: 30.0,
             },
             cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(00.0)),
             inventory_mgr,
             model_mgr,
+            asset_mgr,
             transform_mgr,
             collider_mgr,
             aircraft_input_mgr,
             mesh_renderer_mgr,
+            &mut AircraftEffectMgrs {
+                particle_emitter_mgr,
+                ribbon_trail_mgr,
+            },
             render_state,
         )
         .await
         .unwrap();
 
-    let mut rng = oorandom::Rand32::new(1234);
+    let mut rng = oorandom::Rand32::new(SEED);
 
     for _ in 0..10 {
+        let x = rng.rand_range(0..1000) as f32 - 500.0;
+        let z = rng.rand_range(0..1000) as f32 - 500.0;
+        let ground_height = terrain.height_at(transform_mgr, x, z).unwrap_or(0.0);
+
         aircraft_mgr
             .add(
                 AircraftPilot::Ai,
@@ -116,17 +285,22 @@ pub async fn create(
                 3.0,
                 6.0,
                 cgmath::Point3 {
-                    x: rng.rand_range(0..1000) as f32 - 500.0,
-                    y: rng.rand_range(10..100) as f32,
-                    z: rng.rand_range(0..1000) as f32 - 500.0,
+                    x,
+                    y: ground_height + rng.rand_range(10..100) as f32,
+                    z,
                 },
                 cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(0.0)),
                 inventory_mgr,
                 model_mgr,
+                asset_mgr,
                 transform_mgr,
                 collider_mgr,
                 aircraft_input_mgr,
                 mesh_renderer_mgr,
+                &mut AircraftEffectMgrs {
+                    particle_emitter_mgr,
+                    ribbon_trail_mgr,
+                },
                 render_state,
             )
             .await
@@ -134,6 +308,7 @@ pub async fn create(
     }
 
     // Buildings
+    let ground_height = terrain.height_at(transform_mgr, 0.0, 0.0).unwrap_or(0.0);
     building_mgr
         .add(
             BuildingType::Factory,
@@ -141,16 +316,20 @@ pub async fn create(
             Some(1000),
             cgmath::Point3 {
                 x: 0.0,
-                y: 1.0,
+                y: ground_height + 1.0,
                 z: 0.0,
             },
             cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0)),
             inventory_mgr,
             transform_mgr,
             model_mgr,
+            asset_mgr,
             mesh_renderer_mgr,
+            particle_emitter_mgr,
             render_state,
         )
         .await
         .unwrap();
+
+    terrain
 }
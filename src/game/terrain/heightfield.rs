@@ -0,0 +1,358 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{renderer::model::ModelVertex, resources};
+
+use super::terrain_generator::{self, TerrainNoiseSettings};
+
+/// Where heightfield samples come from.
+#[derive(Clone)]
+pub enum HeightfieldSource {
+    /// Grayscale image file.
+    Heightmap(String),
+    /// Seeded fractal noise, the same seed always generates the same landscape.
+    Noise {
+        settings: TerrainNoiseSettings,
+        seed: u64,
+    },
+}
+
+/// How a heightfield is loaded or generated.
+#[derive(Clone)]
+pub struct HeightfieldSettings {
+    pub source: HeightfieldSource,
+    /// Amount of samples per side.
+    pub resolution: u32,
+    /// World size per side.
+    pub horizontal_scale: f32,
+    /// Height of a white heightmap pixel, or of the highest noise.
+    pub vertical_scale: f32,
+}
+
+impl HeightfieldSettings {
+    /// Heightmap file name or noise seed, with the resolution.
+    pub fn name(&self) -> String {
+        match &self.source {
+            HeightfieldSource::Heightmap(file_name) => {
+                format!("{} ({})", file_name, self.resolution)
+            }
+            HeightfieldSource::Noise { seed, .. } => {
+                format!("Procedural terrain {} ({})", seed, self.resolution)
+            }
+        }
+    }
+
+    /// Reads the heightmap or generates the noise, slow for large resolutions.
+    pub async fn load(&self) -> anyhow::Result<Heightfield> {
+        match &self.source {
+            HeightfieldSource::Heightmap(file_name) => {
+                let heightmap = resources::load_image(file_name).await?;
+                Ok(Heightfield::from_image(
+                    &heightmap,
+                    self.resolution,
+                    self.horizontal_scale,
+                    self.vertical_scale,
+                ))
+            }
+            HeightfieldSource::Noise { settings, seed } => {
+                Ok(terrain_generator::generate_heightfield(
+                    settings,
+                    *seed,
+                    self.resolution,
+                    self.horizontal_scale,
+                    self.vertical_scale,
+                ))
+            }
+        }
+    }
+}
+
+/// Regular grid of heights, centered at the local origin on the XZ plane.
+/// Samples are stored row-major, one row per Z step.
+pub struct Heightfield {
+    /// Amount of samples per side.
+    pub resolution: u32,
+    /// World size per side.
+    pub size: f32,
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    /// Resamples a grayscale image into a `resolution` x `resolution` grid.
+    /// `horizontal_scale` is the world size per side and `vertical_scale` the height of a white pixel.
+    pub fn from_image(
+        img: &image::DynamicImage,
+        resolution: u32,
+        horizontal_scale: f32,
+        vertical_scale: f32,
+    ) -> Self {
+        let luma = img.to_luma16();
+        let (width, height) = luma.dimensions();
+
+        let mut heights = Vec::with_capacity((resolution * resolution) as usize);
+        for iz in 0..resolution {
+            for ix in 0..resolution {
+                // Bilinear sample in image space
+                let u = ix as f32 / (resolution - 1) as f32 * (width - 1) as f32;
+                let v = iz as f32 / (resolution - 1) as f32 * (height - 1) as f32;
+                let x0 = u.floor() as u32;
+                let y0 = v.floor() as u32;
+                let x1 = u32::min(x0 + 1, width - 1);
+                let y1 = u32::min(y0 + 1, height - 1);
+                let tx = u - x0 as f32;
+                let ty = v - y0 as f32;
+
+                let sample = |x, y| luma.get_pixel(x, y).0[0] as f32 / u16::MAX as f32;
+                let top = sample(x0, y0) * (1.0 - tx) + sample(x1, y0) * tx;
+                let bottom = sample(x0, y1) * (1.0 - tx) + sample(x1, y1) * tx;
+
+                heights.push((top * (1.0 - ty) + bottom * ty) * vertical_scale);
+            }
+        }
+
+        Self {
+            resolution,
+            size: horizontal_scale,
+            heights,
+        }
+    }
+
+    /// Builds a `resolution` x `resolution` grid of `size` world units per side,
+    /// with heights given by `height_fn` at local XZ coordinates.
+    pub fn from_fn<F: Fn(f32, f32) -> f32>(resolution: u32, size: f32, height_fn: F) -> Self {
+        let spacing = size / (resolution - 1) as f32;
+        let half_size = size / 2.0;
+
+        let mut heights = Vec::with_capacity((resolution * resolution) as usize);
+        for iz in 0..resolution {
+            for ix in 0..resolution {
+                let x = ix as f32 * spacing - half_size;
+                let z = iz as f32 * spacing - half_size;
+                heights.push(height_fn(x, z));
+            }
+        }
+
+        Self {
+            resolution,
+            size,
+            heights,
+        }
+    }
+
+    /// Distance between two adjacent samples.
+    pub fn spacing(&self) -> f32 {
+        self.size / (self.resolution - 1) as f32
+    }
+
+    /// Height of a grid sample, indices are clamped to the grid.
+    pub fn sample(&self, ix: i64, iz: i64) -> f32 {
+        let max = self.resolution as i64 - 1;
+        let ix = ix.clamp(0, max) as usize;
+        let iz = iz.clamp(0, max) as usize;
+
+        self.heights[iz * self.resolution as usize + ix]
+    }
+
+    /// Local position of a grid sample.
+    pub fn sample_position(&self, ix: u32, iz: u32) -> Vector3<f32> {
+        let half_size = self.size / 2.0;
+        Vector3::new(
+            ix as f32 * self.spacing() - half_size,
+            self.sample(ix as i64, iz as i64),
+            iz as f32 * self.spacing() - half_size,
+        )
+    }
+
+    /// Bilinearly interpolated height at local coordinates.
+    /// Returns `None` outside of the heightfield.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let half_size = self.size / 2.0;
+        if x < -half_size || x > half_size || z < -half_size || z > half_size {
+            return None;
+        }
+
+        let gx = (x + half_size) / self.spacing();
+        let gz = (z + half_size) / self.spacing();
+        let ix = gx.floor() as i64;
+        let iz = gz.floor() as i64;
+        let tx = gx - ix as f32;
+        let tz = gz - iz as f32;
+
+        let top = self.sample(ix, iz) * (1.0 - tx) + self.sample(ix + 1, iz) * tx;
+        let bottom = self.sample(ix, iz + 1) * (1.0 - tx) + self.sample(ix + 1, iz + 1) * tx;
+
+        Some(top * (1.0 - tz) + bottom * tz)
+    }
+
+    /// Highest sample inside a local XZ area, including the interpolated corners.
+    /// Returns `None` if the area doesn't overlap the heightfield.
+    pub fn max_height_in_area(
+        &self,
+        min_x: f32,
+        min_z: f32,
+        max_x: f32,
+        max_z: f32,
+    ) -> Option<f32> {
+        let half_size = self.size / 2.0;
+        let min_x = f32::max(min_x, -half_size);
+        let min_z = f32::max(min_z, -half_size);
+        let max_x = f32::min(max_x, half_size);
+        let max_z = f32::min(max_z, half_size);
+        if min_x > max_x || min_z > max_z {
+            return None;
+        }
+
+        let mut max_height = f32::MIN;
+        for (x, z) in [
+            (min_x, min_z),
+            (max_x, min_z),
+            (min_x, max_z),
+            (max_x, max_z),
+        ] {
+            if let Some(height) = self.height_at(x, z) {
+                max_height = f32::max(max_height, height);
+            }
+        }
+
+        let ix_start = ((min_x + half_size) / self.spacing()).ceil() as i64;
+        let ix_end = ((max_x + half_size) / self.spacing()).floor() as i64;
+        let iz_start = ((min_z + half_size) / self.spacing()).ceil() as i64;
+        let iz_end = ((max_z + half_size) / self.spacing()).floor() as i64;
+        for iz in iz_start..=iz_end {
+            for ix in ix_start..=ix_end {
+                max_height = f32::max(max_height, self.sample(ix, iz));
+            }
+        }
+
+        Some(max_height)
+    }
+
+    /// Builds mesh vertices and triangle indices for a square region of `cells` x `cells` grid cells,
+    /// starting at sample (`start_x`, `start_z`) and taking one sample every `step` samples.
+    /// `cells` must be a multiple of `step`. UVs span 0 to 1 across the whole heightfield.
+    /// With a `skirt_depth`, a vertical skirt is added around the region to hide cracks between
+    /// neighbouring regions built with different steps.
+    pub fn build_region_mesh_data(
+        &self,
+        start_x: u32,
+        start_z: u32,
+        cells: u32,
+        step: u32,
+        skirt_depth: Option<f32>,
+    ) -> (Vec<ModelVertex>, Vec<u32>) {
+        let side = cells / step + 1;
+
+        let mut vertices = Vec::with_capacity((side * side + 4 * side) as usize);
+        for z in 0..side {
+            for x in 0..side {
+                let ix = u32::min(start_x + x * step, self.resolution - 1);
+                let iz = u32::min(start_z + z * step, self.resolution - 1);
+                vertices.push(self.vertex(ix, iz));
+            }
+        }
+
+        let mut indices =
+            Vec::with_capacity(((side - 1) * (side - 1) * 6 + 4 * side * 12) as usize);
+        for z in 0..side - 1 {
+            for x in 0..side - 1 {
+                let i00 = z * side + x;
+                let i10 = i00 + 1;
+                let i01 = i00 + side;
+                let i11 = i01 + 1;
+
+                // Counter-clockwise seen from above
+                indices.extend_from_slice(&[i00, i01, i10, i10, i01, i11]);
+            }
+        }
+
+        if let Some(skirt_depth) = skirt_depth {
+            // Region perimeter, walked side by side
+            let last = side - 1;
+            let edges: [Vec<u32>; 4] = [
+                (0..side).collect(),
+                (0..side).map(|x| last * side + x).collect(),
+                (0..side).map(|z| z * side).collect(),
+                (0..side).map(|z| z * side + last).collect(),
+            ];
+
+            for edge in edges.iter() {
+                let skirt_start = vertices.len() as u32;
+                for i in edge.iter() {
+                    let mut vertex = vertices[*i as usize];
+                    vertex.position[1] -= skirt_depth;
+                    vertices.push(vertex);
+                }
+
+                for k in 0..edge.len() - 1 {
+                    let a = edge[k];
+                    let b = edge[k + 1];
+                    let a_skirt = skirt_start + k as u32;
+                    let b_skirt = a_skirt + 1;
+
+                    // Double-sided, so the winding doesn't depend on the edge
+                    indices.extend_from_slice(&[a, b, a_skirt, b, b_skirt, a_skirt]);
+                    indices.extend_from_slice(&[a, a_skirt, b, b, a_skirt, b_skirt]);
+                }
+            }
+        }
+
+        (vertices, indices)
+    }
+
+    fn vertex(&self, ix: u32, iz: u32) -> ModelVertex {
+        let position = self.sample_position(ix, iz);
+
+        // Central differences, each neighbour is sampled once
+        let i = ix as i64;
+        let j = iz as i64;
+        let dx = self.sample(i + 1, j) - self.sample(i - 1, j);
+        let dz = self.sample(i, j + 1) - self.sample(i, j - 1);
+        let normal = Vector3::new(-dx, 2.0 * self.spacing(), -dz).normalize();
+
+        // Tangent follows +U (X axis), bitangent is flipped like in
+        // `resources::load_model_obj` to match the wgpu texture coordinate system.
+        let tangent = Vector3::new(2.0 * self.spacing(), dx, 0.0).normalize();
+        let bitangent = -Vector3::new(0.0, dz, 2.0 * self.spacing()).normalize();
+
+        ModelVertex {
+            position: position.into(),
+            tex_coords: [
+                ix as f32 / (self.resolution - 1) as f32,
+                iz as f32 / (self.resolution - 1) as f32,
+            ],
+            normal: normal.into(),
+            tangent: tangent.into(),
+            bitangent: bitangent.into(),
+        }
+    }
+
+    /// Returns (min, max) heights.
+    pub fn height_range(&self) -> (f32, f32) {
+        self.heights
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), h| {
+                (f32::min(min, *h), f32::max(max, *h))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::assert_abs_diff_eq;
+
+    use super::Heightfield;
+
+    #[test]
+    fn from_image_scales_heights_and_interpolates() {
+        // Black left column, white right column
+        let image = image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(2, 2, |x, _| {
+            image::Luma([if x == 0 { 0 } else { 255 }])
+        }));
+        let heightfield = Heightfield::from_image(&image, 3, 100.0, 10.0);
+
+        assert_eq!(heightfield.height_range(), (0.0, 10.0));
+        assert_abs_diff_eq!(heightfield.sample(1, 1), 5.0);
+        assert_abs_diff_eq!(heightfield.height_at(-50.0, 0.0).unwrap(), 0.0);
+        assert_abs_diff_eq!(heightfield.height_at(25.0, 10.0).unwrap(), 7.5);
+        assert_eq!(heightfield.height_at(51.0, 0.0), None);
+    }
+}
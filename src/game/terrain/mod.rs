@@ -1 +1,6 @@
+pub mod heightfield;
+pub mod noise;
 pub mod terrain;
+pub mod terrain_chunk;
+pub mod terrain_generator;
+pub mod terrain_renderer;
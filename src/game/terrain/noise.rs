@@ -0,0 +1,115 @@
+/// Seeded 2D gradient (Perlin) noise.
+pub struct PerlinNoise {
+    permutation: [u8; 512],
+}
+
+impl PerlinNoise {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = oorandom::Rand32::new(seed);
+
+        let mut table: [u8; 256] = [0; 256];
+        for (i, value) in table.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        // Fisher-Yates shuffle
+        for i in (1..256).rev() {
+            let j = rng.rand_range(0..(i as u32 + 1)) as usize;
+            table.swap(i, j);
+        }
+
+        let mut permutation = [0; 512];
+        for i in 0..512 {
+            permutation[i] = table[i % 256];
+        }
+
+        Self { permutation }
+    }
+
+    /// Noise value at the given coordinates, roughly in the -1 to 1 range.
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let xi = x.floor() as i64 & 255;
+        let zi = z.floor() as i64 & 255;
+        let xf = x - x.floor();
+        let zf = z - z.floor();
+
+        let u = Self::fade(xf);
+        let v = Self::fade(zf);
+
+        let p = &self.permutation;
+        let xi = xi as usize;
+        let zi = zi as usize;
+        let h00 = p[p[xi] as usize + zi];
+        let h10 = p[p[xi + 1] as usize + zi];
+        let h01 = p[p[xi] as usize + zi + 1];
+        let h11 = p[p[xi + 1] as usize + zi + 1];
+
+        let n00 = Self::gradient(h00, xf, zf);
+        let n10 = Self::gradient(h10, xf - 1.0, zf);
+        let n01 = Self::gradient(h01, xf, zf - 1.0);
+        let n11 = Self::gradient(h11, xf - 1.0, zf - 1.0);
+
+        let nx0 = n00 + u * (n10 - n00);
+        let nx1 = n01 + u * (n11 - n01);
+
+        // Scale the theoretical sqrt(0.5) maximum up to 1
+        (nx0 + v * (nx1 - nx0)) * std::f32::consts::SQRT_2
+    }
+
+    /// Fractal Brownian motion, roughly in the -1 to 1 range.
+    pub fn fbm(&self, x: f32, z: f32, octaves: u32, lacunarity: f32, persistence: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut amplitude_sum = 0.0;
+        let mut frequency = 1.0;
+
+        for _ in 0..octaves {
+            sum += self.sample(x * frequency, z * frequency) * amplitude;
+            amplitude_sum += amplitude;
+            amplitude *= persistence;
+            frequency *= lacunarity;
+        }
+
+        sum / f32::max(amplitude_sum, f32::EPSILON)
+    }
+
+    /// Ridged multifractal, in the 0 to 1 range.
+    /// Sharp crests where the underlying noise crosses zero, used for mountain ranges.
+    pub fn ridged(&self, x: f32, z: f32, octaves: u32, lacunarity: f32, persistence: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut amplitude_sum = 0.0;
+        let mut frequency = 1.0;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            let ridge = 1.0 - f32::abs(self.sample(x * frequency, z * frequency));
+            let ridge = ridge * ridge * weight;
+            // Successive octaves are attenuated in valleys
+            weight = ridge.clamp(0.0, 1.0);
+
+            sum += ridge * amplitude;
+            amplitude_sum += amplitude;
+            amplitude *= persistence;
+            frequency *= lacunarity;
+        }
+
+        sum / f32::max(amplitude_sum, f32::EPSILON)
+    }
+
+    fn fade(t: f32) -> f32 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn gradient(hash: u8, x: f32, z: f32) -> f32 {
+        match hash & 7 {
+            0 => x + z,
+            1 => -x + z,
+            2 => x - z,
+            3 => -x - z,
+            4 => x,
+            5 => -x,
+            6 => z,
+            _ => -z,
+        }
+    }
+}
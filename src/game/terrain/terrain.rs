@@ -1,60 +1,155 @@
-use cgmath::{EuclideanSpace, Point3, Quaternion};
+use std::sync::Arc;
+
+use cgmath::{EuclideanSpace, One, Point3, Quaternion};
 
 use crate::{
+    assets::asset_manager::AssetMgr,
     game::collision::collider::ColliderMgr,
     game::{
-        collision::collider::ColliderType, mesh_renderer::MeshInstancedRendererMgr,
-        model::ModelMgr, transform::TransformMgr,
+        collision::collider::ColliderType,
+        mesh_renderer::MeshInstancedRendererMgr,
+        model::ModelMgr,
+        transform::{TransformMgr, UNIT_SCALE},
+    },
+    renderer::{
+        camera::Camera,
+        render_state::RenderState,
+        terrain_material::{TerrainBlendRules, TerrainLayer, TerrainMaterial, TERRAIN_LAYER_COUNT},
     },
-    renderer::render_state::RenderState,
+};
+
+use super::{
+    heightfield::{Heightfield, HeightfieldSettings},
+    terrain_chunk::{TerrainChunkStreamer, TerrainStreamingSettings},
+    terrain_renderer::TerrainRendererMgr,
 };
 
 pub struct Terrain {
     transform_i: usize,
-    collider_i: usize,
-    mesh_renderer_i: usize,
+    terrain_renderer_i: usize,
+
+    heightfield: Arc<Heightfield>,
+    streamer: TerrainChunkStreamer,
 }
 
 impl Terrain {
+    /// Creates a chunked terrain from a heightmap or from procedural noise.
+    /// Chunks are streamed around the camera in `update`.
+    /// Terrains aren't rotated, ground heights and collisions are along the world Y axis.
     pub async fn new(
-        model_path: &str,
+        heightfield_settings: &HeightfieldSettings,
+        streaming_settings: &TerrainStreamingSettings,
+        splat_map_path: Option<&str>,
+        layers: &[TerrainLayer<'_>; TERRAIN_LAYER_COUNT],
+        blend_rules: &TerrainBlendRules,
         position: Point3<f32>,
-        rotation: Quaternion<f32>,
         transform_mgr: &mut TransformMgr,
         collider_mgr: &mut ColliderMgr,
         model_mgr: &mut ModelMgr,
+        asset_mgr: &mut AssetMgr,
         mesh_renderer_mgr: &mut MeshInstancedRendererMgr,
+        terrain_renderer_mgr: &mut TerrainRendererMgr,
         render_state: &RenderState,
     ) -> Self {
-        let transform_i = transform_mgr.add(position, rotation);
+        // Usually generated in the background by the asset loader
+        let heightfield_i = asset_mgr
+            .load_heightfield(heightfield_settings)
+            .await
+            .unwrap();
+        let heightfield = asset_mgr.heightfields.get(heightfield_i).clone();
 
-        let model_i = model_mgr
-            .add_from_file(model_path, render_state, mesh_renderer_mgr)
-            .await;
+        let transform_i = transform_mgr.add(position, Quaternion::one(), UNIT_SCALE);
 
-        let collider_i = collider_mgr
-            .add_from_model(
-                model_i,
+        let half_size = heightfield.size / 2.0;
+        let (min_y, max_y) = heightfield.height_range();
+        collider_mgr
+            .add_from_bounds(
+                Point3::new(-half_size, min_y, -half_size),
+                Point3::new(half_size, max_y, half_size),
+                None,
                 transform_i,
-                ColliderType::Vertex,
+                ColliderType::Heightfield(heightfield.clone()),
                 false,
                 true,
                 true,
-                &render_state,
+                render_state,
                 transform_mgr,
                 model_mgr,
+                asset_mgr,
                 mesh_renderer_mgr,
             )
             .await
             .unwrap();
 
-        let mesh_renderer_i =
-            mesh_renderer_mgr.add(render_state, model_i, position.to_vec(), rotation);
+        let material = TerrainMaterial::load(
+            &heightfield_settings.name(),
+            splat_map_path,
+            layers,
+            blend_rules,
+            asset_mgr,
+            &render_state.device,
+            &render_state.queue,
+            &terrain_renderer_mgr.material_bind_group_layout,
+        )
+        .await
+        .unwrap();
+        let material_i = terrain_renderer_mgr.add_material(material);
+
+        let terrain_renderer_i = terrain_renderer_mgr.add(
+            render_state,
+            None,
+            material_i,
+            position.to_vec(),
+            Quaternion::one(),
+        );
+
+        let streamer = TerrainChunkStreamer::new(heightfield.clone(), streaming_settings);
 
         Self {
             transform_i,
-            collider_i,
-            mesh_renderer_i,
+            terrain_renderer_i,
+
+            heightfield,
+            streamer,
         }
     }
+
+    /// Streams terrain chunks around the camera.
+    pub fn update(
+        &mut self,
+        camera: &Camera,
+        transform_mgr: &TransformMgr,
+        terrain_renderer_mgr: &mut TerrainRendererMgr,
+        render_state: &RenderState,
+    ) {
+        let position = transform_mgr.position(self.transform_i);
+        let camera_position = Point3::from_vec(camera.position - position);
+
+        self.streamer.update(
+            camera_position,
+            self.terrain_renderer_i,
+            terrain_renderer_mgr,
+            render_state,
+        );
+    }
+
+    /// Returns `true` if all requested chunks have been streamed in.
+    pub fn is_streaming_idle(&self) -> bool {
+        self.streamer.is_idle()
+    }
+
+    /// Distance from the camera at which terrain chunks are unloaded.
+    pub fn view_distance(&self) -> f32 {
+        self.streamer.view_distance()
+    }
+
+    /// Ground height at world XZ coordinates.
+    /// Returns `None` outside of the terrain.
+    pub fn height_at(&self, transform_mgr: &TransformMgr, x: f32, z: f32) -> Option<f32> {
+        let position = transform_mgr.position(self.transform_i);
+
+        self.heightfield
+            .height_at(x - position.x, z - position.z)
+            .map(|height| height + position.y)
+    }
 }
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use cgmath::Point3;
+use wgpu::util::DeviceExt;
+
+use crate::renderer::{model, render_state::RenderState};
+
+use super::{heightfield::Heightfield, terrain_renderer::TerrainRendererMgr};
+
+/// Chunk grid coordinates, in chunks from the heightfield origin sample.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkCoord {
+    pub x: u32,
+    pub z: u32,
+}
+
+/// Terrain chunking, level of detail and streaming settings.
+pub struct TerrainStreamingSettings {
+    /// Grid cells per chunk side at full detail.
+    /// Must divide `resolution - 1` and be a multiple of `2^max_lod`.
+    pub chunk_cells: u32,
+    /// Chunks farther than this distance (in chunks) from the camera are unloaded.
+    pub view_distance_chunks: f32,
+    /// Distance (in chunks) covered by each level of detail.
+    pub lod_distance_chunks: f32,
+    /// Coarsest level of detail, each level halves the vertices per side.
+    pub max_lod: u32,
+    /// Depth of the skirts hiding cracks between chunks with different LOD.
+    pub skirt_depth: f32,
+    /// Amount of background threads generating chunk meshes.
+    pub worker_count: usize,
+}
+
+struct ChunkRequest {
+    coord: ChunkCoord,
+    lod: u32,
+}
+
+struct ChunkResult {
+    coord: ChunkCoord,
+    lod: u32,
+    vertices: Vec<model::ModelVertex>,
+    indices: Vec<u32>,
+}
+
+/// Loads and unloads terrain chunks around the camera.
+/// Chunk meshes are generated on worker threads, GPU buffers are created on the main thread.
+pub struct TerrainChunkStreamer {
+    heightfield: Arc<Heightfield>,
+
+    chunk_cells: u32,
+    chunk_count: u32,
+    view_distance_chunks: f32,
+    lod_distance_chunks: f32,
+    max_lod: u32,
+
+    /// Level of detail of the chunks currently in the terrain renderer.
+    loaded: HashMap<ChunkCoord, u32>,
+    /// Level of detail of the chunks being generated.
+    pending: HashMap<ChunkCoord, u32>,
+
+    request_sender: Option<Sender<ChunkRequest>>,
+    result_receiver: Receiver<ChunkResult>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl TerrainChunkStreamer {
+    pub fn new(heightfield: Arc<Heightfield>, settings: &TerrainStreamingSettings) -> Self {
+        let cells = heightfield.resolution - 1;
+        assert!(
+            cells.is_multiple_of(settings.chunk_cells),
+            "Chunk cells must divide the heightfield cells"
+        );
+        assert!(
+            settings.chunk_cells.is_multiple_of(1 << settings.max_lod),
+            "Chunk cells must be a multiple of the coarsest LOD step"
+        );
+
+        let (request_sender, request_receiver) = mpsc::channel::<ChunkRequest>();
+        let (result_sender, result_receiver) = mpsc::channel::<ChunkResult>();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+
+        let mut workers = Vec::with_capacity(settings.worker_count);
+        for i in 0..usize::max(settings.worker_count, 1) {
+            let heightfield = heightfield.clone();
+            let request_receiver = request_receiver.clone();
+            let result_sender = result_sender.clone();
+            let chunk_cells = settings.chunk_cells;
+            let skirt_depth = settings.skirt_depth;
+
+            let worker = thread::Builder::new()
+                .name(format!("Terrain chunk worker {}", i))
+                .spawn(move || loop {
+                    // The lock is released as soon as a request is taken.
+                    let request = request_receiver.lock().unwrap().recv();
+                    let request = match request {
+                        Ok(request) => request,
+                        // The streamer has been dropped
+                        Err(_) => break,
+                    };
+
+                    let (vertices, indices) = heightfield.build_region_mesh_data(
+                        request.coord.x * chunk_cells,
+                        request.coord.z * chunk_cells,
+                        chunk_cells,
+                        1 << request.lod,
+                        Some(skirt_depth),
+                    );
+
+                    let result = ChunkResult {
+                        coord: request.coord,
+                        lod: request.lod,
+                        vertices,
+                        indices,
+                    };
+                    if result_sender.send(result).is_err() {
+                        break;
+                    }
+                })
+                .unwrap();
+            workers.push(worker);
+        }
+
+        Self {
+            heightfield,
+
+            chunk_cells: settings.chunk_cells,
+            chunk_count: cells / settings.chunk_cells,
+            view_distance_chunks: settings.view_distance_chunks,
+            lod_distance_chunks: settings.lod_distance_chunks,
+            max_lod: settings.max_lod,
+
+            loaded: HashMap::new(),
+            pending: HashMap::new(),
+
+            request_sender: Some(request_sender),
+            result_receiver,
+            workers,
+        }
+    }
+
+    /// World size of a chunk side.
+    pub fn chunk_size(&self) -> f32 {
+        self.chunk_cells as f32 * self.heightfield.spacing()
+    }
+
+    /// World distance at which chunks are unloaded.
+    pub fn view_distance(&self) -> f32 {
+        self.view_distance_chunks * self.chunk_size()
+    }
+
+    /// Requests the chunks around `camera_position`, unloads far chunks and uploads finished ones.
+    /// `camera_position` is in terrain local space.
+    pub fn update(
+        &mut self,
+        camera_position: Point3<f32>,
+        terrain_renderer_i: usize,
+        terrain_renderer_mgr: &mut TerrainRendererMgr,
+        render_state: &RenderState,
+    ) {
+        // Upload finished chunks
+        while let Ok(result) = self.result_receiver.try_recv() {
+            self.pending.remove(&result.coord);
+
+            if self.desired_lod(result.coord, camera_position).is_none() {
+                continue;
+            }
+
+            let mesh =
+                self.create_chunk_mesh(result.coord, result.vertices, result.indices, render_state);
+            terrain_renderer_mgr.set_chunk_mesh(terrain_renderer_i, result.coord, mesh);
+            self.loaded.insert(result.coord, result.lod);
+        }
+
+        // Unload far chunks
+        let mut unloaded = Vec::new();
+        for coord in self.loaded.keys() {
+            if self.desired_lod(*coord, camera_position).is_none() {
+                unloaded.push(*coord);
+            }
+        }
+        for coord in unloaded {
+            self.loaded.remove(&coord);
+            terrain_renderer_mgr.remove_chunk_mesh(terrain_renderer_i, coord);
+        }
+
+        // Request missing chunks and LOD changes
+        let half_size = self.heightfield.size / 2.0;
+        let center_x = ((camera_position.x + half_size) / self.chunk_size()).floor() as i64;
+        let center_z = ((camera_position.z + half_size) / self.chunk_size()).floor() as i64;
+        let radius = self.view_distance_chunks.ceil() as i64;
+        let max = self.chunk_count as i64 - 1;
+
+        for z in i64::max(center_z - radius, 0)..=i64::min(center_z + radius, max) {
+            for x in i64::max(center_x - radius, 0)..=i64::min(center_x + radius, max) {
+                let coord = ChunkCoord {
+                    x: x as u32,
+                    z: z as u32,
+                };
+
+                let lod = match self.desired_lod(coord, camera_position) {
+                    Some(lod) => lod,
+                    None => continue,
+                };
+                if self.pending.contains_key(&coord) || self.loaded.get(&coord) == Some(&lod) {
+                    continue;
+                }
+
+                if let Some(request_sender) = &self.request_sender {
+                    request_sender.send(ChunkRequest { coord, lod }).unwrap();
+                    self.pending.insert(coord, lod);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if no chunk meshes are being generated.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Level of detail for a chunk, `None` if the chunk is out of view distance.
+    fn desired_lod(&self, coord: ChunkCoord, camera_position: Point3<f32>) -> Option<u32> {
+        let half_size = self.heightfield.size / 2.0;
+        let center_x = (coord.x as f32 + 0.5) * self.chunk_size() - half_size;
+        let center_z = (coord.z as f32 + 0.5) * self.chunk_size() - half_size;
+
+        let dx = center_x - camera_position.x;
+        let dz = center_z - camera_position.z;
+        let distance_chunks = f32::sqrt(dx * dx + dz * dz) / self.chunk_size();
+
+        if distance_chunks > self.view_distance_chunks {
+            return None;
+        }
+
+        let lod = (distance_chunks / self.lod_distance_chunks) as u32;
+        Some(u32::min(lod, self.max_lod))
+    }
+
+    fn create_chunk_mesh(
+        &self,
+        coord: ChunkCoord,
+        vertices: Vec<model::ModelVertex>,
+        indices: Vec<u32>,
+        render_state: &RenderState,
+    ) -> model::Mesh {
+        let name = format!("Terrain chunk {} {}", coord.x, coord.z);
+
+        let vertex_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", name)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+        let index_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", name)),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                });
+
+        let half_size = self.heightfield.size / 2.0;
+        let min_x = coord.x as f32 * self.chunk_size() - half_size;
+        let min_z = coord.z as f32 * self.chunk_size() - half_size;
+        let (min_y, max_y) = vertices.iter().fold((f32::MAX, f32::MIN), |(min, max), v| {
+            (f32::min(min, v.position[1]), f32::max(max, v.position[1]))
+        });
+
+        model::Mesh {
+            name,
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material: 0,
+
+            vertices,
+            is_wireframe: false,
+
+            min_x,
+            min_y,
+            min_z,
+            max_x: min_x + self.chunk_size(),
+            max_y,
+            max_z: min_z + self.chunk_size(),
+        }
+    }
+}
+
+impl Drop for TerrainChunkStreamer {
+    fn drop(&mut self) {
+        // Closing the request channel stops the workers
+        self.request_sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
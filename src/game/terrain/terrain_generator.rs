@@ -0,0 +1,110 @@
+use super::{heightfield::Heightfield, noise::PerlinNoise};
+
+/// Flattened rectangular area, e.g. for factories and runways.
+/// Coordinates are in terrain local space.
+#[derive(Clone)]
+pub struct TerrainPlateau {
+    pub center_x: f32,
+    pub center_z: f32,
+    pub half_width: f32,
+    pub half_length: f32,
+    /// Distance over which the plateau blends into the surrounding terrain.
+    pub falloff: f32,
+    /// Plateau height, before `vertical_scale`. Uses the generated height at the center if `None`.
+    pub height: Option<f32>,
+}
+
+/// Procedural landscape settings.
+#[derive(Clone)]
+pub struct TerrainNoiseSettings {
+    /// Amount of noise layers, each adding finer detail.
+    pub octaves: u32,
+    /// Base noise frequency, in cycles per world unit.
+    pub frequency: f32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves.
+    pub persistence: f32,
+    /// How much ridged mountains replace rolling hills, from 0 to 1.
+    pub mountain_weight: f32,
+    /// Frequency of the mask deciding where mountain ranges appear.
+    pub mountain_frequency: f32,
+    /// Height redistribution exponent. Values above 1 widen and flatten valleys.
+    pub valley_exponent: f32,
+    pub plateaus: Vec<TerrainPlateau>,
+}
+
+/// Generates a `resolution` x `resolution` heightfield from fractal noise.
+/// The same seed and settings always produce the same landscape.
+pub fn generate_heightfield(
+    settings: &TerrainNoiseSettings,
+    seed: u64,
+    resolution: u32,
+    horizontal_scale: f32,
+    vertical_scale: f32,
+) -> Heightfield {
+    let noise = PerlinNoise::new(seed);
+    // Independent noise for the mountain mask, so ranges don't follow the hills.
+    let mask_noise = PerlinNoise::new(seed.wrapping_add(1));
+
+    let base_height = |x: f32, z: f32| {
+        let hills = noise.fbm(
+            x * settings.frequency,
+            z * settings.frequency,
+            settings.octaves,
+            settings.lacunarity,
+            settings.persistence,
+        ) * 0.5
+            + 0.5;
+        let mountains = noise.ridged(
+            x * settings.frequency,
+            z * settings.frequency,
+            settings.octaves,
+            settings.lacunarity,
+            settings.persistence,
+        );
+
+        let mask = mask_noise.sample(
+            x * settings.mountain_frequency,
+            z * settings.mountain_frequency,
+        );
+        let mask = smoothstep(-0.2, 0.4, mask) * settings.mountain_weight;
+
+        let height = hills + (mountains - hills) * mask;
+        height.clamp(0.0, 1.0).powf(settings.valley_exponent)
+    };
+
+    let plateau_heights: Vec<f32> = settings
+        .plateaus
+        .iter()
+        .map(|plateau| {
+            plateau
+                .height
+                .unwrap_or_else(|| base_height(plateau.center_x, plateau.center_z))
+        })
+        .collect();
+
+    Heightfield::from_fn(resolution, horizontal_scale, |x, z| {
+        let mut height = base_height(x, z);
+
+        for (plateau, plateau_height) in settings.plateaus.iter().zip(plateau_heights.iter()) {
+            let dx = f32::max(f32::abs(x - plateau.center_x) - plateau.half_width, 0.0);
+            let dz = f32::max(f32::abs(z - plateau.center_z) - plateau.half_length, 0.0);
+            let distance = f32::sqrt(dx * dx + dz * dz);
+
+            let t = smoothstep(0.0, plateau.falloff, distance);
+            height = plateau_height + (height - plateau_height) * t;
+        }
+
+        height * vertical_scale
+    })
+}
+
+fn smoothstep(edge_0: f32, edge_1: f32, x: f32) -> f32 {
+    if edge_1 <= edge_0 {
+        return if x < edge_0 { 0.0 } else { 1.0 };
+    }
+
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use cgmath::{Quaternion, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    assets::{asset_cache::Handle, asset_manager::AssetMgr},
+    game::{model::ModelMgr, transform::UNIT_SCALE},
+    renderer::{
+        model::{self, Model},
+        render_state::{
+            create_render_pipeline_from_source, create_terrain_material_bind_group_layout,
+            RenderState,
+        },
+        terrain_material::TerrainMaterial,
+        texture,
+        vertex::Vertex,
+    },
+    resources::ShaderSource,
+};
+
+use super::terrain_chunk::ChunkCoord;
+
+const MAX_INSTANCE_COUNT: usize = 16;
+
+const SHADER_FILE: &str = "shaders/terrain.wgsl";
+
+/// Renders terrain models and streamed terrain chunks with a splat-mapped `TerrainMaterial`.
+/// Must be rendered after the mesh renderer, as it loads the color and depth attachments.
+pub struct TerrainRendererMgr {
+    model_i: Vec<Option<Handle<Model>>>,
+    material_i: Vec<usize>,
+    chunk_mesh: Vec<HashMap<ChunkCoord, model::Mesh>>,
+
+    instance_raw: Vec<model::InstanceRaw>,
+    instance_buffer: Vec<wgpu::Buffer>,
+
+    material: Vec<TerrainMaterial>,
+
+    pub material_bind_group_layout: wgpu::BindGroupLayout,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    shader: Handle<ShaderSource>,
+}
+
+impl TerrainRendererMgr {
+    pub fn new(render_state: &mut RenderState) -> Self {
+        let material_bind_group_layout =
+            create_terrain_material_bind_group_layout(&render_state.device);
+        let render_pipeline_layout =
+            render_state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Terrain render pipeline layout"),
+                    bind_group_layouts: &[
+                        &material_bind_group_layout,
+                        &render_state.camera_bind_group_layout,
+                        &render_state.light_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+
+        let shader = render_state.shader_mgr.load(SHADER_FILE).unwrap();
+        let render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
+
+        Self {
+            model_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            material_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            chunk_mesh: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            instance_raw: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            instance_buffer: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            material: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            material_bind_group_layout,
+            render_pipeline_layout,
+            render_pipeline,
+            shader,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        create_render_pipeline_from_source(
+            device,
+            render_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+            Some(texture::Texture::DEPTH_FORMAT),
+            true,
+            sample_count,
+            &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+            shader,
+            Some(wgpu::Face::Back),
+        )
+    }
+
+    /// Rebuilds the render pipeline if its shader was reloaded.
+    pub fn reload_shaders(&mut self, render_state: &mut RenderState) {
+        let device = &render_state.device;
+        let sample_count = render_state.sample_count;
+        render_state
+            .shader_mgr
+            .reload_pipeline(self.shader, &mut self.render_pipeline, |shader| {
+                Self::create_pipeline(device, &self.render_pipeline_layout, sample_count, shader)
+            });
+    }
+
+    /// Rebuilds the materials using reloaded textures.
+    pub fn reload_textures(
+        &mut self,
+        reloaded_textures: &[Handle<texture::Texture>],
+        asset_mgr: &AssetMgr,
+        render_state: &RenderState,
+    ) {
+        for material in self.material.iter_mut() {
+            material.reload_textures(
+                reloaded_textures,
+                asset_mgr,
+                &render_state.device,
+                &self.material_bind_group_layout,
+            );
+        }
+    }
+
+    /// Returns material index
+    pub fn add_material(&mut self, material: TerrainMaterial) -> usize {
+        self.material.push(material);
+
+        self.material.len() - 1
+    }
+
+    /// Returns instance index.
+    /// Terrains without a model are drawn only through their chunk meshes.
+    pub fn add(
+        &mut self,
+        render_state: &RenderState,
+        model_i: Option<Handle<Model>>,
+        material_i: usize,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+    ) -> usize {
+        self.model_i.push(model_i);
+        self.material_i.push(material_i);
+        self.chunk_mesh.push(HashMap::new());
+
+        let instance_raw = model::InstanceRaw::new(position, rotation, UNIT_SCALE);
+        self.instance_raw.push(instance_raw);
+
+        let instance_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Terrain instance buffer"),
+                    contents: bytemuck::cast_slice(&[instance_raw]),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+        self.instance_buffer.push(instance_buffer);
+
+        self.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.model_i.len()
+    }
+
+    /// Adds or replaces a chunk mesh, in terrain local space.
+    pub fn set_chunk_mesh(&mut self, index: usize, coord: ChunkCoord, mesh: model::Mesh) {
+        self.chunk_mesh[index].insert(coord, mesh);
+    }
+
+    pub fn remove_chunk_mesh(&mut self, index: usize, coord: ChunkCoord) {
+        self.chunk_mesh[index].remove(&coord);
+    }
+
+    pub fn render(
+        &mut self,
+        model_mgr: &ModelMgr,
+        render_state: &RenderState,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> Result<(), wgpu::SurfaceError> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Terrain render pass"),
+            color_attachments: &[Some(
+                render_state.color_attachment(view, wgpu::LoadOp::Load),
+            )],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &render_state.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.render_pipeline);
+
+        for i in 0..self.len() {
+            let material = &self.material[self.material_i[i]];
+
+            render_pass.set_vertex_buffer(1, self.instance_buffer[i].slice(..));
+            render_pass.set_bind_group(0, &material.bind_group, &[]);
+            render_pass.set_bind_group(1, &render_state.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, &render_state.light_bind_group, &[]);
+
+            let model_meshes = match self.model_i[i] {
+                Some(model_i) => model_mgr.get(model_i).meshes.iter(),
+                None => [].iter(),
+            };
+            for mesh in model_meshes.chain(self.chunk_mesh[i].values()) {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+pub mod ribbon_trail;
+pub mod trail_renderer;
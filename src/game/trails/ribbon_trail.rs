@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use cgmath::{EuclideanSpace, Point3, Vector3};
+
+use crate::game::transform::TransformMgr;
+
+const MAX_TRAIL_COUNT: usize = 512;
+
+#[derive(Clone, Copy)]
+pub struct RibbonTrailSettings {
+    /// Seconds a point stays in the trail.
+    pub lifetime: f32,
+    /// Seconds between recorded points.
+    pub sample_interval: f32,
+
+    /// Linear RGBA, interpolated over the point age.
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    /// World units, interpolated over the point age.
+    pub start_width: f32,
+    pub end_width: f32,
+}
+
+/// Ribbon trails attached to transforms, such as wingtip and engine contrails.
+/// Each trail keeps its recent positions in a ring buffer, in world space.
+pub struct RibbonTrailMgr {
+    pub settings: Vec<RibbonTrailSettings>,
+    pub is_emitting: Vec<bool>,
+    /// Offset from the transform position, in its local space.
+    /// Scaled along with the transform.
+    offset: Vec<Vector3<f32>>,
+    sample_accumulator: Vec<f32>,
+    /// Emitter position in the last update, the trail is drawn up to it while emitting.
+    pub head_position: Vec<Point3<f32>>,
+
+    pub transform_i: Vec<usize>,
+
+    /// Ring buffers, sized for the trail lifetime.
+    point_position: Vec<Vec<Point3<f32>>>,
+    point_age: Vec<Vec<f32>>,
+    /// Where the next point is written.
+    next_point_i: Vec<usize>,
+    point_count: Vec<usize>,
+}
+
+impl RibbonTrailMgr {
+    pub fn new() -> Self {
+        Self {
+            settings: Vec::with_capacity(MAX_TRAIL_COUNT),
+            is_emitting: Vec::with_capacity(MAX_TRAIL_COUNT),
+            offset: Vec::with_capacity(MAX_TRAIL_COUNT),
+            sample_accumulator: Vec::with_capacity(MAX_TRAIL_COUNT),
+            head_position: Vec::with_capacity(MAX_TRAIL_COUNT),
+
+            transform_i: Vec::with_capacity(MAX_TRAIL_COUNT),
+
+            point_position: Vec::with_capacity(MAX_TRAIL_COUNT),
+            point_age: Vec::with_capacity(MAX_TRAIL_COUNT),
+            next_point_i: Vec::with_capacity(MAX_TRAIL_COUNT),
+            point_count: Vec::with_capacity(MAX_TRAIL_COUNT),
+        }
+    }
+
+    /// Returns trail index
+    pub fn add(
+        &mut self,
+        settings: &RibbonTrailSettings,
+        is_emitting: bool,
+        transform_i: usize,
+        offset: Vector3<f32>,
+        transform_mgr: &TransformMgr,
+    ) -> usize {
+        // Enough points for a full lifetime, plus the one about to expire
+        let capacity = (settings.lifetime / settings.sample_interval).ceil() as usize + 1;
+
+        self.settings.push(*settings);
+        self.is_emitting.push(is_emitting);
+        self.offset.push(offset);
+        self.sample_accumulator.push(0.0);
+        self.head_position
+            .push(Self::emitter_position(transform_mgr, transform_i, offset));
+
+        self.transform_i.push(transform_i);
+
+        self.point_position
+            .push(vec![Point3::new(0.0, 0.0, 0.0); capacity]);
+        self.point_age.push(vec![0.0; capacity]);
+        self.next_point_i.push(0);
+        self.point_count.push(0);
+
+        self.len() - 1
+    }
+
+    /// Returns the amount of trails.
+    pub fn len(&self) -> usize {
+        self.settings.len()
+    }
+
+    /// Removes every point, so a teleported transform doesn't leave a trail behind.
+    pub fn clear(&mut self, index: usize) {
+        self.point_count[index] = 0;
+        self.sample_accumulator[index] = 0.0;
+    }
+
+    fn emitter_position(
+        transform_mgr: &TransformMgr,
+        transform_i: usize,
+        offset: Vector3<f32>,
+    ) -> Point3<f32> {
+        transform_mgr.local_to_world(transform_i, Point3::from_vec(offset))
+    }
+
+    pub fn update(&mut self, transform_mgr: &TransformMgr, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        for i in 0..self.len() {
+            let settings = self.settings[i];
+
+            for age in &mut self.point_age[i] {
+                *age += dt;
+            }
+            // The oldest points expire first
+            while self.point_count[i] > 0
+                && self.point_age[i][self.ring_i(i, self.point_count[i] - 1)] >= settings.lifetime
+            {
+                self.point_count[i] -= 1;
+            }
+
+            let position =
+                Self::emitter_position(transform_mgr, self.transform_i[i], self.offset[i]);
+            self.head_position[i] = position;
+
+            if !self.is_emitting[i] {
+                self.sample_accumulator[i] = 0.0;
+                continue;
+            }
+
+            self.sample_accumulator[i] += dt;
+            if self.point_count[i] == 0 || self.sample_accumulator[i] >= settings.sample_interval {
+                self.sample_accumulator[i] = 0.0;
+                self.push_point(i, position);
+            }
+        }
+    }
+
+    /// Overwrites the oldest point when the ring buffer is full.
+    fn push_point(&mut self, index: usize, position: Point3<f32>) {
+        let capacity = self.point_position[index].len();
+        let point_i = self.next_point_i[index];
+
+        self.point_position[index][point_i] = position;
+        self.point_age[index][point_i] = 0.0;
+        self.next_point_i[index] = (point_i + 1) % capacity;
+        self.point_count[index] = usize::min(self.point_count[index] + 1, capacity);
+    }
+
+    /// Ring buffer index of the `nth` newest point.
+    fn ring_i(&self, index: usize, nth: usize) -> usize {
+        let capacity = self.point_position[index].len();
+        (self.next_point_i[index] + capacity - 1 - nth) % capacity
+    }
+
+    /// Recorded points and their ages in seconds, newest first.
+    pub fn points(&self, index: usize) -> impl Iterator<Item = (Point3<f32>, f32)> + '_ {
+        (0..self.point_count[index]).map(move |nth| {
+            let point_i = self.ring_i(index, nth);
+            (
+                self.point_position[index][point_i],
+                self.point_age[index][point_i],
+            )
+        })
+    }
+
+    /// Color of a point of `age` seconds.
+    pub fn color(&self, index: usize, age: f32) -> [f32; 4] {
+        let settings = &self.settings[index];
+        let t = f32::min(age / settings.lifetime, 1.0);
+
+        let mut color = settings.start_color;
+        for (channel, end) in color.iter_mut().zip(settings.end_color) {
+            *channel += (end - *channel) * t;
+        }
+
+        color
+    }
+
+    /// Width of a point of `age` seconds.
+    pub fn width(&self, index: usize, age: f32) -> f32 {
+        let settings = &self.settings[index];
+        let t = f32::min(age / settings.lifetime, 1.0);
+
+        settings.start_width + (settings.end_width - settings.start_width) * t
+    }
+}
@@ -0,0 +1,235 @@
+use std::mem::size_of;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::{
+    assets::asset_cache::Handle,
+    renderer::{
+        render_state::{create_render_pipeline_from_source, RenderState},
+        texture,
+        vertex::Vertex,
+    },
+    resources::ShaderSource,
+};
+
+use super::ribbon_trail::RibbonTrailMgr;
+
+const SHADER_FILE: &str = "shaders/ribbon_trail.wgsl";
+
+const MAX_VERTEX_COUNT: usize = 65536;
+/// Two triangles per segment between trail points.
+const VERTICES_PER_SEGMENT: usize = 6;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TrailVertex {
+    /// Side goes from -1 to 1 across the ribbon.
+    position_side: [f32; 4],
+    color: [f32; 4],
+}
+
+impl Vertex for TrailVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<TrailVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                // Position and side
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Color
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Renders ribbon trails as camera facing strips, rebuilt every frame.
+/// Alpha blended and depth tested against the scene without writing depth.
+pub struct TrailRendererMgr {
+    vertices: Vec<TrailVertex>,
+    vertex_buffer: wgpu::Buffer,
+
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    shader: Handle<ShaderSource>,
+}
+
+impl TrailRendererMgr {
+    pub fn new(render_state: &mut RenderState) -> Self {
+        let device = &render_state.device;
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail vertex buffer"),
+            size: (MAX_VERTEX_COUNT * size_of::<TrailVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Trail render pipeline layout"),
+                bind_group_layouts: &[&render_state.camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let shader = render_state.shader_mgr.load(SHADER_FILE).unwrap();
+        let render_pipeline = Self::create_pipeline(
+            &render_state.device,
+            &render_pipeline_layout,
+            render_state.sample_count,
+            render_state.shader_mgr.get(shader),
+        )
+        .unwrap();
+
+        Self {
+            vertices: Vec::with_capacity(MAX_VERTEX_COUNT),
+            vertex_buffer,
+
+            render_pipeline_layout,
+            render_pipeline,
+            shader,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        create_render_pipeline_from_source(
+            device,
+            render_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            wgpu::BlendState::ALPHA_BLENDING,
+            Some(texture::Texture::DEPTH_FORMAT),
+            false,
+            sample_count,
+            &[TrailVertex::desc()],
+            shader,
+            // Ribbons twist, both sides are visible
+            None,
+        )
+    }
+
+    /// Rebuilds the render pipeline if its shader was reloaded.
+    pub fn reload_shaders(&mut self, render_state: &mut RenderState) {
+        let device = &render_state.device;
+        let sample_count = render_state.sample_count;
+        render_state
+            .shader_mgr
+            .reload_pipeline(self.shader, &mut self.render_pipeline, |shader| {
+                Self::create_pipeline(device, &self.render_pipeline_layout, sample_count, shader)
+            });
+    }
+
+    /// Builds the strips of `ribbon_trail_mgr`, facing the camera.
+    pub fn update(&mut self, ribbon_trail_mgr: &RibbonTrailMgr, render_state: &RenderState) {
+        let camera_position = render_state.camera.position;
+
+        self.vertices.clear();
+        let mut points = Vec::new();
+        for trail_i in 0..ribbon_trail_mgr.len() {
+            // Emitting trails reach up to the emitter
+            points.clear();
+            if ribbon_trail_mgr.is_emitting[trail_i] {
+                points.push((ribbon_trail_mgr.head_position[trail_i], 0.0));
+            }
+            points.extend(ribbon_trail_mgr.points(trail_i));
+            if points.len() < 2 {
+                continue;
+            }
+
+            let mut last_side = Vector3::unit_y();
+            let mut last_edge: Option<[TrailVertex; 2]> = None;
+            for (point_i, (position, age)) in points.iter().copied().enumerate() {
+                let previous = points[point_i.saturating_sub(1)].0;
+                let next = points[usize::min(point_i + 1, points.len() - 1)].0;
+
+                let side = (previous - next).cross(camera_position - position);
+                // Keeps the last side where the trail points at the camera
+                if side.magnitude2() > f32::EPSILON {
+                    last_side = side.normalize();
+                }
+
+                let half_width = ribbon_trail_mgr.width(trail_i, age) * 0.5;
+                let color = ribbon_trail_mgr.color(trail_i, age);
+                let edge = [-1.0, 1.0].map(|side| {
+                    Self::vertex(position + last_side * half_width * side, side, color)
+                });
+
+                if let Some(last_edge) = last_edge {
+                    if self.vertices.len() + VERTICES_PER_SEGMENT > MAX_VERTEX_COUNT {
+                        break;
+                    }
+                    self.vertices.extend_from_slice(&[
+                        last_edge[0],
+                        last_edge[1],
+                        edge[1],
+                        last_edge[0],
+                        edge[1],
+                        edge[0],
+                    ]);
+                }
+                last_edge = Some(edge);
+            }
+        }
+
+        render_state.queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&self.vertices),
+        );
+    }
+
+    fn vertex(position: Point3<f32>, side: f32, color: [f32; 4]) -> TrailVertex {
+        TrailVertex {
+            position_side: [position.x, position.y, position.z, side],
+            color,
+        }
+    }
+
+    /// Must be rendered after the opaque passes, as it keeps their depth.
+    pub fn render(
+        &mut self,
+        render_state: &RenderState,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> Result<(), wgpu::SurfaceError> {
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Trail render pass"),
+            color_attachments: &[Some(
+                render_state.color_attachment(view, wgpu::LoadOp::Load),
+            )],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &render_state.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
+        render_pass.draw(0..self.vertices.len() as u32, 0..1);
+
+        Ok(())
+    }
+}
@@ -1,11 +1,61 @@
-use cgmath::{Euler, InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use std::cell::Cell;
+
+use anyhow::{bail, Result};
+use cgmath::{
+    ElementWise, EuclideanSpace, Euler, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation,
+    Rotation3, Transform, Vector3,
+};
 const MAX_INSTANCE_COUNT: usize = 128;
 
-// OPTIMIZE: Rotation calculations are allocating new Quaternions and Eulers all the time.
-//           An approach could be caching up, right, forward, pitch, yaw, roll values and keeping the dirty state.
+/// Scale that keeps the model size.
+pub const UNIT_SCALE: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0);
+/// Smallest scale per axis, so normal matrices and collider bounds stay finite.
+pub const MIN_SCALE: f32 = 1e-6;
+
+/// Local axes of a rotation, in world space.
+#[derive(Clone, Copy)]
+struct Basis {
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    forward: Vector3<f32>,
+}
+
+impl Basis {
+    fn from_rotation(rotation: Quaternion<f32>) -> Self {
+        Self {
+            right: rotation.rotate_vector(Vector3::unit_x()).normalize(),
+            up: rotation.rotate_vector(Vector3::unit_y()).normalize(),
+            forward: rotation.rotate_vector(Vector3::unit_z()).normalize(),
+        }
+    }
+}
+
+/// Transforms, optionally parented to another transform.
+/// `position`, `rotation` and `scale` are in world space. Child transforms are driven by their
+/// local transform instead, and their world transform is recalculated in `update`.
+///
+/// Basis vectors, euler angles and world matrices are cached on first use.
+/// The world transform is only written through `set_position`, `set_rotation` and `set_scale`,
+/// or the methods built on them, so the cached values are invalidated.
 pub struct TransformMgr {
-    pub position: Vec<Point3<f32>>,
-    pub rotation: Vec<Quaternion<f32>>,
+    position: Vec<Point3<f32>>,
+    rotation: Vec<Quaternion<f32>>,
+    /// Per axis, in local space. Negative values mirror.
+    scale: Vec<Vector3<f32>>,
+
+    /// `None` when invalidated by a rotation change.
+    basis: Vec<Cell<Option<Basis>>>,
+    euler: Vec<Cell<Option<Euler<Rad<f32>>>>>,
+    /// `None` when invalidated by any transform change.
+    world_matrix: Vec<Cell<Option<Matrix4<f32>>>>,
+
+    /// Relative to the parent, unused by root transforms.
+    pub local_position: Vec<Point3<f32>>,
+    pub local_rotation: Vec<Quaternion<f32>>,
+    pub local_scale: Vec<Vector3<f32>>,
+
+    parent_i: Vec<Option<usize>>,
+    children_i: Vec<Vec<usize>>,
 }
 
 impl TransformMgr {
@@ -13,23 +63,234 @@ impl TransformMgr {
         TransformMgr {
             position: Vec::with_capacity(MAX_INSTANCE_COUNT),
             rotation: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            scale: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            basis: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            euler: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            world_matrix: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            local_position: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            local_rotation: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            local_scale: Vec::with_capacity(MAX_INSTANCE_COUNT),
+
+            parent_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
+            children_i: Vec::with_capacity(MAX_INSTANCE_COUNT),
         }
     }
 
+    /// Adds a root transform.
+    /// Panics if a scale component is closer to zero than `MIN_SCALE`.
     /// Returns instance index
-    pub fn add<V: Into<Point3<f32>>>(&mut self, position: V, rotation: Quaternion<f32>) -> usize {
-        self.position.push(position.into());
+    pub fn add<V: Into<Point3<f32>>>(
+        &mut self,
+        position: V,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> usize {
+        Self::check_scale(scale);
+
+        let position = position.into();
+        self.position.push(position);
         self.rotation.push(rotation);
+        self.scale.push(scale);
+
+        self.basis.push(Cell::new(None));
+        self.euler.push(Cell::new(None));
+        self.world_matrix.push(Cell::new(None));
+
+        self.local_position.push(position);
+        self.local_rotation.push(rotation);
+        self.local_scale.push(scale);
+
+        self.parent_i.push(None);
+        self.children_i.push(Vec::new());
 
         self.len() - 1
     }
 
+    /// Adds a transform relative to `parent_i`, with its world transform already calculated.
+    /// Returns instance index
+    pub fn add_child<V: Into<Point3<f32>>>(
+        &mut self,
+        parent_i: usize,
+        local_position: V,
+        local_rotation: Quaternion<f32>,
+        local_scale: Vector3<f32>,
+    ) -> usize {
+        let index = self.add(local_position, local_rotation, local_scale);
+        self.parent_i[index] = Some(parent_i);
+        self.children_i[parent_i].push(index);
+        self.update_world_transform(index);
+
+        index
+    }
+
     /// Returns the amount of managed instances.
     pub fn len(&self) -> usize {
         self.position.len()
     }
 
-    pub fn update(&mut self) {}
+    // Hierarchy API for attaching and detaching at runtime, the scene only uses `add_child` so far
+    #[allow(dead_code)]
+    pub fn parent(&self, index: usize) -> Option<usize> {
+        self.parent_i[index]
+    }
+
+    /// Moves the transform under `parent_i`, or makes it a root with `None`.
+    /// The world transform is kept, the local transform is recalculated from it.
+    /// Fails for descendants, and for parents with a scale component closer to zero than `MIN_SCALE`.
+    #[allow(dead_code)]
+    pub fn set_parent(&mut self, index: usize, parent_i: Option<usize>) -> Result<()> {
+        if let Some(parent_i) = parent_i {
+            if self.is_ancestor_or_self(index, parent_i) {
+                bail!(
+                    "Transform {} can't be parented to its descendant {}",
+                    index,
+                    parent_i
+                );
+            }
+
+            let parent_scale = self.scale[parent_i];
+            if parent_scale.x.abs() < MIN_SCALE
+                || parent_scale.y.abs() < MIN_SCALE
+                || parent_scale.z.abs() < MIN_SCALE
+            {
+                bail!(
+                    "Transform {} can't be parented to {} with the zero scale {:?}",
+                    index,
+                    parent_i,
+                    parent_scale
+                );
+            }
+        }
+
+        if let Some(old_parent_i) = self.parent_i[index] {
+            self.children_i[old_parent_i].retain(|child_i| *child_i != index);
+        }
+        self.parent_i[index] = parent_i;
+
+        match parent_i {
+            Some(parent_i) => {
+                self.children_i[parent_i].push(index);
+
+                let inverse_rotation = self.rotation[parent_i].invert();
+                let offset = self.position[index] - self.position[parent_i];
+                let local_position = inverse_rotation
+                    .rotate_vector(offset)
+                    .div_element_wise(self.scale[parent_i]);
+
+                self.local_position[index] = Point3::from_vec(local_position);
+                self.local_rotation[index] = inverse_rotation * self.rotation[index];
+                self.local_scale[index] = self.scale[index].div_element_wise(self.scale[parent_i]);
+            }
+            None => {
+                self.local_position[index] = self.position[index];
+                self.local_rotation[index] = self.rotation[index];
+                self.local_scale[index] = self.scale[index];
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_ancestor_or_self(&self, ancestor_i: usize, index: usize) -> bool {
+        let mut current_i = Some(index);
+        while let Some(i) = current_i {
+            if i == ancestor_i {
+                return true;
+            }
+            current_i = self.parent_i[i];
+        }
+
+        false
+    }
+
+    /// Propagates the world transforms from the roots down to their children.
+    pub fn update(&mut self) {
+        let mut stack = Vec::new();
+        for root_i in 0..self.len() {
+            if self.parent_i[root_i].is_some() {
+                continue;
+            }
+
+            // Parents are always updated before their children
+            stack.extend_from_slice(&self.children_i[root_i]);
+            while let Some(index) = stack.pop() {
+                self.update_world_transform(index);
+                stack.extend_from_slice(&self.children_i[index]);
+            }
+        }
+    }
+
+    /// Combines the local transform with the world transform of the parent.
+    /// Scale is applied per axis, without the shear a rotated non-uniform parent scale would cause.
+    fn update_world_transform(&mut self, index: usize) {
+        let Some(parent_i) = self.parent_i[index] else {
+            return;
+        };
+
+        let parent_rotation = self.rotation[parent_i];
+        let parent_scale = self.scale[parent_i];
+        let offset = self.local_position[index]
+            .to_vec()
+            .mul_element_wise(parent_scale);
+
+        self.set_position(
+            index,
+            self.position[parent_i] + parent_rotation.rotate_vector(offset),
+        );
+        self.set_rotation(index, parent_rotation * self.local_rotation[index]);
+        self.set_scale(
+            index,
+            parent_scale.mul_element_wise(self.local_scale[index]),
+        );
+    }
+
+    pub fn position(&self, index: usize) -> Point3<f32> {
+        self.position[index]
+    }
+
+    pub fn rotation(&self, index: usize) -> Quaternion<f32> {
+        self.rotation[index]
+    }
+
+    pub fn scale(&self, index: usize) -> Vector3<f32> {
+        self.scale[index]
+    }
+
+    pub fn set_position(&mut self, index: usize, position: Point3<f32>) {
+        if self.position[index] != position {
+            self.position[index] = position;
+            self.world_matrix[index].set(None);
+        }
+    }
+
+    pub fn set_rotation(&mut self, index: usize, rotation: Quaternion<f32>) {
+        if self.rotation[index] != rotation {
+            self.rotation[index] = rotation;
+            self.basis[index].set(None);
+            self.euler[index].set(None);
+            self.world_matrix[index].set(None);
+        }
+    }
+
+    /// Panics if a scale component is closer to zero than `MIN_SCALE`.
+    pub fn set_scale(&mut self, index: usize, scale: Vector3<f32>) {
+        Self::check_scale(scale);
+
+        if self.scale[index] != scale {
+            self.scale[index] = scale;
+            self.world_matrix[index].set(None);
+        }
+    }
+
+    fn check_scale(scale: Vector3<f32>) {
+        assert!(
+            scale.x.abs() >= MIN_SCALE && scale.y.abs() >= MIN_SCALE && scale.z.abs() >= MIN_SCALE,
+            "Transform scale {:?} can't be inverted",
+            scale
+        );
+    }
 
     /// Rotate local principal rotation axes.
     /// Takes pitch, yaw and roll as Rad<f32>.
@@ -46,37 +307,158 @@ impl TransformMgr {
 
         let combined_rotation = x_rotation * y_rotation * z_rotation;
 
-        self.rotation[index] = combined_rotation * self.rotation[index];
+        self.set_rotation(index, combined_rotation * self.rotation[index]);
     }
 
     pub fn rotate_around_axis(&mut self, index: usize, axis: Vector3<f32>, angle: Rad<f32>) {
         let rotation = Quaternion::from_axis_angle(axis, -angle);
-        self.rotation[index] = rotation * self.rotation[index];
+        self.set_rotation(index, rotation * self.rotation[index]);
     }
 
     pub fn euler(&self, index: usize) -> Euler<Rad<f32>> {
-        Euler::from(self.rotation[index])
+        if let Some(euler) = self.euler[index].get() {
+            return euler;
+        }
+
+        let euler = Euler::from(self.rotation[index]);
+        self.euler[index].set(Some(euler));
+        euler
+    }
+
+    fn basis(&self, index: usize) -> Basis {
+        if let Some(basis) = self.basis[index].get() {
+            return basis;
+        }
+
+        let basis = Basis::from_rotation(self.rotation[index]);
+        self.basis[index].set(Some(basis));
+        basis
     }
 
     pub fn forward(&self, index: usize) -> Vector3<f32> {
-        self.rotation[index]
-            .rotate_vector(Vector3::unit_z())
-            .normalize()
+        self.basis(index).forward
     }
 
     pub fn right(&self, index: usize) -> Vector3<f32> {
-        self.rotation[index]
-            .rotate_vector(Vector3::unit_x())
-            .normalize()
+        self.basis(index).right
     }
 
     pub fn up(&self, index: usize) -> Vector3<f32> {
-        self.rotation[index]
-            .rotate_vector(Vector3::unit_y())
-            .normalize()
+        self.basis(index).up
+    }
+
+    /// Scales, then rotates, then translates.
+    pub fn world_matrix(&self, index: usize) -> Matrix4<f32> {
+        if let Some(world_matrix) = self.world_matrix[index].get() {
+            return world_matrix;
+        }
+
+        let scale = self.scale[index];
+        let world_matrix = Matrix4::from_translation(self.position[index].to_vec())
+            * Matrix4::from(self.rotation[index])
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+        self.world_matrix[index].set(Some(world_matrix));
+        world_matrix
+    }
+
+    /// Transforms a point from the local space of the transform to world space.
+    pub fn local_to_world(&self, index: usize, point: Point3<f32>) -> Point3<f32> {
+        self.world_matrix(index).transform_point(point)
     }
 
     pub fn translate(&mut self, index: usize, translation: Vector3<f32>) {
-        self.position[index] += translation;
+        self.set_position(index, self.position[index] + translation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{
+        assert_abs_diff_eq, Deg, InnerSpace, One, Point3, Quaternion, Rotation3, Vector3,
+    };
+
+    use super::{TransformMgr, UNIT_SCALE};
+
+    #[test]
+    fn set_parent_keeps_world_transform() {
+        let mut transform_mgr = TransformMgr::new();
+        let parent_i = transform_mgr.add(
+            Point3::new(3.0, -1.0, 4.0),
+            Quaternion::from_axis_angle(Vector3::new(1.0, 1.0, 0.0).normalize(), Deg(30.0)),
+            Vector3::new(2.0, 0.5, 1.0),
+        );
+        let child_i = transform_mgr.add(
+            Point3::new(5.0, 2.0, -1.0),
+            Quaternion::from_angle_y(Deg(70.0)),
+            UNIT_SCALE,
+        );
+        let position = transform_mgr.position(child_i);
+        let rotation = transform_mgr.rotation(child_i);
+
+        transform_mgr.set_parent(child_i, Some(parent_i)).unwrap();
+        transform_mgr.update();
+        assert_eq!(transform_mgr.parent(child_i), Some(parent_i));
+        assert_abs_diff_eq!(transform_mgr.position(child_i), position, epsilon = 1e-4);
+        assert_abs_diff_eq!(transform_mgr.rotation(child_i), rotation, epsilon = 1e-4);
+
+        // The child follows its parent, and stays in place once unparented
+        transform_mgr.translate(parent_i, Vector3::new(0.0, 10.0, 0.0));
+        transform_mgr.update();
+        let moved_position = position + Vector3::new(0.0, 10.0, 0.0);
+        assert_abs_diff_eq!(
+            transform_mgr.position(child_i),
+            moved_position,
+            epsilon = 1e-4
+        );
+
+        transform_mgr.set_parent(child_i, None).unwrap();
+        transform_mgr.translate(parent_i, Vector3::new(0.0, 10.0, 0.0));
+        transform_mgr.update();
+        assert_eq!(transform_mgr.parent(child_i), None);
+        assert_abs_diff_eq!(
+            transform_mgr.position(child_i),
+            moved_position,
+            epsilon = 1e-4
+        );
+        assert_abs_diff_eq!(transform_mgr.rotation(child_i), rotation, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn set_parent_rejects_descendants() {
+        let mut transform_mgr = TransformMgr::new();
+        let root_i = transform_mgr.add(Point3::new(0.0, 0.0, 0.0), Quaternion::one(), UNIT_SCALE);
+        let child_i = transform_mgr.add_child(
+            root_i,
+            Point3::new(1.0, 0.0, 0.0),
+            Quaternion::one(),
+            UNIT_SCALE,
+        );
+        let grandchild_i = transform_mgr.add_child(
+            child_i,
+            Point3::new(1.0, 0.0, 0.0),
+            Quaternion::one(),
+            UNIT_SCALE,
+        );
+
+        assert!(transform_mgr
+            .set_parent(root_i, Some(grandchild_i))
+            .is_err());
+        assert!(transform_mgr.set_parent(root_i, Some(root_i)).is_err());
+        assert_eq!(transform_mgr.parent(root_i), None);
+
+        // Moving under a sibling branch is fine
+        transform_mgr
+            .set_parent(grandchild_i, Some(root_i))
+            .unwrap();
+        assert_eq!(transform_mgr.parent(grandchild_i), Some(root_i));
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be inverted")]
+    fn set_scale_rejects_zero_scale() {
+        let mut transform_mgr = TransformMgr::new();
+        let index = transform_mgr.add(Point3::new(0.0, 0.0, 0.0), Quaternion::one(), UNIT_SCALE);
+
+        transform_mgr.set_scale(index, Vector3::new(1.0, 0.0, 1.0));
     }
 }
@@ -0,0 +1,119 @@
+//! Benchmark of the cached transform basis vectors and world matrices.
+//! Compares them against rebuilding the values from the rotation on every call.
+//! Ignored by default, run with
+//! `cargo test --release transform_benchmark -- --ignored --nocapture`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use cgmath::{
+    EuclideanSpace, Euler, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation, Rotation3,
+    Vector3,
+};
+
+use super::transform::{TransformMgr, UNIT_SCALE};
+
+const TRANSFORM_COUNT: usize = 4096;
+const FRAME_COUNT: usize = 200;
+/// Transforms moved every frame, the rest stay still like buildings and props.
+const MOVING_TRANSFORM_COUNT: usize = 256;
+/// How often each value is read per frame, by gameplay, cameras, emitters and renderers.
+const READS_PER_FRAME: usize = 4;
+
+fn create_transform_mgr() -> TransformMgr {
+    let mut transform_mgr = TransformMgr::new();
+    for i in 0..TRANSFORM_COUNT {
+        let angle = i as f32 * 0.01;
+        transform_mgr.add(
+            Point3::new(i as f32, 0.0, -(i as f32)),
+            Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 3.0).normalize(), Rad(angle)),
+            UNIT_SCALE,
+        );
+    }
+
+    transform_mgr
+}
+
+fn move_transforms(transform_mgr: &mut TransformMgr, frame: usize) {
+    for i in 0..MOVING_TRANSFORM_COUNT {
+        let index = (frame * MOVING_TRANSFORM_COUNT + i) % TRANSFORM_COUNT;
+        transform_mgr.translate(index, Vector3::new(0.0, 0.0, 0.1));
+        transform_mgr.rotate_local_axes(index, Rad(0.01), Rad(0.02), Rad(0.0));
+    }
+}
+
+/// The values as calculated before caching.
+fn read_uncached(transform_mgr: &TransformMgr, index: usize) -> f32 {
+    let rotation = transform_mgr.rotation(index);
+    let scale = transform_mgr.scale(index);
+
+    let forward = rotation.rotate_vector(Vector3::unit_z()).normalize();
+    let right = rotation.rotate_vector(Vector3::unit_x()).normalize();
+    let up = rotation.rotate_vector(Vector3::unit_y()).normalize();
+    let euler = Euler::from(rotation);
+    let world_matrix = Matrix4::from_translation(transform_mgr.position(index).to_vec())
+        * Matrix4::from(rotation)
+        * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+
+    forward.x + right.y + up.z + euler.x.0 + world_matrix.w.x
+}
+
+fn read_cached(transform_mgr: &TransformMgr, index: usize) -> f32 {
+    let forward = transform_mgr.forward(index);
+    let right = transform_mgr.right(index);
+    let up = transform_mgr.up(index);
+    let euler = transform_mgr.euler(index);
+    let world_matrix = transform_mgr.world_matrix(index);
+
+    forward.x + right.y + up.z + euler.x.0 + world_matrix.w.x
+}
+
+fn run(read: fn(&TransformMgr, usize) -> f32) -> Duration {
+    let mut transform_mgr = create_transform_mgr();
+
+    let start = Instant::now();
+    let mut sum = 0.0;
+    for frame in 0..FRAME_COUNT {
+        move_transforms(&mut transform_mgr, frame);
+        for _ in 0..READS_PER_FRAME {
+            for index in 0..TRANSFORM_COUNT {
+                sum += read(black_box(&transform_mgr), black_box(index));
+            }
+        }
+    }
+    black_box(sum);
+
+    start.elapsed()
+}
+
+#[test]
+#[ignore = "benchmark, run in release with --ignored --nocapture"]
+fn transform_benchmark() {
+    // Warms up caches and clocks
+    run(read_uncached);
+    run(read_cached);
+
+    let uncached = run(read_uncached);
+    let cached = run(read_cached);
+
+    println!(
+        "{} transforms, {} moving, {} frames, {} reads per frame",
+        TRANSFORM_COUNT, MOVING_TRANSFORM_COUNT, FRAME_COUNT, READS_PER_FRAME
+    );
+    println!(
+        "uncached: {:>8.2} ms, {:>6.1} ns per read",
+        uncached.as_secs_f64() * 1000.0,
+        uncached.as_nanos() as f64 / (FRAME_COUNT * READS_PER_FRAME * TRANSFORM_COUNT) as f64
+    );
+    println!(
+        "cached:   {:>8.2} ms, {:>6.1} ns per read",
+        cached.as_secs_f64() * 1000.0,
+        cached.as_nanos() as f64 / (FRAME_COUNT * READS_PER_FRAME * TRANSFORM_COUNT) as f64
+    );
+    println!(
+        "speedup:  {:.2}x",
+        uncached.as_secs_f64() / cached.as_secs_f64()
+    );
+}
@@ -1,11 +1,18 @@
 use winit::{
     dpi::LogicalPosition,
-    event::{Event, WindowEvent},
+    event::{DeviceEvent, Event, WindowEvent},
+    window::CursorGrabMode,
 };
 
 pub struct CursorMgr {
     pub x: f32,
     pub y: f32,
+
+    /// Relative mouse motion since the last update, unaffected by the cursor grab.
+    pub delta_x: f32,
+    pub delta_y: f32,
+
+    is_grabbed: bool,
 }
 
 impl CursorMgr {
@@ -19,7 +26,15 @@ impl CursorMgr {
             })
             .unwrap();
 
-        Self { x: 0.0, y: 0.0 }
+        Self {
+            x: 0.0,
+            y: 0.0,
+
+            delta_x: 0.0,
+            delta_y: 0.0,
+
+            is_grabbed: false,
+        }
     }
 
     pub fn input<T>(&mut self, event: &winit::event::Event<T>, window: &winit::window::Window) {
@@ -34,7 +49,41 @@ impl CursorMgr {
                 }
                 _ => (),
             },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                self.delta_x += delta.0 as f32;
+                self.delta_y += delta.1 as f32;
+            }
             _ => (),
         };
     }
+
+    /// Keeps the cursor hidden inside the window while grabbed, for mouse look.
+    pub fn set_grabbed(&mut self, window: &winit::window::Window, is_grabbed: bool) {
+        if is_grabbed == self.is_grabbed {
+            return;
+        }
+        self.is_grabbed = is_grabbed;
+
+        // Not every platform supports both grab modes
+        let result = if is_grabbed {
+            window
+                .set_cursor_grab(CursorGrabMode::Confined)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Locked))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            eprintln!("Cursor grab failed: {:?}", e);
+        }
+        window.set_cursor_visible(!is_grabbed);
+    }
+
+    /// Clears the mouse motion, so it only lasts for the update after the motion events.
+    pub fn end_frame(&mut self) {
+        self.delta_x = 0.0;
+        self.delta_y = 0.0;
+    }
 }
@@ -46,7 +46,12 @@ impl KeyboardMgr {
         }
     }
 
-    // TODO: make key down and up more responsive, consider clearing the state after each update
+    /// Clears the key down and up states, so they only last for the update after the key event.
+    pub fn end_frame(&mut self) {
+        self.key_down = [false; KEYCODE_COUNT];
+        self.key_up = [false; KEYCODE_COUNT];
+    }
+
     fn update_key_state(&mut self, key: VirtualKeyCode, state: ElementState) {
         let key = key as usize;
         let is_pressed = state == ElementState::Pressed;
@@ -1,3 +1,4 @@
+mod assets;
 mod audio;
 mod game;
 mod input;
@@ -17,6 +18,7 @@ use winit::{
 const WINDOW_TITLE: &str = "terminal_ld53";
 const WINDOW_WIDTH: u16 = 1600;
 const WINDOW_HEIGHT: u16 = 900;
+const MSAA_SAMPLE_COUNT: u32 = 4;
 
 fn main() {
     // Use pollster to block thread while game loop runs
@@ -36,8 +38,8 @@ async fn game_loop<'a>() {
         .build(&event_loop)
         .unwrap();
 
-    let mut render_state = RenderState::new(window).await;
-    let mut game_state = GameState::new(&event_loop, &mut render_state).await;
+    let mut render_state = RenderState::new(window, MSAA_SAMPLE_COUNT).await;
+    let mut game_state = GameState::new(&event_loop, &mut render_state);
 
     let mut last_render_time = Instant::now();
 
@@ -46,13 +48,13 @@ async fn game_loop<'a>() {
         *control_flow = ControlFlow::Poll;
 
         // Game state input
-        game_state.input(&event, &render_state.window);
+        game_state.input(&event, render_state.window());
 
         match event {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == render_state.window.id() => match event {
+            } if window_id == render_state.window().id() => match event {
                 WindowEvent::CloseRequested
                 | WindowEvent::KeyboardInput {
                     input:
@@ -64,6 +66,19 @@ async fn game_loop<'a>() {
                     ..
                 } => *control_flow = ControlFlow::Exit,
 
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F12),
+                            ..
+                        },
+                    ..
+                } => match render_state.save_screenshot(&mut game_state) {
+                    Ok(path) => println!("Screenshot saved: {:?}", path),
+                    Err(e) => eprintln!("Screenshot failed: {:?}", e),
+                },
+
                 WindowEvent::Resized(physical_size) => {
                     render_state.resize(*physical_size);
                 }
@@ -74,7 +89,7 @@ async fn game_loop<'a>() {
 
                 _ => {}
             },
-            Event::RedrawRequested(window_id) if window_id == render_state.window.id() => {
+            Event::RedrawRequested(window_id) if window_id == render_state.window().id() => {
                 let now = Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
@@ -94,7 +109,7 @@ async fn game_loop<'a>() {
                 }
             }
             Event::MainEventsCleared => {
-                render_state.window.request_redraw();
+                render_state.window().request_redraw();
             }
             _ => {}
         }
@@ -1,9 +1,8 @@
 use cgmath::{
-    perspective, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation, SquareMatrix, Vector3,
+    ortho, perspective, Deg, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation, SquareMatrix,
+    Vector3,
 };
 
-use crate::game::transform::TransformMgr;
-
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -12,6 +11,9 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+/// Closest allowed near plane, depth precision degrades quickly below it.
+const MIN_ZNEAR: f32 = 0.01;
+
 #[derive(Debug)]
 pub struct Camera {
     pub position: Point3<f32>,
@@ -23,11 +25,6 @@ impl Camera {
         Self { position, rotation }
     }
 
-    pub fn set_from_transform_mgr(&mut self, transform_mgr: &TransformMgr, index: usize) {
-        self.position = transform_mgr.position[index];
-        self.rotation = transform_mgr.rotation[index];
-    }
-
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         let forward = self.rotation.rotate_vector(Vector3::unit_z()).normalize();
         let up = self.rotation.rotate_vector(Vector3::unit_y()).normalize();
@@ -36,18 +33,36 @@ impl Camera {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProjectionMode {
+    Perspective,
+    /// Parallel view, for maps and top-down views.
+    Orthographic,
+}
+
 pub struct Projection {
+    pub mode: ProjectionMode,
     aspect: f32,
+    /// Vertical field of view, in perspective mode.
     fovy: Rad<f32>,
+    /// Vertical extent of the view in world units, in orthographic mode.
+    ortho_height: f32,
     znear: f32,
     zfar: f32,
 }
 
 impl Projection {
+    /// Vertical field of view of the default perspective.
+    pub const DEFAULT_FOVY: Deg<f32> = Deg(80.0);
+    pub const MIN_FOVY: Deg<f32> = Deg(10.0);
+    pub const MAX_FOVY: Deg<f32> = Deg(150.0);
+
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
         Self {
+            mode: ProjectionMode::Perspective,
             aspect: width as f32 / height as f32,
             fovy: fovy.into(),
+            ortho_height: 100.0,
             znear,
             zfar,
         }
@@ -57,8 +72,47 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    /// Clamped between `MIN_FOVY` and `MAX_FOVY`.
+    pub fn set_fovy<F: Into<Rad<f32>>>(&mut self, fovy: F) {
+        let min: Rad<f32> = Self::MIN_FOVY.into();
+        let max: Rad<f32> = Self::MAX_FOVY.into();
+        self.fovy = Rad(fovy.into().0.clamp(min.0, max.0));
+    }
+
+    pub fn set_ortho_height(&mut self, ortho_height: f32) {
+        self.ortho_height = f32::max(ortho_height, f32::EPSILON);
+    }
+
+    pub fn set_zfar(&mut self, zfar: f32) {
+        self.set_clip_planes(self.znear, zfar);
+    }
+
+    /// The near plane is kept in front of the camera and before the far plane.
+    pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) {
+        self.znear = f32::max(znear, MIN_ZNEAR);
+        self.zfar = f32::max(zfar, self.znear + MIN_ZNEAR);
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let projection = match self.mode {
+            ProjectionMode::Perspective => {
+                perspective(self.fovy, self.aspect, self.znear, self.zfar)
+            }
+            ProjectionMode::Orthographic => {
+                let half_height = self.ortho_height * 0.5;
+                let half_width = half_height * self.aspect;
+                ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        };
+
+        OPENGL_TO_WGPU_MATRIX * projection
     }
 }
 
@@ -0,0 +1,59 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3, Vector4};
+
+use super::camera::{Camera, Projection};
+
+/// View frustum as six inward facing planes, in world space.
+pub struct Frustum {
+    /// Plane normal in `xyz` and distance in `w`.
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn new(camera: &Camera, projection: &Projection) -> Self {
+        Self::from_matrix(projection.calc_matrix() * camera.calc_matrix())
+    }
+
+    /// Extracts the planes from a view projection matrix with a 0 to 1 depth range.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let row = |i: usize| {
+            Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let (row_0, row_1, row_2, row_3) = (row(0), row(1), row(2), row(3));
+
+        let planes = [
+            row_3 + row_0, // Left
+            row_3 - row_0, // Right
+            row_3 + row_1, // Bottom
+            row_3 - row_1, // Top
+            row_2,         // Near
+            row_3 - row_2, // Far
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+
+        Self { planes }
+    }
+
+    /// Returns `false` if the axis aligned box is fully outside of the frustum.
+    /// Boxes close to a frustum corner may be reported as intersecting.
+    pub fn intersects_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> bool {
+        for plane in &self.planes {
+            // Box corner farthest along the plane normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+
+            if plane.truncate().dot(corner) + plane.w < 0.0 {
+                return false;
+            }
+        }
+
+        true
+    }
+}
@@ -1,6 +1,11 @@
 pub mod camera;
+pub mod frustum;
 pub mod light;
 pub mod model;
+pub mod offscreen;
+pub mod post_process;
 pub mod render_state;
+pub mod shader;
+pub mod terrain_material;
 pub mod texture;
 pub mod vertex;
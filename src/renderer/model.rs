@@ -1,9 +1,14 @@
-use wgpu::BindGroupLayout;
+use cgmath::SquareMatrix;
+use wgpu::{util::DeviceExt, BindGroupLayout};
 
-use crate::resources;
+use crate::{
+    assets::{asset_cache::Handle, asset_manager::AssetMgr},
+    game::transform::MIN_SCALE,
+};
 
 use super::{render_state::RenderState, texture, vertex::Vertex};
 use std::ops::Range;
+use std::path::PathBuf;
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
@@ -15,11 +20,15 @@ pub struct Model {
     pub max_x: f32,
     pub max_y: f32,
     pub max_z: f32,
+
+    /// Asset files the model was loaded from, for hot reloading. Empty for generated models.
+    pub source_files: Vec<PathBuf>,
 }
 
 impl Model {
     pub async fn new_from_single_mesh(
         mesh: Mesh,
+        asset_mgr: &mut AssetMgr,
         render_state: &RenderState,
         texture_bind_group_layout: &BindGroupLayout,
     ) -> Self {
@@ -31,6 +40,7 @@ impl Model {
         let max_z = mesh.max_z;
 
         let material = Material::load_default_material(
+            asset_mgr,
             &render_state.device,
             &render_state.queue,
             texture_bind_group_layout,
@@ -47,44 +57,76 @@ impl Model {
             max_x,
             max_y,
             max_z,
+            source_files: Vec::new(),
         }
     }
 }
 
+/// Texture of the default material, loaded as both color and normal map.
+pub const DEFAULT_TEXTURE_PATH: &str = "textures/yellow.png";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    opacity: f32,
+    // Uniforms are 16 byte aligned
+    _padding: [f32; 3],
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
+    pub diffuse_texture: Handle<texture::Texture>,
+    pub normal_texture: Handle<texture::Texture>,
+    /// Transparent materials are drawn blended, after the opaque ones.
+    pub is_transparent: bool,
     pub bind_group: wgpu::BindGroup,
 }
 
 impl Material {
+    /// `opacity`, from 0 to 1, is multiplied with the diffuse texture alpha in the shader.
+    /// Materials with an `opacity` below 1 are transparent.
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        diffuse_texture: Handle<texture::Texture>,
+        normal_texture: Handle<texture::Texture>,
+        opacity: f32,
+        asset_mgr: &AssetMgr,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let diffuse = asset_mgr.textures.get(diffuse_texture);
+        let normal = asset_mgr.textures.get(normal_texture);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&[MaterialUniform {
+                opacity,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(name),
             layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&diffuse.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&diffuse.sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&normal.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
                 },
             ],
         });
@@ -93,27 +135,49 @@ impl Material {
             name: String::from(name),
             diffuse_texture,
             normal_texture,
+            is_transparent: opacity < 1.0,
             bind_group,
         }
     }
 
+    /// Removes the material's references to its textures.
+    pub fn release_textures(&self, asset_mgr: &mut AssetMgr) {
+        asset_mgr.textures.release(self.diffuse_texture);
+        asset_mgr.textures.release(self.normal_texture);
+    }
+
     pub async fn load_default_material(
+        asset_mgr: &mut AssetMgr,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         layout: &wgpu::BindGroupLayout,
     ) -> anyhow::Result<Material> {
-        let file_path = std::path::Path::new("textures/yellow.png");
-
-        let diffuse_texture =
-            resources::load_texture(file_path.to_str().unwrap(), false, device, queue).await?;
-        let normal_texture =
-            resources::load_texture(file_path.to_str().unwrap(), true, device, queue).await?;
+        let diffuse_texture = asset_mgr
+            .load_texture(
+                DEFAULT_TEXTURE_PATH,
+                texture::TextureKind::Color,
+                &texture::SamplerOptions::CLAMP,
+                device,
+                queue,
+            )
+            .await?;
+        let normal_texture = asset_mgr
+            .load_texture(
+                DEFAULT_TEXTURE_PATH,
+                texture::TextureKind::NormalMap,
+                &texture::SamplerOptions::CLAMP,
+                device,
+                queue,
+            )
+            .await?;
 
         Ok(Material::new(
             device,
             "Default texture",
             diffuse_texture,
             normal_texture,
+            1.0,
+            asset_mgr,
             layout,
         ))
     }
@@ -316,23 +380,64 @@ where
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
     pub normal: [[f32; 3]; 3],
+    /// Linear RGBA, multiplies the diffuse color.
+    pub tint: [f32; 4],
+    /// Linear RGB, added to the lit color. Values above 1 bloom.
+    pub emissive: [f32; 3],
+    /// Above 0 draws a silhouette outline around the instance.
+    pub outline: f32,
 }
 
 impl InstanceRaw {
-    pub fn new(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
-        let model = cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from(rotation);
+    pub const NO_TINT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    pub const NO_EMISSIVE: [f32; 3] = [0.0, 0.0, 0.0];
 
+    pub fn new(
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) -> Self {
         Self {
-            model: model.into(),
-            normal: cgmath::Matrix3::from(rotation).into(),
+            model: Self::model_matrix(position, rotation, scale).into(),
+            normal: Self::normal_matrix(rotation, scale).into(),
+            tint: Self::NO_TINT,
+            emissive: Self::NO_EMISSIVE,
+            outline: 0.0,
         }
     }
 
-    pub fn update(&mut self, position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) {
-        let new_model =
-            cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from(rotation);
-        self.model = new_model.into();
-        self.normal = cgmath::Matrix3::from(rotation).into();
+    pub fn update(
+        &mut self,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) {
+        self.model = Self::model_matrix(position, rotation, scale).into();
+        self.normal = Self::normal_matrix(rotation, scale).into();
+    }
+
+    /// Scales, then rotates, then translates.
+    fn model_matrix(
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(position)
+            * cgmath::Matrix4::from(rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+    }
+
+    /// Inverse transpose of the model rotation and scale, so normals stay perpendicular
+    /// to non-uniformly scaled surfaces. For a rotation R and scale S it is R * S^-1.
+    /// Scales closer to zero than `MIN_SCALE` are clamped, so the inverse stays finite.
+    fn normal_matrix(
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) -> cgmath::Matrix3<f32> {
+        let inverse = |scale: f32| 1.0 / f32::max(scale.abs(), MIN_SCALE).copysign(scale);
+        let inverse_scale =
+            cgmath::Vector3::new(inverse(scale.x), inverse(scale.y), inverse(scale.z));
+        cgmath::Matrix3::from(rotation) * cgmath::Matrix3::from_diagonal(inverse_scale)
     }
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -376,6 +481,24 @@ impl InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // Tint
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Emissive
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 29]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                // Outline
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 32]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
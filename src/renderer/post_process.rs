@@ -0,0 +1,506 @@
+use wgpu::util::DeviceExt;
+
+use crate::{assets::asset_cache::Handle, resources::ShaderSource};
+
+use super::{render_state::create_render_pipeline_from_source, shader::ShaderMgr, texture};
+
+const THRESHOLD_SHADER_FILE: &str = "shaders/bloom_threshold.wgsl";
+const BLUR_SHADER_FILE: &str = "shaders/bloom_blur.wgsl";
+const TONEMAP_SHADER_FILE: &str = "shaders/tonemap.wgsl";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tonemapper {
+    Aces,
+    Filmic,
+}
+
+pub struct PostProcessSettings {
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    /// Brightness above which pixels contribute to bloom.
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    /// Amount of horizontal and vertical blur pass pairs.
+    pub bloom_blur_passes: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessUniform {
+    exposure: f32,
+    tonemapper: u32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+}
+
+impl PostProcessUniform {
+    fn new(settings: &PostProcessSettings) -> Self {
+        Self {
+            exposure: settings.exposure,
+            tonemapper: match settings.tonemapper {
+                Tonemapper::Aces => 0,
+                Tonemapper::Filmic => 1,
+            },
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: settings.bloom_intensity,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurUniform {
+    direction: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// HDR scene target with bloom and tonemapping to the surface.
+/// The bloom chain runs at half resolution.
+pub struct PostProcess {
+    pub settings: PostProcessSettings,
+
+    /// The scene is rendered (or resolved) into this texture.
+    pub hdr_texture: texture::Texture,
+    /// Bloom ping-pong textures, the result ends up in the first one.
+    bloom_texture: [texture::Texture; 2],
+
+    uniform_buffer: wgpu::Buffer,
+    blur_buffer: [wgpu::Buffer; 2],
+
+    source_bind_group_layout: wgpu::BindGroupLayout,
+    tonemap_bind_group_layout: wgpu::BindGroupLayout,
+
+    threshold_bind_group: wgpu::BindGroup,
+    /// Horizontal blur reads the first bloom texture, vertical blur the second one.
+    blur_bind_group: [wgpu::BindGroup; 2],
+    tonemap_bind_group: wgpu::BindGroup,
+
+    source_pipeline_layout: wgpu::PipelineLayout,
+    tonemap_pipeline_layout: wgpu::PipelineLayout,
+    /// Tonemap target format and sample count, for hot reloading.
+    surface_format: wgpu::TextureFormat,
+    sample_count: u32,
+
+    threshold_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+
+    threshold_shader: Handle<ShaderSource>,
+    blur_shader: Handle<ShaderSource>,
+    tonemap_shader: Handle<ShaderSource>,
+}
+
+impl PostProcess {
+    /// `sample_count` applies to the tonemap pass, which targets the surface.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        settings: PostProcessSettings,
+        shader_mgr: &mut ShaderMgr,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post process uniform buffer"),
+            contents: bytemuck::cast_slice(&[PostProcessUniform::new(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let blur_buffer = [[1.0, 0.0], [0.0, 1.0]].map(|direction| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Bloom blur uniform buffer"),
+                contents: bytemuck::cast_slice(&[BlurUniform {
+                    direction,
+                    _padding: [0.0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let source_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Post process source bind group layout"),
+                entries: &[texture_entry(0), sampler_entry(1), uniform_entry(2)],
+            });
+        let tonemap_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Tonemap bind group layout"),
+                entries: &[
+                    texture_entry(0),
+                    sampler_entry(1),
+                    texture_entry(2),
+                    uniform_entry(3),
+                ],
+            });
+
+        let source_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Post process source pipeline layout"),
+                bind_group_layouts: &[&source_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let tonemap_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Tonemap pipeline layout"),
+                bind_group_layouts: &[&tonemap_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let threshold_shader = shader_mgr.load(THRESHOLD_SHADER_FILE).unwrap();
+        let threshold_pipeline = Self::create_pipeline(
+            device,
+            &source_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            1,
+            shader_mgr.get(threshold_shader),
+        )
+        .unwrap();
+        let blur_shader = shader_mgr.load(BLUR_SHADER_FILE).unwrap();
+        let blur_pipeline = Self::create_pipeline(
+            device,
+            &source_pipeline_layout,
+            texture::Texture::HDR_FORMAT,
+            1,
+            shader_mgr.get(blur_shader),
+        )
+        .unwrap();
+        let tonemap_shader = shader_mgr.load(TONEMAP_SHADER_FILE).unwrap();
+        let tonemap_pipeline = Self::create_pipeline(
+            device,
+            &tonemap_pipeline_layout,
+            config.format,
+            sample_count,
+            shader_mgr.get(tonemap_shader),
+        )
+        .unwrap();
+
+        let (hdr_texture, bloom_texture) = Self::create_textures(device, config);
+        let (threshold_bind_group, blur_bind_group, tonemap_bind_group) = Self::create_bind_groups(
+            device,
+            &hdr_texture,
+            &bloom_texture,
+            &uniform_buffer,
+            &blur_buffer,
+            &source_bind_group_layout,
+            &tonemap_bind_group_layout,
+        );
+
+        Self {
+            settings,
+
+            hdr_texture,
+            bloom_texture,
+
+            uniform_buffer,
+            blur_buffer,
+
+            source_bind_group_layout,
+            tonemap_bind_group_layout,
+
+            threshold_bind_group,
+            blur_bind_group,
+            tonemap_bind_group,
+
+            source_pipeline_layout,
+            tonemap_pipeline_layout,
+            surface_format: config.format,
+            sample_count,
+
+            threshold_pipeline,
+            blur_pipeline,
+            tonemap_pipeline,
+
+            threshold_shader,
+            blur_shader,
+            tonemap_shader,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        shader: &ShaderSource,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        create_render_pipeline_from_source(
+            device,
+            layout,
+            color_format,
+            wgpu::BlendState::REPLACE,
+            None,
+            false,
+            sample_count,
+            &[],
+            shader,
+            None,
+        )
+    }
+
+    /// Rebuilds the pipelines whose shaders were reloaded.
+    pub fn reload_shaders(&mut self, shader_mgr: &mut ShaderMgr, device: &wgpu::Device) {
+        shader_mgr.reload_pipeline(
+            self.threshold_shader,
+            &mut self.threshold_pipeline,
+            |shader| {
+                Self::create_pipeline(
+                    device,
+                    &self.source_pipeline_layout,
+                    texture::Texture::HDR_FORMAT,
+                    1,
+                    shader,
+                )
+            },
+        );
+        shader_mgr.reload_pipeline(self.blur_shader, &mut self.blur_pipeline, |shader| {
+            Self::create_pipeline(
+                device,
+                &self.source_pipeline_layout,
+                texture::Texture::HDR_FORMAT,
+                1,
+                shader,
+            )
+        });
+        shader_mgr.reload_pipeline(self.tonemap_shader, &mut self.tonemap_pipeline, |shader| {
+            Self::create_pipeline(
+                device,
+                &self.tonemap_pipeline_layout,
+                self.surface_format,
+                self.sample_count,
+                shader,
+            )
+        });
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.hdr_texture, self.bloom_texture) = Self::create_textures(device, config);
+        (
+            self.threshold_bind_group,
+            self.blur_bind_group,
+            self.tonemap_bind_group,
+        ) = Self::create_bind_groups(
+            device,
+            &self.hdr_texture,
+            &self.bloom_texture,
+            &self.uniform_buffer,
+            &self.blur_buffer,
+            &self.source_bind_group_layout,
+            &self.tonemap_bind_group_layout,
+        );
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (texture::Texture, [texture::Texture; 2]) {
+        let hdr_texture = texture::Texture::create_render_target(
+            device,
+            config.width,
+            config.height,
+            texture::Texture::HDR_FORMAT,
+            "HDR texture",
+        );
+        let bloom_texture = ["Bloom texture 0", "Bloom texture 1"].map(|label| {
+            texture::Texture::create_render_target(
+                device,
+                config.width / 2,
+                config.height / 2,
+                texture::Texture::HDR_FORMAT,
+                label,
+            )
+        });
+
+        (hdr_texture, bloom_texture)
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        hdr_texture: &texture::Texture,
+        bloom_texture: &[texture::Texture; 2],
+        uniform_buffer: &wgpu::Buffer,
+        blur_buffer: &[wgpu::Buffer; 2],
+        source_bind_group_layout: &wgpu::BindGroupLayout,
+        tonemap_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::BindGroup, [wgpu::BindGroup; 2], wgpu::BindGroup) {
+        let source_bind_group = |source: &texture::Texture, buffer: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Post process source bind group"),
+                layout: source_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&source.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        let threshold_bind_group = source_bind_group(hdr_texture, uniform_buffer);
+        let blur_bind_group = [
+            source_bind_group(&bloom_texture[0], &blur_buffer[0]),
+            source_bind_group(&bloom_texture[1], &blur_buffer[1]),
+        ];
+
+        let tonemap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap bind group"),
+            layout: tonemap_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&hdr_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&bloom_texture[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        (threshold_bind_group, blur_bind_group, tonemap_bind_group)
+    }
+
+    /// Debug window for tuning the settings at runtime.
+    pub fn ui(&mut self, context: &egui::Context) {
+        let settings = &mut self.settings;
+
+        egui::Window::new("Post process")
+            .default_open(false)
+            .resizable(false)
+            .show(context, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Tonemapper:");
+                    ui.radio_value(&mut settings.tonemapper, Tonemapper::Aces, "ACES");
+                    ui.radio_value(&mut settings.tonemapper, Tonemapper::Filmic, "Filmic");
+                });
+                ui.add(egui::Slider::new(&mut settings.exposure, 0.1..=4.0).text("Exposure"));
+                ui.add(
+                    egui::Slider::new(&mut settings.bloom_threshold, 0.0..=4.0)
+                        .text("Bloom threshold"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut settings.bloom_intensity, 0.0..=2.0)
+                        .text("Bloom intensity"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut settings.bloom_blur_passes, 0..=8)
+                        .text("Bloom blur passes"),
+                );
+            });
+    }
+
+    /// Runs bloom on the HDR texture and tonemaps it into `color_attachment`.
+    pub fn render(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        color_attachment: wgpu::RenderPassColorAttachment,
+    ) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[PostProcessUniform::new(&self.settings)]),
+        );
+
+        Self::fullscreen_pass(
+            encoder,
+            "Bloom threshold pass",
+            &self.bloom_texture[0].view,
+            &self.threshold_pipeline,
+            &self.threshold_bind_group,
+        );
+        for _ in 0..self.settings.bloom_blur_passes {
+            Self::fullscreen_pass(
+                encoder,
+                "Bloom horizontal blur pass",
+                &self.bloom_texture[1].view,
+                &self.blur_pipeline,
+                &self.blur_bind_group[0],
+            );
+            Self::fullscreen_pass(
+                encoder,
+                "Bloom vertical blur pass",
+                &self.bloom_texture[0].view,
+                &self.blur_pipeline,
+                &self.blur_bind_group[1],
+            );
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap pass"),
+            color_attachments: &[Some(color_attachment)],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.tonemap_pipeline);
+        render_pass.set_bind_group(0, &self.tonemap_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        view: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
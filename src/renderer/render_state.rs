@@ -1,4 +1,5 @@
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use cgmath::Rotation3;
 use cgmath::Zero;
@@ -7,9 +8,13 @@ use wgpu::InstanceFlags;
 use winit::window::Window;
 
 use crate::game::game_state::GameState;
+use crate::resources::ShaderSource;
 
 use super::camera;
 use super::light;
+use super::offscreen::{self, OffscreenTarget};
+use super::post_process::{PostProcess, PostProcessSettings, Tonemapper};
+use super::shader::ShaderMgr;
 use super::texture;
 
 const CLEAR_COLOR: wgpu::Color = wgpu::Color {
@@ -19,15 +24,36 @@ const CLEAR_COLOR: wgpu::Color = wgpu::Color {
     a: 1.0,
 };
 
+const SCREENSHOT_DIR: &str = "screenshots";
+
+const POST_PROCESS_SETTINGS: PostProcessSettings = PostProcessSettings {
+    exposure: 1.0,
+    tonemapper: Tonemapper::Aces,
+    bloom_threshold: 1.0,
+    bloom_intensity: 0.6,
+    bloom_blur_passes: 2,
+};
+
 pub struct RenderState {
-    pub surface: wgpu::Surface,
+    /// `None` for headless render states.
+    pub surface: Option<wgpu::Surface>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    pub window: Window,
+    /// `None` for headless render states.
+    pub window: Option<Window>,
     pub clear_color: wgpu::Color,
+    /// MSAA samples per pixel, shared by every render pipeline.
+    pub sample_count: u32,
+    /// Multisampled HDR scene target, resolved to the post process HDR texture.
+    /// `None` without MSAA.
+    pub msaa_texture: Option<texture::Texture>,
+    /// Multisampled surface target for the tonemap and UI passes. `None` without MSAA.
+    pub surface_msaa_texture: Option<texture::Texture>,
     pub depth_texture: texture::Texture,
+    pub post_process: PostProcess,
+    pub shader_mgr: ShaderMgr,
     pub camera: camera::Camera,
     pub projection: camera::Projection,
     pub camera_uniform: camera::CameraUniform,
@@ -41,7 +67,9 @@ pub struct RenderState {
 }
 
 impl RenderState {
-    pub async fn new(window: Window) -> RenderState {
+    /// `sample_count` is the requested MSAA sample count (1, 2, 4 or 8).
+    /// It's lowered to the highest count supported by the adapter.
+    pub async fn new(window: Window, sample_count: u32) -> RenderState {
         let size = window.inner_size();
 
         // wgpu instance
@@ -69,7 +97,9 @@ impl RenderState {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // Required for sample counts other than 1 and 4
+                    features: adapter.features()
+                        & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
                     limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
@@ -100,6 +130,70 @@ impl RenderState {
         };
         surface.configure(&device, &config);
 
+        Self::create(
+            Some(window),
+            Some(surface),
+            &adapter,
+            device,
+            queue,
+            config,
+            sample_count,
+        )
+    }
+
+    /// Creates a render state without a window, rendering only offscreen.
+    /// Uses the fallback (software) adapter when available, see `offscreen::request_headless_device`.
+    // Windowless API for tools and image tests, the game itself always has a window
+    #[allow(dead_code)]
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> anyhow::Result<RenderState> {
+        let (adapter, device, queue) = offscreen::request_headless_device().await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        Ok(Self::create(
+            None,
+            None,
+            &adapter,
+            device,
+            queue,
+            config,
+            sample_count,
+        ))
+    }
+
+    fn create(
+        window: Option<Window>,
+        surface: Option<wgpu::Surface>,
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> RenderState {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
+
+        let sample_count = supported_sample_count(
+            adapter,
+            &[
+                config.format,
+                texture::Texture::HDR_FORMAT,
+                texture::Texture::DEPTH_FORMAT,
+            ],
+            sample_count,
+        );
+
         let light_uniform = light::LightUniform::new([10.0, 10.0, 2.0], [1.0, 1.0, 1.0]);
 
         let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -141,8 +235,13 @@ impl RenderState {
             cgmath::Quaternion::zero(),
         );
 
-        let projection =
-            camera::Projection::new(config.width, config.height, cgmath::Deg(80.0), 0.1, 500.0);
+        let projection = camera::Projection::new(
+            config.width,
+            config.height,
+            camera::Projection::DEFAULT_FOVY,
+            0.1,
+            500.0,
+        );
 
         let mut camera_uniform = camera::CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
@@ -177,8 +276,31 @@ impl RenderState {
             }],
         });
 
+        let msaa_texture = texture::Texture::create_msaa_texture(
+            &device,
+            &config,
+            texture::Texture::HDR_FORMAT,
+            sample_count,
+            "MSAA color texture",
+        );
+        let surface_msaa_texture = texture::Texture::create_msaa_texture(
+            &device,
+            &config,
+            config.format,
+            sample_count,
+            "MSAA surface texture",
+        );
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "Depth texture");
+            texture::Texture::create_depth_texture(&device, &config, sample_count, "Depth texture");
+
+        let mut shader_mgr = ShaderMgr::new();
+        let post_process = PostProcess::new(
+            &device,
+            &config,
+            sample_count,
+            POST_PROCESS_SETTINGS,
+            &mut shader_mgr,
+        );
 
         Self {
             window,
@@ -188,7 +310,12 @@ impl RenderState {
             config,
             size,
             clear_color: CLEAR_COLOR,
+            sample_count,
+            msaa_texture,
+            surface_msaa_texture,
             depth_texture,
+            post_process,
+            shader_mgr,
             camera,
             projection,
             camera_uniform,
@@ -207,9 +334,30 @@ impl RenderState {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "Depth texture");
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
+            self.msaa_texture = texture::Texture::create_msaa_texture(
+                &self.device,
+                &self.config,
+                texture::Texture::HDR_FORMAT,
+                self.sample_count,
+                "MSAA color texture",
+            );
+            self.surface_msaa_texture = texture::Texture::create_msaa_texture(
+                &self.device,
+                &self.config,
+                self.config.format,
+                self.sample_count,
+                "MSAA surface texture",
+            );
+            self.depth_texture = texture::Texture::create_depth_texture(
+                &self.device,
+                &self.config,
+                self.sample_count,
+                "Depth texture",
+            );
+            self.post_process.resize(&self.device, &self.config);
             self.projection.resize(new_size.width, new_size.height);
         }
     }
@@ -238,8 +386,62 @@ impl RenderState {
         );
     }
 
+    /// Panics for headless render states.
+    pub fn window(&self) -> &Window {
+        self.window
+            .as_ref()
+            .expect("Headless render states have no window")
+    }
+
+    /// Color attachment for scene passes targeting the HDR `view`.
+    /// With MSAA, renders to the multisampled texture and resolves into `view`.
+    pub fn color_attachment<'a>(
+        &'a self,
+        view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        Self::resolved_color_attachment(&self.msaa_texture, view, load)
+    }
+
+    /// Color attachment for passes targeting the surface `view`.
+    pub fn surface_color_attachment<'a>(
+        &'a self,
+        view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        Self::resolved_color_attachment(&self.surface_msaa_texture, view, load)
+    }
+
+    fn resolved_color_attachment<'a>(
+        msaa_texture: &'a Option<texture::Texture>,
+        view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        let ops = wgpu::Operations {
+            load,
+            store: wgpu::StoreOp::Store,
+        };
+
+        match msaa_texture {
+            Some(msaa_texture) => wgpu::RenderPassColorAttachment {
+                view: &msaa_texture.view,
+                resolve_target: Some(view),
+                ops,
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops,
+            },
+        }
+    }
+
     pub fn render(&mut self, game_state: &mut GameState) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let surface = self
+            .surface
+            .as_ref()
+            .expect("Headless render states can only render offscreen");
+        let output = surface.get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -256,13 +458,90 @@ impl RenderState {
 
         Ok(())
     }
+
+    /// Renders a frame into an offscreen texture and reads it back.
+    /// `render` records the passes targeting the given surface-like view.
+    pub fn render_to_image<F>(&self, render: F) -> anyhow::Result<image::RgbaImage>
+    where
+        F: FnOnce(&RenderState, &mut wgpu::CommandEncoder, &wgpu::TextureView),
+    {
+        let target = OffscreenTarget::new(
+            &self.device,
+            self.config.width,
+            self.config.height,
+            self.config.format,
+        );
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen render encoder"),
+            });
+
+        render(self, &mut encoder, &target.view);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        target.read_to_image(&self.device, &self.queue)
+    }
+
+    /// Saves a frame as a timestamped PNG. Returns the file path.
+    pub fn save_screenshot(&self, game_state: &mut GameState) -> anyhow::Result<PathBuf> {
+        let image = self.render_to_image(|render_state, encoder, view| {
+            game_state.render(render_state, encoder, view)
+        })?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = PathBuf::from(SCREENSHOT_DIR).join(format!("screenshot_{}.png", timestamp));
+        std::fs::create_dir_all(SCREENSHOT_DIR)?;
+        image.save(&path)?;
+
+        Ok(path)
+    }
+}
+
+/// Highest sample count up to `requested` that all `formats` support.
+fn supported_sample_count(
+    adapter: &wgpu::Adapter,
+    formats: &[wgpu::TextureFormat],
+    requested: u32,
+) -> u32 {
+    let adapter_specific = adapter
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+    let sample_count = [8, 4, 2, 1]
+        .into_iter()
+        .filter(|count| *count <= requested)
+        .find(|count| {
+            *count == 1
+                || ((adapter_specific || *count == 4)
+                    && formats.iter().all(|format| {
+                        adapter
+                            .get_texture_format_features(*format)
+                            .flags
+                            .sample_count_supported(*count)
+                    }))
+        })
+        .unwrap_or(1);
+
+    if sample_count != requested {
+        eprintln!(
+            "MSAA sample count {} not supported, using {}",
+            requested, sample_count
+        );
+    }
+
+    sample_count
 }
 
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
     depth_format: Option<wgpu::TextureFormat>,
+    depth_write_enabled: bool,
+    sample_count: u32,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
     cull_mode: Option<wgpu::Face>,
@@ -282,10 +561,7 @@ pub fn create_render_pipeline(
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
+                blend: Some(blend),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
@@ -300,13 +576,13 @@ pub fn create_render_pipeline(
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: true,
+            depth_write_enabled,
             depth_compare: wgpu::CompareFunction::Less,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
@@ -314,6 +590,43 @@ pub fn create_render_pipeline(
     })
 }
 
+/// Creates a render pipeline with a preprocessed shader from `ShaderMgr`.
+/// Shader compile errors are returned instead of panicking.
+pub fn create_render_pipeline_from_source(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+    depth_format: Option<wgpu::TextureFormat>,
+    depth_write_enabled: bool,
+    sample_count: u32,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &ShaderSource,
+    cull_mode: Option<wgpu::Face>,
+) -> anyhow::Result<wgpu::RenderPipeline> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let render_pipeline = create_render_pipeline(
+        device,
+        layout,
+        color_format,
+        blend,
+        depth_format,
+        depth_write_enabled,
+        sample_count,
+        vertex_layouts,
+        wgpu::ShaderModuleDescriptor {
+            label: shader.files[0].to_str(),
+            source: wgpu::ShaderSource::Wgsl(shader.code.as_str().into()),
+        },
+        cull_mode,
+    );
+    if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+        return Err(anyhow::anyhow!("{}", error));
+    }
+
+    Ok(render_pipeline)
+}
+
 pub fn create_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
     device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("Texture bind group layout"),
@@ -352,6 +665,67 @@ pub fn create_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGrou
                 ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                 count: None,
             },
+            // Material uniform
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+pub fn create_terrain_material_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let layer_texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Terrain material bind group layout"),
+        entries: &[
+            // Splat map
+            layer_texture_entry(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Layer sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Layer textures
+            layer_texture_entry(3),
+            layer_texture_entry(4),
+            layer_texture_entry(5),
+            layer_texture_entry(6),
+            // Material uniform
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ],
     })
 }
@@ -0,0 +1,138 @@
+use crate::{
+    assets::asset_cache::{AssetCache, Handle},
+    resources::{self, AssetWatcher, ShaderSource},
+};
+
+/// Caches preprocessed shader sources and reloads them when their files change.
+/// Shader errors are kept and shown in the UI until the shader compiles again.
+pub struct ShaderMgr {
+    shaders: AssetCache<ShaderSource>,
+    /// Shaders reloaded in the last update, whose pipelines must be recreated.
+    reloaded: Vec<Handle<ShaderSource>>,
+
+    error_shader_file: Vec<String>,
+    error_message: Vec<String>,
+}
+
+impl ShaderMgr {
+    pub fn new() -> Self {
+        Self {
+            shaders: AssetCache::new(),
+            reloaded: Vec::new(),
+
+            error_shader_file: Vec::new(),
+            error_message: Vec::new(),
+        }
+    }
+
+    /// Loads a shader, or adds a reference to it if it's already loaded.
+    pub fn load(&mut self, shader_file: &str) -> anyhow::Result<Handle<ShaderSource>> {
+        if let Some(handle) = self.shaders.acquire_with_key(shader_file) {
+            return Ok(handle);
+        }
+
+        let shader = resources::load_shader(shader_file)?;
+
+        Ok(self.shaders.add(shader_file, shader))
+    }
+
+    pub fn get(&self, shader: Handle<ShaderSource>) -> &ShaderSource {
+        self.shaders.get(shader)
+    }
+
+    /// Reloads shaders assembled from changed files.
+    /// Must be called before the renderers reload their pipelines.
+    pub fn update(&mut self, asset_watcher: &AssetWatcher) {
+        self.reloaded.clear();
+        if asset_watcher.changed_files().is_empty() {
+            return;
+        }
+
+        let handles: Vec<Handle<ShaderSource>> = self.shaders.handles().collect();
+        for handle in handles {
+            if !asset_watcher.is_changed(&self.shaders.get(handle).files) {
+                continue;
+            }
+
+            let shader_file = self.shaders.key(handle).to_string();
+            match resources::load_shader(&shader_file) {
+                Ok(shader) => {
+                    self.shaders.replace(handle, shader);
+                    self.reloaded.push(handle);
+                }
+                Err(e) => {
+                    println!("Failed to reload shader {}: {}", shader_file, e);
+                    self.set_error(&shader_file, e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Recreates `render_pipeline` with `create` if its shader was reloaded.
+    /// On failure the previous pipeline is kept and the error is shown in the UI.
+    pub fn reload_pipeline(
+        &mut self,
+        shader: Handle<ShaderSource>,
+        render_pipeline: &mut wgpu::RenderPipeline,
+        create: impl FnOnce(&ShaderSource) -> anyhow::Result<wgpu::RenderPipeline>,
+    ) {
+        if !self.reloaded.contains(&shader) {
+            return;
+        }
+
+        let shader_file = self.shaders.key(shader).to_string();
+        match create(self.shaders.get(shader)) {
+            Ok(new_render_pipeline) => {
+                println!("Reloaded shader: {}", shader_file);
+                *render_pipeline = new_render_pipeline;
+                self.clear_error(&shader_file);
+            }
+            Err(e) => {
+                println!("Failed to reload shader {}: {}", shader_file, e);
+                self.set_error(&shader_file, e.to_string());
+            }
+        }
+    }
+
+    fn set_error(&mut self, shader_file: &str, message: String) {
+        match self.error_index(shader_file) {
+            Some(index) => self.error_message[index] = message,
+            None => {
+                self.error_shader_file.push(shader_file.to_string());
+                self.error_message.push(message);
+            }
+        }
+    }
+
+    fn clear_error(&mut self, shader_file: &str) {
+        if let Some(index) = self.error_index(shader_file) {
+            self.error_shader_file.remove(index);
+            self.error_message.remove(index);
+        }
+    }
+
+    fn error_index(&self, shader_file: &str) -> Option<usize> {
+        self.error_shader_file
+            .iter()
+            .position(|file| file == shader_file)
+    }
+
+    pub fn ui(&self, context: &egui::Context) {
+        if self.error_shader_file.is_empty() {
+            return;
+        }
+
+        egui::Window::new("Shader errors")
+            .default_width(600.0)
+            .vscroll(true)
+            .show(context, |ui| {
+                for (shader_file, message) in
+                    self.error_shader_file.iter().zip(self.error_message.iter())
+                {
+                    ui.colored_label(egui::Color32::LIGHT_RED, shader_file);
+                    ui.label(egui::RichText::new(message).monospace());
+                    ui.separator();
+                }
+            });
+    }
+}
@@ -0,0 +1,279 @@
+use wgpu::util::DeviceExt;
+
+use crate::assets::{asset_cache::Handle, asset_manager::AssetMgr};
+
+use super::texture;
+
+/// Amount of blended layers: grass, dirt, sand and cliff.
+pub const TERRAIN_LAYER_COUNT: usize = 4;
+
+/// Generated splat map used without a splat map file, shared by every such material.
+const DEFAULT_SPLAT_MAP_KEY: &str = "Default splat map";
+
+/// A single terrain layer texture.
+/// `tint` multiplies the sampled color, which allows using grayscale detail maps as layers.
+/// `tiling` is the amount of texture repetitions across the terrain UV range.
+pub struct TerrainLayer<'a> {
+    pub texture_path: &'a str,
+    pub tint: [f32; 3],
+    pub tiling: f32,
+}
+
+/// Automatic blending rules applied on top of the splat map.
+pub struct TerrainBlendRules {
+    /// Height under which the sand layer takes over.
+    pub sand_max_height: f32,
+    /// Height range used to fade sand into the splat map layers.
+    pub sand_blend_height: f32,
+    /// Slope (`1 - normal.y`) where the cliff layer starts to appear.
+    pub cliff_min_slope: f32,
+    /// Slope (`1 - normal.y`) where the cliff layer fully covers the terrain.
+    pub cliff_max_slope: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainMaterialUniform {
+    pub layer_tint: [[f32; 4]; TERRAIN_LAYER_COUNT],
+    pub layer_tiling: [f32; TERRAIN_LAYER_COUNT],
+    pub sand_max_height: f32,
+    pub sand_blend_height: f32,
+    pub cliff_min_slope: f32,
+    pub cliff_max_slope: f32,
+}
+
+impl TerrainMaterialUniform {
+    pub fn new(layers: &[TerrainLayer; TERRAIN_LAYER_COUNT], rules: &TerrainBlendRules) -> Self {
+        let mut layer_tint = [[1.0; 4]; TERRAIN_LAYER_COUNT];
+        let mut layer_tiling = [1.0; TERRAIN_LAYER_COUNT];
+        for (i, layer) in layers.iter().enumerate() {
+            layer_tint[i] = [layer.tint[0], layer.tint[1], layer.tint[2], 1.0];
+            layer_tiling[i] = layer.tiling;
+        }
+
+        Self {
+            layer_tint,
+            layer_tiling,
+            sand_max_height: rules.sand_max_height,
+            sand_blend_height: rules.sand_blend_height,
+            cliff_min_slope: rules.cliff_min_slope,
+            cliff_max_slope: rules.cliff_max_slope,
+        }
+    }
+}
+
+/// Terrain material blending several layers with a splat map.
+/// Splat map channels hold layer weights: R grass, G dirt, B sand, A cliff.
+pub struct TerrainMaterial {
+    pub name: String,
+    pub splat_texture: Handle<texture::Texture>,
+    pub layer_textures: Vec<Handle<texture::Texture>>,
+    pub layer_sampler: wgpu::Sampler,
+    /// Kept to rebuild the bind group when textures are reloaded.
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl TerrainMaterial {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        splat_texture: Handle<texture::Texture>,
+        layer_textures: Vec<Handle<texture::Texture>>,
+        uniform: TerrainMaterialUniform,
+        asset_mgr: &AssetMgr,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        // Layers are tiled, so they need a repeating sampler.
+        let layer_sampler =
+            texture::SamplerOptions::TILING.create_sampler(device, Some("Terrain layer sampler"));
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain material buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = Self::create_bind_group(
+            device,
+            name,
+            splat_texture,
+            &layer_textures,
+            &layer_sampler,
+            &uniform_buffer,
+            asset_mgr,
+            layout,
+        );
+
+        Self {
+            name: String::from(name),
+            splat_texture,
+            layer_textures,
+            layer_sampler,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        name: &str,
+        splat_texture: Handle<texture::Texture>,
+        layer_textures: &[Handle<texture::Texture>],
+        layer_sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        asset_mgr: &AssetMgr,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        let splat_texture = asset_mgr.textures.get(splat_texture);
+        let layer_textures: Vec<&texture::Texture> = layer_textures
+            .iter()
+            .map(|texture| asset_mgr.textures.get(*texture))
+            .collect();
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(name),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&splat_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&splat_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(layer_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&layer_textures[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&layer_textures[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&layer_textures[2].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&layer_textures[3].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Loads layer textures and the splat map.
+    /// Without a splat map the first layer is used everywhere the automatic rules don't apply.
+    pub async fn load(
+        name: &str,
+        splat_map_path: Option<&str>,
+        layers: &[TerrainLayer<'_>; TERRAIN_LAYER_COUNT],
+        rules: &TerrainBlendRules,
+        asset_mgr: &mut AssetMgr,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<TerrainMaterial> {
+        // Splat weights must not be gamma corrected, hence loaded as linear.
+        let splat_texture = match splat_map_path {
+            Some(path) => {
+                asset_mgr
+                    .load_texture(
+                        path,
+                        texture::TextureKind::Data,
+                        &texture::SamplerOptions::CLAMP,
+                        device,
+                        queue,
+                    )
+                    .await?
+            }
+            None => match asset_mgr.textures.acquire_with_key(DEFAULT_SPLAT_MAP_KEY) {
+                Some(splat_texture) => splat_texture,
+                None => {
+                    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                        1,
+                        1,
+                        image::Rgba([255, 0, 0, 0]),
+                    ));
+                    let splat_texture = texture::Texture::from_image(
+                        device,
+                        queue,
+                        &img,
+                        Some(DEFAULT_SPLAT_MAP_KEY),
+                        texture::TextureKind::Data,
+                        &texture::SamplerOptions::CLAMP,
+                    )?;
+                    asset_mgr.add_texture(
+                        DEFAULT_SPLAT_MAP_KEY,
+                        splat_texture,
+                        texture::TextureKind::Data,
+                        &texture::SamplerOptions::CLAMP,
+                    )
+                }
+            },
+        };
+
+        let mut layer_textures = Vec::with_capacity(TERRAIN_LAYER_COUNT);
+        for layer in layers.iter() {
+            layer_textures.push(
+                asset_mgr
+                    .load_texture(
+                        layer.texture_path,
+                        texture::TextureKind::Color,
+                        &texture::SamplerOptions::TILING,
+                        device,
+                        queue,
+                    )
+                    .await?,
+            );
+        }
+
+        let uniform = TerrainMaterialUniform::new(layers, rules);
+
+        Ok(TerrainMaterial::new(
+            device,
+            name,
+            splat_texture,
+            layer_textures,
+            uniform,
+            asset_mgr,
+            layout,
+        ))
+    }
+
+    /// Rebuilds the bind group if any of the material textures was reloaded.
+    pub fn reload_textures(
+        &mut self,
+        reloaded_textures: &[Handle<texture::Texture>],
+        asset_mgr: &AssetMgr,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        let is_reloaded = std::iter::once(&self.splat_texture)
+            .chain(self.layer_textures.iter())
+            .any(|texture| reloaded_textures.contains(texture));
+        if !is_reloaded {
+            return;
+        }
+
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            self.splat_texture,
+            &self.layer_textures,
+            &self.layer_sampler,
+            &self.uniform_buffer,
+            asset_mgr,
+            layout,
+        );
+    }
+}
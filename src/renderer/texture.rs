@@ -1,5 +1,58 @@
 use anyhow::Result;
-use image::GenericImageView;
+
+/// How texel values are interpreted, which decides the format and mip filtering.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureKind {
+    /// sRGB color, filtered in linear space.
+    Color,
+    /// Tangent space normals, renormalized on each mip level.
+    NormalMap,
+    /// Linear data such as splat weights.
+    Data,
+}
+
+/// Sampler configuration for a texture.
+#[derive(Debug, Copy, Clone)]
+pub struct SamplerOptions {
+    pub address_mode: wgpu::AddressMode,
+    pub filter_mode: wgpu::FilterMode,
+    /// Maximum anisotropy, from 1 to 16. Only used with linear filtering.
+    pub anisotropy: u16,
+}
+
+impl SamplerOptions {
+    pub const CLAMP: Self = Self {
+        address_mode: wgpu::AddressMode::ClampToEdge,
+        filter_mode: wgpu::FilterMode::Linear,
+        anisotropy: 1,
+    };
+
+    pub const TILING: Self = Self {
+        address_mode: wgpu::AddressMode::Repeat,
+        filter_mode: wgpu::FilterMode::Linear,
+        anisotropy: 16,
+    };
+
+    pub fn create_sampler(&self, device: &wgpu::Device, label: Option<&str>) -> wgpu::Sampler {
+        // Anisotropic filtering requires linear filters
+        let anisotropy = match self.filter_mode {
+            wgpu::FilterMode::Linear => self.anisotropy.clamp(1, 16),
+            wgpu::FilterMode::Nearest => 1,
+        };
+
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.filter_mode,
+            min_filter: self.filter_mode,
+            mipmap_filter: self.filter_mode,
+            anisotropy_clamp: anisotropy,
+            ..Default::default()
+        })
+    }
+}
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -9,45 +62,81 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
-        is_normal_map: bool,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?.flipv();
+        let mip_chain = Self::decode_mip_chain(bytes, kind)?;
 
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Ok(Self::from_mip_chain(
+            device,
+            queue,
+            &mip_chain,
+            Some(label),
+            kind,
+            sampler_options,
+        ))
     }
 
+    /// Uploads the image with a full mip chain, generated on the CPU.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
-        is_normal_map: bool,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let mip_chain = Self::generate_mip_chain(img.to_rgba8(), kind);
+
+        Ok(Self::from_mip_chain(
+            device,
+            queue,
+            &mip_chain,
+            label,
+            kind,
+            sampler_options,
+        ))
+    }
+
+    /// Decodes an image file and generates its mip chain.
+    /// Doesn't need the GPU, so it can run on loader threads.
+    pub fn decode_mip_chain(bytes: &[u8], kind: TextureKind) -> Result<Vec<image::RgbaImage>> {
+        let img = image::load_from_memory(bytes)?.flipv();
 
+        Ok(Self::generate_mip_chain(img.to_rgba8(), kind))
+    }
+
+    /// Uploads a mip chain from `decode_mip_chain`, the full size image first.
+    pub fn from_mip_chain(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mip_chain: &[image::RgbaImage],
+        label: Option<&str>,
+        kind: TextureKind,
+        sampler_options: &SamplerOptions,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width: mip_chain[0].width(),
+            height: mip_chain[0].height(),
             depth_or_array_layers: 1,
         };
 
-        let format = if is_normal_map {
-            wgpu::TextureFormat::Rgba8Unorm
-        } else {
-            wgpu::TextureFormat::Rgba8UnormSrgb
+        let format = match kind {
+            TextureKind::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureKind::NormalMap | TextureKind::Data => wgpu::TextureFormat::Rgba8Unorm,
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: mip_chain.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
@@ -55,43 +144,112 @@ impl Texture {
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        for (mip_level, mip) in mip_chain.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                mip,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * mip.width()),
+                    rows_per_image: Some(mip.height()),
+                },
+                wgpu::Extent3d {
+                    width: mip.width(),
+                    height: mip.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let sampler = sampler_options.create_sampler(device, label);
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
+    }
+
+    /// Returns every mip level, starting with the full size image, down to 1x1.
+    fn generate_mip_chain(rgba: image::RgbaImage, kind: TextureKind) -> Vec<image::RgbaImage> {
+        // Texel value decoding, so filtering happens in linear (or normal) space
+        let decode: [[f32; 256]; 4] = std::array::from_fn(|channel| {
+            std::array::from_fn(|value| {
+                let value = value as f32 / 255.0;
+                match (kind, channel) {
+                    (TextureKind::Color, 0..=2) => srgb_to_linear(value),
+                    (TextureKind::NormalMap, 0..=2) => value * 2.0 - 1.0,
+                    _ => value,
+                }
+            })
+        });
+
+        let mut mip_chain = vec![rgba];
+
+        loop {
+            let previous = mip_chain.last().unwrap();
+            if previous.width() == 1 && previous.height() == 1 {
+                break;
+            }
+
+            let width = u32::max(previous.width() / 2, 1);
+            let height = u32::max(previous.height() / 2, 1);
+            let mut mip = image::RgbaImage::new(width, height);
+
+            for y in 0..height {
+                for x in 0..width {
+                    // 2x2 box filter, clamped for odd and 1 texel wide levels
+                    let mut average = [0.0; 4];
+                    for i in 0..4 {
+                        let source_x = u32::min(x * 2 + (i & 1), previous.width() - 1);
+                        let source_y = u32::min(y * 2 + (i >> 1), previous.height() - 1);
+                        let pixel = previous.get_pixel(source_x, source_y).0;
+                        for channel in 0..4 {
+                            average[channel] += decode[channel][pixel[channel] as usize] * 0.25;
+                        }
+                    }
+
+                    mip.put_pixel(x, y, image::Rgba(Self::encode_texel(average, kind)));
+                }
+            }
+
+            mip_chain.push(mip);
+        }
+
+        mip_chain
+    }
+
+    fn encode_texel(mut texel: [f32; 4], kind: TextureKind) -> [u8; 4] {
+        match kind {
+            TextureKind::Color => {
+                for channel in texel.iter_mut().take(3) {
+                    *channel = linear_to_srgb(*channel);
+                }
+            }
+            TextureKind::NormalMap => {
+                let length =
+                    f32::sqrt(texel[0] * texel[0] + texel[1] * texel[1] + texel[2] * texel[2]);
+                let length = f32::max(length, f32::EPSILON);
+                for channel in texel.iter_mut().take(3) {
+                    *channel = (*channel / length) * 0.5 + 0.5;
+                }
+            }
+            TextureKind::Data => {}
+        }
+
+        texel.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
     }
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -104,7 +262,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             view_formats: &[Self::DEPTH_FORMAT],
@@ -132,4 +290,103 @@ impl Texture {
             sampler,
         }
     }
+
+    /// Multisampled color target with the surface size.
+    /// Returns `None` if `sample_count` is 1.
+    pub fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Option<Self> {
+        if sample_count <= 1 {
+            return None;
+        }
+
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            view_formats: &[],
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Some(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Color target that can be sampled by later passes.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: u32::max(width, 1),
+            height: u32::max(height, 1),
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            view_formats: &[],
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
 }
@@ -1,50 +1,348 @@
 use kira::sound::static_sound::StaticSoundData;
 use kira::sound::static_sound::StaticSoundSettings;
+use notify::Watcher;
+use std::cell::RefCell;
 use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
 use wgpu::util::DeviceExt;
 
+use crate::assets::asset_archive::AssetArchive;
+use crate::assets::asset_manager::AssetMgr;
 use crate::renderer::model;
 use crate::renderer::texture;
 
 const ASSETS_ROOT_PATH: &str = "assets";
+/// Packed by `build.rs`, copied next to the binary by the `package_assets` example.
+const ASSET_ARCHIVE_FILE_NAME: &str = "assets.pak";
+
+/// Where assets are read from, chosen on the first load.
+enum AssetSource {
+    /// Loose files, the only source with hot reload.
+    Folder(PathBuf),
+    /// Packed archive file, or the archive embedded in the binary.
+    Archive(AssetArchive),
+}
+
+static ASSET_SOURCE: OnceLock<AssetSource> = OnceLock::new();
+
+fn asset_source() -> &'static AssetSource {
+    ASSET_SOURCE.get_or_init(find_asset_source)
+}
+
+/// Prefers the assets directory in the working directory or next to the executable,
+/// then the archive next to the executable, then the assets embedded in the binary.
+fn find_asset_source() -> AssetSource {
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+
+    let mut folders = vec![PathBuf::from(ASSETS_ROOT_PATH)];
+    if let Some(exe_dir) = &exe_dir {
+        folders.push(exe_dir.join(ASSETS_ROOT_PATH));
+    }
+    for folder in folders {
+        if folder.is_dir() {
+            println!("Loading assets from {:?}", folder);
+            return AssetSource::Folder(folder);
+        }
+    }
+
+    if let Some(exe_dir) = &exe_dir {
+        let archive_path = exe_dir.join(ASSET_ARCHIVE_FILE_NAME);
+        if archive_path.is_file() {
+            match AssetArchive::open(&archive_path) {
+                Ok(archive) => {
+                    println!(
+                        "Loading assets from {:?} ({} files)",
+                        archive_path,
+                        archive.len()
+                    );
+                    return AssetSource::Archive(archive);
+                }
+                Err(e) => println!("Failed to open {:?}: {}", archive_path, e),
+            }
+        }
+    }
+
+    if let Some(archive) = embedded_archive() {
+        println!("Loading embedded assets ({} files)", archive.len());
+        return AssetSource::Archive(archive);
+    }
+
+    // Nothing found, loads fail with the expected path
+    AssetSource::Folder(PathBuf::from(ASSETS_ROOT_PATH))
+}
+
+#[cfg(feature = "embed_assets")]
+fn embedded_archive() -> Option<AssetArchive> {
+    static EMBEDDED_ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/assets.pak"));
+
+    Some(AssetArchive::from_embedded(EMBEDDED_ARCHIVE).unwrap())
+}
+
+#[cfg(not(feature = "embed_assets"))]
+fn embedded_archive() -> Option<AssetArchive> {
+    None
+}
+
+/// Reads a file from the asset source. Paths are relative to the assets root.
+fn read_asset(file_name: &Path) -> anyhow::Result<Vec<u8>> {
+    match asset_source() {
+        AssetSource::Folder(root) => Ok(std::fs::read(root.join(file_name))?),
+        AssetSource::Archive(archive) => archive.read(file_name),
+    }
+}
+
+/// Watches the assets directory for changed files, for hot reloading.
+/// Assets are loaded from the source directory, so edits show up without a rebuild.
+/// Disabled when assets are loaded from an archive.
+pub struct AssetWatcher {
+    /// `None` if the assets directory can't be watched, hot reload is then disabled.
+    _watcher: Option<notify::RecommendedWatcher>,
+    receiver: mpsc::Receiver<notify::Result<notify::Event>>,
+    assets_root: PathBuf,
+
+    /// Files changed since the last update, relative to the assets root.
+    changed_files: Vec<PathBuf>,
+}
+
+impl AssetWatcher {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let folder = match asset_source() {
+            AssetSource::Folder(folder) => folder.clone(),
+            AssetSource::Archive(_) => {
+                println!("Asset hot reload disabled: assets are loaded from an archive");
+                return Self {
+                    _watcher: None,
+                    receiver,
+                    assets_root: PathBuf::new(),
+                    changed_files: Vec::new(),
+                };
+            }
+        };
+        let assets_root = folder.canonicalize().unwrap_or(folder);
+
+        let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+            watcher.watch(&assets_root, notify::RecursiveMode::Recursive)?;
+            Ok(watcher)
+        });
+        let watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                println!("Asset hot reload disabled: {}", e);
+                None
+            }
+        };
+
+        Self {
+            _watcher: watcher,
+            receiver,
+            assets_root,
+            changed_files: Vec::new(),
+        }
+    }
+
+    /// Collects the files changed since the last update.
+    /// Must be called before the assets are reloaded.
+    pub fn update(&mut self) {
+        self.changed_files.clear();
+
+        for event in self.receiver.try_iter() {
+            let Ok(event) = event else {
+                continue;
+            };
+            if event.kind.is_access() {
+                continue;
+            }
+
+            for path in event.paths {
+                let Ok(file) = path.strip_prefix(&self.assets_root) else {
+                    continue;
+                };
+                if !self.changed_files.iter().any(|changed| changed == file) {
+                    println!("Asset changed: {:?}", file);
+                    self.changed_files.push(file.to_path_buf());
+                }
+            }
+        }
+    }
+
+    pub fn changed_files(&self) -> &[PathBuf] {
+        &self.changed_files
+    }
+
+    /// Returns `true` if any of `files` changed since the last update.
+    pub fn is_changed(&self, files: &[PathBuf]) -> bool {
+        files.iter().any(|file| self.changed_files.contains(file))
+    }
+}
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(ASSETS_ROOT_PATH).join(file_name);
-    println!("Loading (text): {:?}", path);
-    let txt = std::fs::read_to_string(path)?;
+    println!("Loading (text): {:?}", file_name);
+    let txt = String::from_utf8(read_asset(Path::new(file_name))?)?;
 
     Ok(txt)
 }
 
+/// Reads from the assets directory, the asset archive or the embedded assets.
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(ASSETS_ROOT_PATH).join(file_name);
-    println!("Loading (binary): {:?}", path);
-    let data = std::fs::read(path)?;
+    println!("Loading (binary): {:?}", file_name);
+    let data = read_asset(Path::new(file_name))?;
 
     Ok(data)
 }
 
+pub async fn load_image(file_name: &str) -> anyhow::Result<image::DynamicImage> {
+    let data = load_binary(file_name).await?;
+
+    Ok(image::load_from_memory(&data)?)
+}
+
+/// WGSL source with `#include` directives resolved.
+pub struct ShaderSource {
+    pub code: String,
+    /// Files the source was assembled from, the root file first.
+    /// Paths are relative to the assets root.
+    pub files: Vec<PathBuf>,
+}
+
+/// Loads a WGSL shader, replacing `#include "file.wgsl"` lines with the file contents.
+/// Include paths are relative to the including file, and each file is included once.
+/// Synchronous, as pipelines are created outside of async code.
+pub fn load_shader(file_name: &str) -> anyhow::Result<ShaderSource> {
+    let mut files = Vec::new();
+    let code = preprocess_shader(Path::new(file_name), &mut files)?;
+
+    Ok(ShaderSource { code, files })
+}
+
+fn preprocess_shader(file_path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<String> {
+    files.push(file_path.to_path_buf());
+
+    println!("Loading (shader): {:?}", file_path);
+    let source = read_asset(file_path)
+        .and_then(|source| Ok(String::from_utf8(source)?))
+        .map_err(|e| anyhow::anyhow!("Failed to read shader {:?}: {}", file_path, e))?;
+
+    let mut code = String::with_capacity(source.len());
+    for (line_i, line) in source.lines().enumerate() {
+        let Some(include) = line.trim().strip_prefix("#include") else {
+            code.push_str(line);
+            code.push('\n');
+            continue;
+        };
+
+        let include_name = include
+            .trim()
+            .strip_prefix('"')
+            .and_then(|include| include.strip_suffix('"'))
+            .ok_or_else(|| {
+                anyhow::anyhow!("{:?}:{}: expected #include \"file\"", file_path, line_i + 1)
+            })?;
+        let include_path = normalize_path(
+            &file_path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(include_name),
+        );
+
+        if !files.contains(&include_path) {
+            code.push_str(&preprocess_shader(&include_path, files)?);
+        }
+    }
+
+    Ok(code)
+}
+
+/// Resolves `.` and `..` components, so paths to the same asset compare equal.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
 pub async fn load_texture(
     file_name: &str,
-    is_normal_map: bool,
+    kind: texture::TextureKind,
+    sampler_options: &texture::SamplerOptions,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
 
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    texture::Texture::from_bytes(device, queue, &data, file_name, kind, sampler_options)
+}
+
+/// Model geometry and material texture paths, loaded without GPU access.
+/// Created on loader threads and turned into a `Model` with `create_model`.
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+
+    pub min_x: f32,
+    pub min_y: f32,
+    pub min_z: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+    pub max_z: f32,
+
+    pub source_files: Vec<PathBuf>,
+}
+
+pub struct MeshData {
+    pub vertices: Vec<model::ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material: usize,
+
+    pub min_x: f32,
+    pub min_y: f32,
+    pub min_z: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+    pub max_z: f32,
+}
+
+/// Texture paths are relative to the assets root.
+pub struct MaterialData {
+    pub name: String,
+    pub diffuse_texture: PathBuf,
+    pub normal_texture: PathBuf,
+    /// MTL dissolve, materials below 1 are transparent.
+    pub opacity: f32,
 }
 
-// TODO: support loading models without a normal map.
 pub async fn load_model_obj(
     file_name: &str,
+    asset_mgr: &mut AssetMgr,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
+    let model_data = load_model_data(file_name).await?;
+
+    create_model(file_name, model_data, asset_mgr, device, queue, layout).await
+}
+
+/// Parses an OBJ file and its materials, and calculates tangents and bounds.
+// TODO: support loading models without a normal map.
+pub async fn load_model_data(file_name: &str) -> anyhow::Result<ModelData> {
     let file_path = std::path::Path::new(file_name);
     let path_root = file_path.parent().unwrap();
 
+    // Every file the model is loaded from, for hot reloading
+    let source_files = RefCell::new(vec![file_path.to_path_buf()]);
+
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -56,32 +354,37 @@ pub async fn load_model_obj(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let path = std::path::Path::new(&path_root).join(p);
-            let path_str = path.to_str().unwrap();
-            let mat_text = load_string(path_str).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let source_files = &source_files;
+            async move {
+                let path = std::path::Path::new(&path_root).join(p);
+                let path_str = path.to_str().unwrap();
+                source_files.borrow_mut().push(normalize_path(&path));
+                // Missing materials are errors rather than panics, files may be mid-save when hot reloading
+                let mat_text = load_string(path_str)
+                    .await
+                    .map_err(|_| tobj::LoadError::OpenFileFailed)?;
+                tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            }
         },
     )
     .await?;
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let path_diffuse = std::path::Path::new(&path_root).join(&m.diffuse_texture);
-        let path_normal = std::path::Path::new(&path_root).join(&m.normal_texture);
         let diffuse_texture =
-            load_texture(path_diffuse.to_str().unwrap(), false, device, queue).await?;
-        // TODO: add default normal map texture for when one is not defined in the OBJ file.
+            normalize_path(&std::path::Path::new(&path_root).join(&m.diffuse_texture));
         let normal_texture =
-            load_texture(path_normal.to_str().unwrap(), true, device, queue).await?;
+            normalize_path(&std::path::Path::new(&path_root).join(&m.normal_texture));
+        source_files.borrow_mut().push(diffuse_texture.clone());
+        source_files.borrow_mut().push(normal_texture.clone());
 
-        materials.push(model::Material::new(
-            device,
-            &m.name,
+        materials.push(MaterialData {
+            name: m.name,
             diffuse_texture,
             normal_texture,
-            layout,
-        ));
+            opacity: m.dissolve,
+        });
     }
 
     let mut model_min_x = f32::MAX;
@@ -206,17 +509,6 @@ pub async fn load_model_obj(
                 v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
             }
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
             // Get model min/max positions
             model_min_x = f32::min(min_x, model_min_x);
             model_min_y = f32::min(min_y, model_min_y);
@@ -225,15 +517,10 @@ pub async fn load_model_obj(
             model_max_y = f32::max(max_y, model_max_y);
             model_max_z = f32::max(max_z, model_max_z);
 
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-
+            MeshData {
                 vertices,
-                is_wireframe: false,
+                indices: m.mesh.indices,
+                material: m.mesh.material_id.unwrap_or(0),
 
                 min_x,
                 min_y,
@@ -245,7 +532,7 @@ pub async fn load_model_obj(
         })
         .collect::<Vec<_>>();
 
-    Ok(model::Model {
+    Ok(ModelData {
         meshes,
         materials,
         min_x: model_min_x,
@@ -254,15 +541,120 @@ pub async fn load_model_obj(
         max_x: model_max_x,
         max_y: model_max_y,
         max_z: model_max_z,
+        source_files: source_files.into_inner(),
+    })
+}
+
+/// Creates the GPU buffers and materials of a loaded model.
+/// Material textures are loaded through `asset_mgr`, so already loaded textures are shared.
+pub async fn create_model(
+    file_name: &str,
+    model_data: ModelData,
+    asset_mgr: &mut AssetMgr,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let mut materials = Vec::new();
+    for m in model_data.materials.iter() {
+        let diffuse_texture = asset_mgr
+            .load_texture(
+                m.diffuse_texture.to_str().unwrap(),
+                texture::TextureKind::Color,
+                &texture::SamplerOptions::TILING,
+                device,
+                queue,
+            )
+            .await;
+        // TODO: add default normal map texture for when one is not defined in the OBJ file.
+        let normal_texture = asset_mgr
+            .load_texture(
+                m.normal_texture.to_str().unwrap(),
+                texture::TextureKind::NormalMap,
+                &texture::SamplerOptions::TILING,
+                device,
+                queue,
+            )
+            .await;
+
+        match (diffuse_texture, normal_texture) {
+            (Ok(diffuse_texture), Ok(normal_texture)) => materials.push(model::Material::new(
+                device,
+                &m.name,
+                diffuse_texture,
+                normal_texture,
+                m.opacity,
+                asset_mgr,
+                layout,
+            )),
+            (diffuse_texture, normal_texture) => {
+                // Release the textures of the partially loaded model, or they would never unload
+                for texture in diffuse_texture.iter().chain(normal_texture.iter()) {
+                    asset_mgr.textures.release(*texture);
+                }
+                for material in materials.iter() {
+                    material.release_textures(asset_mgr);
+                }
+                return Err(diffuse_texture.err().or(normal_texture.err()).unwrap());
+            }
+        }
+    }
+
+    let meshes = model_data
+        .meshes
+        .into_iter()
+        .map(|m| {
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Vertex Buffer", file_name)),
+                contents: bytemuck::cast_slice(&m.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Index Buffer", file_name)),
+                contents: bytemuck::cast_slice(&m.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            model::Mesh {
+                name: file_name.to_string(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: m.indices.len() as u32,
+                material: m.material,
+
+                vertices: m.vertices,
+                is_wireframe: false,
+
+                min_x: m.min_x,
+                min_y: m.min_y,
+                min_z: m.min_z,
+                max_x: m.max_x,
+                max_y: m.max_y,
+                max_z: m.max_z,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        min_x: model_data.min_x,
+        min_y: model_data.min_y,
+        min_z: model_data.min_z,
+        max_x: model_data.max_x,
+        max_y: model_data.max_y,
+        max_z: model_data.max_z,
+        source_files: model_data.source_files,
     })
 }
 
 // TODO: add streaming audio loading support
 pub async fn load_static_sound_data(file_name: &str) -> anyhow::Result<StaticSoundData> {
-    let path = std::path::Path::new(ASSETS_ROOT_PATH).join(file_name);
-    println!("Loading (static sound data): {:?}", path);
+    println!("Loading (static sound data): {:?}", file_name);
+    let data = read_asset(Path::new(file_name))?;
 
-    let sound_data = StaticSoundData::from_file(path, StaticSoundSettings::default())?;
+    let sound_data =
+        StaticSoundData::from_cursor(Cursor::new(data), StaticSoundSettings::default())?;
 
     Ok(sound_data)
 }